@@ -0,0 +1,51 @@
+use mpcemu_v53::{Vector, Report};
+use std::path::PathBuf;
+
+/// Run single-step test vectors (one JSON file per opcode, named after it)
+/// and print a pass/fail table by opcode. Arguments are files or directories
+/// of files; `--mask XXXX` only compares the given flags.
+fn main () -> Result<(), Box<dyn std::error::Error>> {
+    let mut flags_mask = 0xFFFF;
+    let mut files: Vec<PathBuf> = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--mask" {
+            let mask = args.next().ok_or("--mask needs a value")?;
+            flags_mask = u16::from_str_radix(&mask, 16)?;
+        } else if std::fs::metadata(&arg)?.is_dir() {
+            let mut entries = std::fs::read_dir(&arg)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            entries.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(arg.into());
+        }
+    }
+    if files.is_empty() {
+        return Err("usage: vectors [--mask XXXX] FILE_OR_DIRECTORY...".into())
+    }
+
+    let mut report = Report::new();
+    for file in files {
+        let opcode = file.file_stem().unwrap_or_default().to_string_lossy().to_uppercase();
+        let vectors = Vector::parse_all(&std::fs::read_to_string(&file)?)
+            .map_err(|e| format!("{}: {e}", file.display()))?;
+        let mut shown = false;
+        for vector in vectors.iter() {
+            let mismatches = vector.run(flags_mask);
+            // Show the first failure of each opcode
+            if !mismatches.is_empty() && !shown {
+                shown = true;
+                println!("{opcode}: {}", vector.name);
+                for mismatch in mismatches.iter() {
+                    println!("  {mismatch}");
+                }
+            }
+            report.record(&opcode, &mismatches);
+        }
+    }
+    print!("\n{report}");
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use mpcemu_v53::{Bus, MemoryMap, Unmapped, StateWriter, StateReader, StateError};
+
+/// Memory of the MPC: the ROM at the top of the first megabyte, RAM below
+/// it and above it (reachable through the XA page registers).
+pub fn memory (rom: Vec<u8>) -> Result<MemoryMap, &'static str> {
+    let base = 0x100000u32.checked_sub(rom.len() as u32).ok_or("ROM image too big")?;
+    Ok(MemoryMap::new(Unmapped::Float(0xFF))
+        .ram(0x00000, base)
+        .rom(base, rom)
+        .ram(0x100000, 0x100000))
+}
+
+/// Memory, printing what's written to the display port.
+pub struct Display(pub MemoryMap);
+
+impl Bus for Display {
+    fn peek (&self, addr: u32) -> u8 {
+        self.0.peek(addr)
+    }
+    fn write (&mut self, addr: u32, value: u8) {
+        self.0.write(addr, value)
+    }
+    fn input (&mut self, port: u16) -> u8 {
+        self.0.input(port)
+    }
+    fn take_fault (&mut self) -> Option<u32> {
+        self.0.take_fault()
+    }
+    fn interrupt_acknowledge (&mut self, input: u8) -> u8 {
+        self.0.interrupt_acknowledge(input)
+    }
+    fn dma_requests (&mut self) -> u8 {
+        self.0.dma_requests()
+    }
+    fn dma_read (&mut self, channel: u8) -> u8 {
+        self.0.dma_read(channel)
+    }
+    fn dma_write (&mut self, channel: u8, value: u8) {
+        self.0.dma_write(channel, value)
+    }
+    fn dma_terminal_count (&mut self, channel: u8) {
+        self.0.dma_terminal_count(channel)
+    }
+    fn save_state (&self, state: &mut StateWriter) {
+        self.0.save_state(state)
+    }
+    fn load_state (&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.0.load_state(state)
+    }
+    fn output (&mut self, port: u16, value: u8) {
+        self.0.output(port, value);
+        if port == 0x00E0 {
+            if value.is_ascii() {
+                let value = value as char;
+                println!("0x00E0 -> '{value}'");
+            } else {
+                println!("0x00E0 -> 0x{value:02X}");
+            }
+        }
+    }
+}
@@ -1,27 +1,68 @@
-use mpcemu_v53::CPU as V53;
+use mpcemu_v53::{CPU as V53, Stop, TraceReader};
+use mpcemu_cli::{Display, memory};
+
+/// Print state and disassembly before each instruction
+const DEBUG: bool = false;
 
 fn main () -> Result<(), Box<dyn std::error::Error>> {
-    let bin = std::fs::read("./data/mpc2000xl.bin")?;
-    let mut memory = vec![];
-    memory.extend_from_slice(&bin);
-    memory.extend_from_slice(&bin);
-    let mut cpu = V53::new(memory);
+    let rom  = std::fs::read("./data/mpc2000xl.bin")?;
+    let mut cpu = V53::with_bus(Box::new(Display(memory(rom)?)));
 
-    cpu.on_output(0x00E0, Box::new(|cpu: &V53| {
-        let value = cpu.ports()[0x00E0] as char;
-        if value.is_ascii() {
-            println!("0x00E0 -> {value}");
-        } else {
-            println!("0x00E0 -> (unprintable)");
+    // `--trace FILE [--mask XXXX]` runs in lockstep with a reference trace;
+    // `--load FILE` starts from a save state;
+    // `--save FILE --at ADDRESS` saves the state on reaching an address
+    let mut trace = None;
+    let mut flags_mask = 0xFFFF;
+    let mut save = None;
+    let mut save_at = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = Some(args.next().ok_or("--trace needs a file")?),
+            "--mask" => flags_mask = u16::from_str_radix(&args.next().ok_or("--mask needs a value")?, 16)?,
+            "--load" => cpu.load_state(&std::fs::read(args.next().ok_or("--load needs a file")?)?)?,
+            "--save" => save = Some(args.next().ok_or("--save needs a file")?),
+            "--at" => save_at = Some(u32::from_str_radix(&args.next().ok_or("--at needs an address")?, 16)?),
+            _ => return Err(format!("unknown argument {arg}").into())
+        }
+    }
+    if save.is_some() != save_at.is_some() {
+        return Err("--save and --at go together".into())
+    }
+    if let Some(addr) = save_at {
+        cpu.set_breakpoint(addr);
+    }
+    if let Some(trace) = trace {
+        let file = std::io::BufReader::new(std::fs::File::open(&trace)?);
+        let mut records = TraceReader::new(file);
+        match cpu.lockstep(&mut records, flags_mask) {
+            Ok(steps) => println!("{steps} instructions match {trace}"),
+            Err(divergence) => print!("{divergence}"),
         }
-    }));
+        if let Some(error) = records.error() {
+            return Err(format!("{trace}: {error}").into())
+        }
+        return Ok(())
+    }
 
     let mut first: bool = true;
     let mut last_address: u32 = cpu.program_address();
     println!("\n\nRunning from {:x}:", cpu.program_address());
     loop {
         let address = cpu.program_address();
-        cpu.step(false && (first || last_address != address));
+        match cpu.step(DEBUG && (first || last_address != address)) {
+            Ok(()) | Err(Stop::Halted) => {},
+            Err(Stop::Breakpoint(addr)) if Some(addr) == save_at => {
+                let file = save.as_ref().unwrap();
+                std::fs::write(file, cpu.save_state())?;
+                println!("Saved state at {addr:05X} to {file}");
+                return Ok(())
+            },
+            Err(stop) => return Err(stop.into())
+        }
+        for (offset, data) in cpu.take_unused_writes() {
+            println!("{address:05X}: write {data:02X} to unused internal I/O offset {offset:02X}");
+        }
         last_address = address;
         first = false;
         // 0xF986C out 0E0h, al -> write to screen
@@ -0,0 +1,133 @@
+use crate::{StateWriter, StateReader, StateError};
+
+/// Memory and I/O address spaces as seen by a CPU. Memory, ROM, memory-mapped
+/// and port-mapped devices implement this, so that the CPU doesn't need to
+/// know what's attached where.
+pub trait Bus {
+
+    /// Read a byte from memory without side effects,
+    /// e.g. for disassembly and debugging.
+    fn peek (&self, addr: u32) -> u8;
+
+    /// Read a byte from memory. Devices with read side effects override this;
+    /// by default it's the same as [Bus::peek].
+    fn read (&mut self, addr: u32) -> u8 {
+        self.peek(addr)
+    }
+
+    /// Write a byte to memory.
+    fn write (&mut self, addr: u32, value: u8);
+
+    /// Read a byte from an I/O port.
+    fn input (&mut self, port: u16) -> u8;
+
+    /// Write a byte to an I/O port.
+    fn output (&mut self, port: u16, value: u8);
+
+    /// Address of the last access to unmapped memory since the previous
+    /// call, if the bus reports those.
+    fn take_fault (&mut self) -> Option<u32> {
+        None
+    }
+
+    /// Interrupt acknowledge cycle of an interrupt controller cascaded on
+    /// an input of the CPU's ICU: returns the vector it supplies. Nothing
+    /// answers by default, so the data bus floats.
+    fn interrupt_acknowledge (&mut self, _input: u8) -> u8 {
+        0xFF
+    }
+
+    /// DMA request inputs (DREQ0-DREQ3) asserted by devices, as bits 0-3.
+    fn dma_requests (&mut self) -> u8 {
+        0x00
+    }
+
+    /// DMA transfer from the device of a channel to memory: returns the
+    /// byte the device supplies.
+    fn dma_read (&mut self, _channel: u8) -> u8 {
+        0xFF
+    }
+
+    /// DMA transfer from memory to the device of a channel.
+    fn dma_write (&mut self, _channel: u8, _value: u8) {
+    }
+
+    /// The count of a DMA channel ran out, which devices see on the
+    /// terminal count output.
+    fn dma_terminal_count (&mut self, _channel: u8) {
+    }
+
+    /// Append the contents of writable memory and the state of devices to
+    /// a save state. ROM contents aren't saved.
+    fn save_state (&self, _state: &mut StateWriter) {
+    }
+
+    /// Restore what [Bus::save_state] saved, into a bus with the same
+    /// memory and devices.
+    fn load_state (&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+        Ok(())
+    }
+
+}
+
+/// Flat, writable memory with no devices attached. Reads outside of it
+/// return FFH and writes outside of it are ignored. I/O ports just hold
+/// the last value written to them.
+pub struct Memory {
+    memory: Vec<u8>,
+    ports:  Vec<u8>,
+}
+
+impl Memory {
+
+    /// Create `size` bytes of memory, initialized from `image`
+    /// (which must not be larger).
+    pub fn new (size: usize, image: &[u8]) -> Self {
+        let mut memory = vec![0x00; size];
+        memory[..image.len()].copy_from_slice(image);
+        Self { memory, ports: vec![0x00; 0x10000] }
+    }
+
+    /// Read-only handle to memory
+    pub fn memory (&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Read-only handle to I/O ports
+    pub fn ports (&self) -> &[u8] {
+        &self.ports
+    }
+
+}
+
+impl Bus for Memory {
+
+    fn peek (&self, addr: u32) -> u8 {
+        self.memory.get(addr as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write (&mut self, addr: u32, value: u8) {
+        if let Some(byte) = self.memory.get_mut(addr as usize) {
+            *byte = value
+        }
+    }
+
+    fn input (&mut self, port: u16) -> u8 {
+        self.ports[port as usize]
+    }
+
+    fn output (&mut self, port: u16, value: u8) {
+        self.ports[port as usize] = value
+    }
+
+    fn save_state (&self, state: &mut StateWriter) {
+        state.bytes(&self.memory);
+        state.bytes(&self.ports);
+    }
+
+    fn load_state (&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.memory)?;
+        state.bytes_into(&mut self.ports)
+    }
+
+}
@@ -1,36 +1,36 @@
-pub type Instruction<CPU> = (String, Vec<u8>, Box<dyn Fn(&mut CPU)->u64>);
-
-#[macro_export] macro_rules! instruction {
-    ($name: ident ( $cpu: ident ) $body:block) => {
-        #[inline] pub fn $name ($cpu: &mut CPU) -> Instruction<CPU> $body
-    }
-}
+mod bus;
+mod map;
+mod state;
+pub use bus::*;
+pub use map::*;
+pub use state::*;
 
 #[macro_export] macro_rules! define_instruction_set (
 
     ($([$code:literal, $inst:literal, $info:literal, $impl:ident],)+$(,)?) => {
 
         #[allow(unused)]
-        pub fn get_instruction_name (code: u8) -> &'static str {
+        pub fn get_instruction_name (code: u8) -> Option<&'static str> {
             match code {
-                $($code => $inst),+,
-                _ => panic!("undefined instruction {}", code),
+                $($code => Some($inst)),+,
+                _ => None,
             }
         }
 
         #[allow(unused)]
-        pub fn get_instruction_description (code: u8) -> &'static str {
+        pub fn get_instruction_description (code: u8) -> Option<&'static str> {
             match code {
-                $($code => $info),+,
-                _ => panic!("undefined instruction {}", code),
+                $($code => Some($info)),+,
+                _ => None,
             }
         }
 
         #[allow(unused)]
-        pub fn execute_instruction (state: &mut CPU, code: u8) -> u64 {
+        /// Returns `None` for undefined instructions.
+        pub fn execute_instruction (state: &mut CPU, code: u8) -> Option<u64> {
             match code {
-                $($code => $impl(state)),+,
-                _ => panic!("undefined instruction {}", code),
+                $($code => Some($impl(state))),+,
+                _ => None,
             }
         }
 
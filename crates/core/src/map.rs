@@ -0,0 +1,161 @@
+use crate::{Bus, StateWriter, StateReader, StateError};
+
+/// Contents of a range of addresses in a [MemoryMap].
+enum Region {
+    /// Read-only memory. Writes are ignored.
+    Rom(Vec<u8>),
+    /// Writable memory.
+    Ram(Vec<u8>),
+    /// Another range of addresses, starting at this address.
+    Mirror { target: u32, size: u32 },
+}
+
+impl Region {
+    fn size (&self) -> u32 {
+        match self {
+            Self::Rom(data) | Self::Ram(data) => data.len() as u32,
+            Self::Mirror { size, .. } => *size,
+        }
+    }
+}
+
+/// What happens on access to an address outside of every region.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unmapped {
+    /// Reads return this value (the bus floats); writes are ignored.
+    Float(u8),
+    /// Like floating at FFH, but the address is also reported by
+    /// [Bus::take_fault].
+    Fault,
+}
+
+/// Memory made of ROM, RAM and mirrored regions, each at an explicit base
+/// address. Regions added later take precedence where they overlap.
+/// I/O ports just hold the last value written to them.
+pub struct MemoryMap {
+    regions:  Vec<(u32, Region)>,
+    unmapped: Unmapped,
+    fault:    Option<u32>,
+    ports:    Vec<u8>,
+}
+
+impl MemoryMap {
+
+    /// Create a memory map with nothing mapped yet.
+    pub fn new (unmapped: Unmapped) -> Self {
+        Self { regions: vec![], unmapped, fault: None, ports: vec![0x00; 0x10000] }
+    }
+
+    /// Map ROM with the given contents at `base`.
+    pub fn rom (mut self, base: u32, data: Vec<u8>) -> Self {
+        self.regions.push((base, Region::Rom(data)));
+        self
+    }
+
+    /// Map `size` bytes of zeroed RAM at `base`.
+    pub fn ram (mut self, base: u32, size: u32) -> Self {
+        self.regions.push((base, Region::Ram(vec![0x00; size as usize])));
+        self
+    }
+
+    /// Make `size` bytes at `base` access the same memory as `target`.
+    pub fn mirror (mut self, base: u32, size: u32, target: u32) -> Self {
+        self.regions.push((base, Region::Mirror { target, size }));
+        self
+    }
+
+    /// Index of the ROM or RAM region containing `addr`, following mirrors,
+    /// and the offset into it.
+    fn resolve (&self, addr: u32) -> Option<(usize, usize)> {
+        let mut addr = addr;
+        // Bound the number of mirrors followed, in case they form a cycle
+        for _ in 0..=self.regions.len() {
+            let (index, (base, region)) = self.regions.iter().enumerate().rev()
+                .find(|(_, (base, region))| addr >= *base && addr - base < region.size())?;
+            match region {
+                // A mirror past the end of the address space is unmapped
+                Region::Mirror { target, .. } => addr = target.checked_add(addr - base)?,
+                _ => return Some((index, (addr - base) as usize)),
+            }
+        }
+        None
+    }
+
+    /// Value read from unmapped memory.
+    fn float (&self) -> u8 {
+        match self.unmapped {
+            Unmapped::Float(value) => value,
+            Unmapped::Fault => 0xFF,
+        }
+    }
+
+}
+
+impl Bus for MemoryMap {
+
+    fn peek (&self, addr: u32) -> u8 {
+        match self.resolve(addr) {
+            Some((index, offset)) => match &self.regions[index].1 {
+                Region::Rom(data) | Region::Ram(data) => data[offset],
+                Region::Mirror { .. } => unreachable!(),
+            },
+            None => self.float()
+        }
+    }
+
+    fn read (&mut self, addr: u32) -> u8 {
+        if self.unmapped == Unmapped::Fault && self.resolve(addr).is_none() {
+            self.fault = Some(addr);
+        }
+        self.peek(addr)
+    }
+
+    fn write (&mut self, addr: u32, value: u8) {
+        match self.resolve(addr) {
+            Some((index, offset)) => if let Region::Ram(data) = &mut self.regions[index].1 {
+                data[offset] = value
+            },
+            None => if self.unmapped == Unmapped::Fault {
+                self.fault = Some(addr)
+            }
+        }
+    }
+
+    fn input (&mut self, port: u16) -> u8 {
+        self.ports[port as usize]
+    }
+
+    fn output (&mut self, port: u16, value: u8) {
+        self.ports[port as usize] = value
+    }
+
+    fn take_fault (&mut self) -> Option<u32> {
+        self.fault.take()
+    }
+
+    /// Saves the contents of each RAM region, in the order they were mapped.
+    fn save_state (&self, state: &mut StateWriter) {
+        for (_, region) in self.regions.iter() {
+            if let Region::Ram(data) = region {
+                state.bytes(data);
+            }
+        }
+        state.bytes(&self.ports);
+        match self.fault {
+            Some(addr) => { state.bool(true); state.u32(addr) },
+            None => state.bool(false),
+        }
+    }
+
+    fn load_state (&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for (_, region) in self.regions.iter_mut() {
+            if let Region::Ram(data) = region {
+                state.bytes_into(data)?;
+            }
+        }
+        state.bytes_into(&mut self.ports)?;
+        self.fault = if state.bool()? { Some(state.u32()?) } else { None };
+        Ok(())
+    }
+
+}
@@ -0,0 +1,151 @@
+use std::fmt::{Display, Formatter, Result};
+
+/// Reason why a save state couldn't be loaded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// Not a save state, or a corrupted one
+    Format,
+    /// Saved in an incompatible version of the format
+    Version { found: u16, expected: u16 },
+    /// Ended before all of the state was read
+    Truncated,
+    /// Saved from a machine with different memory or devices
+    Layout,
+}
+
+impl Display for StateError {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::Format =>
+                write!(f, "not a save state"),
+            Self::Version { found, expected } =>
+                write!(f, "save state version {found}, expected {expected}"),
+            Self::Truncated =>
+                write!(f, "save state is truncated"),
+            Self::Layout =>
+                write!(f, "save state is from a machine with different memory or devices"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Builds a save state. Values are little-endian; byte strings are
+/// preceded by their length.
+#[derive(Debug, Clone, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+
+    pub fn new () -> Self {
+        Self::default()
+    }
+
+    pub fn u8 (&mut self, value: u8) {
+        self.data.push(value)
+    }
+
+    pub fn u16 (&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes())
+    }
+
+    pub fn u32 (&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes())
+    }
+
+    pub fn u64 (&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes())
+    }
+
+    pub fn bool (&mut self, value: bool) {
+        self.u8(value as u8)
+    }
+
+    /// Write a byte string, preceded by its length.
+    pub fn bytes (&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value)
+    }
+
+    /// Write bytes as they are, e.g. a magic number.
+    pub fn raw (&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value)
+    }
+
+    pub fn finish (self) -> Vec<u8> {
+        self.data
+    }
+
+}
+
+/// Reads back the values of a save state, in the order [StateWriter]
+/// wrote them.
+#[derive(Debug, Clone)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+
+    pub fn new (data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Take the next `length` bytes as they are.
+    pub fn raw (&mut self, length: usize) -> std::result::Result<&'a [u8], StateError> {
+        if self.data.len() < length {
+            return Err(StateError::Truncated)
+        }
+        let (value, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(value)
+    }
+
+    pub fn u8 (&mut self) -> std::result::Result<u8, StateError> {
+        Ok(self.raw(1)?[0])
+    }
+
+    pub fn u16 (&mut self) -> std::result::Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.raw(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32 (&mut self) -> std::result::Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.raw(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64 (&mut self) -> std::result::Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.raw(8)?.try_into().unwrap()))
+    }
+
+    pub fn bool (&mut self) -> std::result::Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Format)
+        }
+    }
+
+    /// Read a byte string written by [StateWriter::bytes].
+    pub fn bytes (&mut self) -> std::result::Result<&'a [u8], StateError> {
+        let length = self.u32()? as usize;
+        self.raw(length)
+    }
+
+    /// Read a byte string into `target`, which must have the same length.
+    pub fn bytes_into (&mut self, target: &mut [u8]) -> std::result::Result<(), StateError> {
+        let value = self.bytes()?;
+        if value.len() != target.len() {
+            return Err(StateError::Layout)
+        }
+        target.copy_from_slice(value);
+        Ok(())
+    }
+
+    /// Check that the whole state was read.
+    pub fn finish (self) -> std::result::Result<(), StateError> {
+        if self.data.is_empty() { Ok(()) } else { Err(StateError::Format) }
+    }
+
+}
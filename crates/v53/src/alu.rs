@@ -0,0 +1,285 @@
+use crate::*;
+
+/// Mnemonics of the eight basic arithmetic/logic operations, indexed by the
+/// operation code in bits 5..3 of opcodes 00H-3DH and in the reg field of
+/// the 80H-83H immediate groups.
+pub const ALU_NAMES: [&str;8] = ["ADD", "OR", "ADDC", "SUBC", "AND", "SUB", "XOR", "CMP"];
+
+/// Operation code of CMP, the only operation which discards its result.
+pub const ALU_CMP: u8 = 0b111;
+
+/// Operation code of AND, which TEST performs without storing the result.
+pub const ALU_AND: u8 = 0b100;
+
+impl CPU {
+
+    /// Perform one of the eight basic operations on a byte or word,
+    /// update CY, V, AC, P, Z and S, and return the result.
+    pub fn alu (&mut self, code: u8, word: bool, dst: u16, src: u16) -> u16 {
+        let (mask, msb) = if word { (0xFFFF, 0x8000) } else { (0xFF, 0x80) };
+        let dst = dst as u32 & mask;
+        let src = src as u32 & mask;
+        let result = match code {
+            0b000 => self.alu_add(dst, src, 0, mask, msb),
+            0b001 => self.alu_logic(dst | src),
+            0b010 => self.alu_add(dst, src, self.cy() as u32, mask, msb),
+            0b011 => self.alu_sub(dst, src, self.cy() as u32, mask, msb),
+            0b100 => self.alu_logic(dst & src),
+            0b101 => self.alu_sub(dst, src, 0, mask, msb),
+            0b110 => self.alu_logic(dst ^ src),
+            0b111 => self.alu_sub(dst, src, 0, mask, msb),
+            _ => unreachable!("alu code {code:b}"),
+        } & mask;
+        self.set_pzs_sized(result as u16, word);
+        result as u16
+    }
+
+    /// INC or DEC a byte or word: like ADD/SUB with 1, but CY is unaffected.
+    pub fn inc_dec (&mut self, word: bool, value: u16, dec: bool) -> u16 {
+        let cy = self.cy();
+        let code = if dec { 0b101 } else { 0b000 };
+        let result = self.alu(code, word, value, 1);
+        self.set_cy(cy);
+        result
+    }
+
+    fn alu_add (&mut self, dst: u32, src: u32, carry: u32, mask: u32, msb: u32) -> u32 {
+        let result = dst + src + carry;
+        self.set_cy(result > mask);
+        self.set_v(((result ^ dst) & (result ^ src) & msb) != 0);
+        self.set_ac(((dst ^ src ^ result) & 0x10) != 0);
+        result
+    }
+
+    fn alu_sub (&mut self, dst: u32, src: u32, borrow: u32, mask: u32, msb: u32) -> u32 {
+        let result = dst.wrapping_sub(src).wrapping_sub(borrow);
+        self.set_cy(src + borrow > dst);
+        self.set_v(((dst ^ src) & (dst ^ result) & msb) != 0);
+        self.set_ac(((dst ^ src ^ result) & 0x10) != 0);
+        result & mask
+    }
+
+    fn alu_logic (&mut self, result: u32) -> u32 {
+        self.set_cy(false);
+        self.set_v(false);
+        self.set_ac(false);
+        result
+    }
+
+}
+
+/// Mnemonics of the shift/rotate operations, indexed by the reg field
+/// of groups C0H, C1H and D0H-D3H. Code 110 is undefined.
+pub const SHIFT_NAMES: [&str;8] = ["ROL", "ROR", "ROLC", "RORC", "SHL", "SHR", "(undefined)", "SHRA"];
+
+impl CPU {
+
+    /// Shift or rotate a byte or word `count` times, bit by bit, and return
+    /// the result. CY receives the last bit shifted out. V is only defined for
+    /// a count of 1 (set if the MSB changed); other counts leave it untouched.
+    /// Shifts also update P, Z and S; rotates don't. A count of 0 is a no-op.
+    pub fn shift (&mut self, code: u8, word: bool, value: u16, count: u8) -> u16 {
+        if count == 0 {
+            return value
+        }
+        let (mask, msb) = if word { (0xFFFF, 0x8000) } else { (0xFF, 0x80) };
+        let mut result = value as u32 & mask;
+        for _ in 0..count {
+            let cy = self.cy() as u32;
+            result = match code {
+                0b000 => {
+                    self.set_cy(result & msb > 0);
+                    ((result << 1) | (result & msb > 0) as u32) & mask
+                },
+                0b001 => {
+                    self.set_cy(result & 1 > 0);
+                    (result >> 1) | if result & 1 > 0 { msb } else { 0 }
+                },
+                0b010 => {
+                    self.set_cy(result & msb > 0);
+                    ((result << 1) | cy) & mask
+                },
+                0b011 => {
+                    self.set_cy(result & 1 > 0);
+                    (result >> 1) | if cy > 0 { msb } else { 0 }
+                },
+                0b100 => {
+                    self.set_cy(result & msb > 0);
+                    (result << 1) & mask
+                },
+                0b101 => {
+                    self.set_cy(result & 1 > 0);
+                    result >> 1
+                },
+                0b111 => {
+                    self.set_cy(result & 1 > 0);
+                    (result >> 1) | (result & msb)
+                },
+                _ => unreachable!("shift code {code:b}")
+            }
+        }
+        if count == 1 {
+            self.set_v(code != 0b111 && (result ^ value as u32) & msb > 0);
+        }
+        if code >= 0b100 {
+            self.set_pzs_sized(result as u16, word);
+        }
+        result as u16
+    }
+
+}
+
+/// Interrupt vector raised by DIVU/DIV on division by zero or quotient overflow.
+pub const VECTOR_DIVIDE_ERROR: u8 = 0;
+
+impl CPU {
+
+    /// Unsigned multiply of AL by a byte (into AW) or of AW by a word (into DW:AW).
+    /// CY and V are set if the upper half of the result is nonzero.
+    pub fn mulu (&mut self, word: bool, src: u16) {
+        let upper = if word {
+            let result = self.aw() as u32 * src as u32;
+            self.set_aw(result as u16);
+            self.set_dw((result >> 16) as u16);
+            (result >> 16) as u16
+        } else {
+            let result = self.al() as u16 * (src as u8) as u16;
+            self.set_aw(result);
+            result >> 8
+        };
+        self.set_cy(upper != 0);
+        self.set_v(upper != 0);
+    }
+
+    /// Signed multiply of AL by a byte (into AW) or of AW by a word (into DW:AW).
+    /// CY and V are set if the upper half is not a sign extension of the lower half.
+    pub fn mul (&mut self, word: bool, src: u16) {
+        let extended = if word {
+            let result = self.aw() as i16 as i32 * src as i16 as i32;
+            self.set_aw(result as u16);
+            self.set_dw((result >> 16) as u16);
+            result == result as i16 as i32
+        } else {
+            let result = self.al() as i8 as i16 * src as u8 as i8 as i16;
+            self.set_aw(result as u16);
+            result == result as i8 as i16
+        };
+        self.set_cy(!extended);
+        self.set_v(!extended);
+    }
+
+    /// Unsigned divide of AW by a byte (AL = quotient, AH = remainder) or of
+    /// DW:AW by a word (AW = quotient, DW = remainder). Returns `false` without
+    /// modifying registers if the divisor is zero or the quotient overflows.
+    pub fn divu (&mut self, word: bool, src: u16) -> bool {
+        if src == 0 {
+            return false
+        }
+        if word {
+            let dividend = (self.dw() as u32) << 16 | self.aw() as u32;
+            let quotient = dividend / src as u32;
+            if quotient > 0xFFFF {
+                return false
+            }
+            self.set_aw(quotient as u16);
+            self.set_dw((dividend % src as u32) as u16);
+        } else {
+            let divisor  = src as u8 as u16;
+            let quotient = self.aw() / divisor;
+            if quotient > 0xFF {
+                return false
+            }
+            self.set_ah((self.aw() % divisor) as u8);
+            self.set_al(quotient as u8);
+        }
+        true
+    }
+
+    /// Signed divide of AW by a byte (AL = quotient, AH = remainder) or of
+    /// DW:AW by a word (AW = quotient, DW = remainder). The remainder takes the
+    /// sign of the dividend. Returns `false` without modifying registers if the
+    /// divisor is zero or the quotient is out of range.
+    pub fn div (&mut self, word: bool, src: u16) -> bool {
+        if word {
+            let dividend = ((self.dw() as u32) << 16 | self.aw() as u32) as i32 as i64;
+            let divisor  = src as i16 as i64;
+            if divisor == 0 {
+                return false
+            }
+            let quotient = dividend / divisor;
+            if quotient < i16::MIN as i64 || quotient > i16::MAX as i64 {
+                return false
+            }
+            self.set_aw(quotient as u16);
+            self.set_dw((dividend % divisor) as u16);
+        } else {
+            let dividend = self.aw() as i16 as i32;
+            let divisor  = src as u8 as i8 as i32;
+            if divisor == 0 {
+                return false
+            }
+            let quotient = dividend / divisor;
+            if quotient < i8::MIN as i32 || quotient > i8::MAX as i32 {
+                return false
+            }
+            self.set_ah((dividend % divisor) as u8);
+            self.set_al(quotient as u8);
+        }
+        true
+    }
+
+}
+
+/// Mnemonics of group F6H/F7H, indexed by the reg field. Code 001 is undefined.
+pub const GROUP1_NAMES: [&str;8] = ["TEST", "(undefined)", "NOT", "NEG", "MULU", "MUL", "DIVU", "DIV"];
+
+impl CPU {
+
+    /// Group F6H (byte) / F7H (word): TEST rm, imm; NOT; NEG; MULU; MUL; DIVU;
+    /// DIV. Division errors raise the divide error vector through the interrupt
+    /// sequence. Returns the cycle count.
+    pub fn group1 (&mut self, code: u8, word: bool, rm: Operand, imm: u16) -> u64 {
+        let src = self.read_operand(word, rm);
+        let memory = self.operand_address(rm).is_some();
+        if code == 0b000 {
+            self.alu(ALU_AND, word, src, imm);
+            return if memory { 6 } else { 2 }
+        }
+        // Register form cost, memory form cost
+        let (reg_cost, mem_cost) = match (code, word) {
+            (0b010, _) | (0b011, _) => (2, 7),
+            (0b100, false) => (8, 12),
+            (0b100, true)  => (12, 16),
+            (0b101, false) => (12, 16),
+            (0b101, true)  => (16, 20),
+            (0b110, false) => (11, 15),
+            (0b110, true)  => (19, 23),
+            (0b111, false) => (17, 20),
+            (0b111, true)  => (24, 28),
+            _ => unreachable!()
+        };
+        let cost = if memory { mem_cost } else { reg_cost };
+        let ok = match code {
+            0b010 => {
+                self.write_operand(word, rm, !src);
+                true
+            },
+            0b011 => {
+                let result = self.alu(0b101, word, 0, src);
+                self.write_operand(word, rm, result);
+                true
+            },
+            0b100 => { self.mulu(word, src); true },
+            0b101 => { self.mul(word, src); true },
+            0b110 => self.divu(word, src),
+            0b111 => self.div(word, src),
+            _ => unreachable!()
+        };
+        if ok {
+            cost
+        } else {
+            cost + self.interrupt(VECTOR_DIVIDE_ERROR)
+        }
+    }
+
+}
@@ -0,0 +1,135 @@
+use crate::*;
+
+impl CPU {
+
+    /// ADJBA: adjust AL after unpacked BCD addition, carrying into AH.
+    pub fn adjba (&mut self) {
+        if (self.al() & 0x0F) > 9 || self.ac() {
+            self.set_al(self.al().wrapping_add(6));
+            self.set_ah(self.ah().wrapping_add(1));
+            self.set_ac(true);
+            self.set_cy(true);
+        } else {
+            self.set_ac(false);
+            self.set_cy(false);
+        }
+        self.set_al(self.al() & 0x0F);
+    }
+
+    /// ADJBS: adjust AL after unpacked BCD subtraction, borrowing from AH.
+    pub fn adjbs (&mut self) {
+        if (self.al() & 0x0F) > 9 || self.ac() {
+            self.set_al(self.al().wrapping_sub(6));
+            self.set_ah(self.ah().wrapping_sub(1));
+            self.set_ac(true);
+            self.set_cy(true);
+        } else {
+            self.set_ac(false);
+            self.set_cy(false);
+        }
+        self.set_al(self.al() & 0x0F);
+    }
+
+    /// ADJ4A: adjust AL after packed BCD addition.
+    pub fn adj4a (&mut self) {
+        let al = self.al();
+        let cy = self.cy();
+        if (al & 0x0F) > 9 || self.ac() {
+            self.set_al(al.wrapping_add(0x06));
+            self.set_ac(true);
+        } else {
+            self.set_ac(false);
+        }
+        if al > 0x99 || cy {
+            self.set_al(self.al().wrapping_add(0x60));
+            self.set_cy(true);
+        } else {
+            self.set_cy(false);
+        }
+        self.set_pzs_u8(self.al());
+    }
+
+    /// ADJ4S: adjust AL after packed BCD subtraction.
+    pub fn adj4s (&mut self) {
+        let al = self.al();
+        let cy = self.cy();
+        if (al & 0x0F) > 9 || self.ac() {
+            self.set_al(al.wrapping_sub(0x06));
+            self.set_ac(true);
+        } else {
+            self.set_ac(false);
+        }
+        if al > 0x99 || cy {
+            self.set_al(self.al().wrapping_sub(0x60));
+            self.set_cy(true);
+        } else {
+            self.set_cy(false);
+        }
+        self.set_pzs_u8(self.al());
+    }
+
+    /// CVTBD: convert binary AL to unpacked BCD in AH (tens) and AL (units).
+    pub fn cvtbd (&mut self) {
+        let al = self.al();
+        self.set_ah(al / 10);
+        self.set_al(al % 10);
+        self.set_pzs(self.aw());
+    }
+
+    /// CVTDB: convert unpacked BCD in AH (tens) and AL (units) to binary AL.
+    pub fn cvtdb (&mut self) {
+        self.set_al(self.ah().wrapping_mul(10).wrapping_add(self.al()));
+        self.set_ah(0);
+        self.set_pzs_u8(self.al());
+    }
+
+    /// ADD4S, SUB4S and CMP4S: operate on packed BCD strings of CL digits,
+    /// least significant byte first. The source is at DS0:IX (segment can be
+    /// overridden), the destination at DS1:IY. IX and IY are not modified.
+    /// CY holds the final carry/borrow and Z is set if every result byte is 0.
+    /// Returns the number of bytes processed.
+    pub fn bcd_string (&mut self, sub: bool, store: bool) -> u16 {
+        let count = (self.cl() as u16).div_ceil(2);
+        let mut carry = false;
+        let mut zero  = true;
+        for i in 0..count {
+            let src = self.read_u8(self.ix().wrapping_add(i));
+            let dst_offset = self.iy().wrapping_add(i);
+            let dst = self.read_segment_u8(self.ds1(), dst_offset);
+            let src = ((src >> 4) * 10 + (src & 0x0F)) as i16;
+            let dst = ((dst >> 4) * 10 + (dst & 0x0F)) as i16;
+            let mut result = if sub {
+                dst - src - carry as i16
+            } else {
+                dst + src + carry as i16
+            };
+            carry = !(0..100).contains(&result);
+            result = result.rem_euclid(100);
+            let packed = (((result / 10) << 4) | (result % 10)) as u8;
+            if packed != 0 {
+                zero = false;
+            }
+            if store {
+                self.write_segment_u8(self.ds1(), dst_offset, packed);
+            }
+        }
+        self.set_cy(carry);
+        self.set_z(zero);
+        count
+    }
+
+    /// ROL4: rotate the nibbles of a byte left through the low nibble of AL.
+    pub fn rol4 (&mut self, value: u8) -> u8 {
+        let al = self.al();
+        self.set_al((al & 0xF0) | (value >> 4));
+        (value << 4) | (al & 0x0F)
+    }
+
+    /// ROR4: rotate the nibbles of a byte right through the low nibble of AL.
+    pub fn ror4 (&mut self, value: u8) -> u8 {
+        let al = self.al();
+        self.set_al((al & 0xF0) | (value & 0x0F));
+        ((al & 0x0F) << 4) | (value >> 4)
+    }
+
+}
@@ -1,3 +1,6 @@
+// Complete sets of bit masks; not every one is in use.
+#![allow(dead_code)]
+
 pub const B0: u8 = 0b00000001;
 pub const B1: u8 = 0b00000010;
 pub const B2: u8 = 0b00000100;
@@ -0,0 +1,75 @@
+use crate::*;
+
+/// Mnemonics of the single-bit instructions 0F 10-1F, indexed by bits 2..1
+/// of the second opcode byte.
+pub const BIT_NAMES: [&str;4] = ["TEST1", "CLR1", "SET1", "NOT1"];
+
+impl CPU {
+
+    /// TEST1, CLR1, SET1 or NOT1 on bit `bit` of a byte or word.
+    /// The bit number is taken modulo the operand width. TEST1 sets Z if the
+    /// bit is clear and resets CY and V; the others leave flags untouched.
+    /// Returns the (possibly modified) value.
+    pub fn bit_op (&mut self, code: u8, word: bool, value: u16, bit: u8) -> u16 {
+        let mask = 1 << (bit & if word { 0x0F } else { 0x07 });
+        match code {
+            0b00 => {
+                self.set_z(value & mask == 0);
+                self.set_cy(false);
+                self.set_v(false);
+                value
+            },
+            0b01 => value & !mask,
+            0b10 => value | mask,
+            0b11 => value ^ mask,
+            _ => unreachable!()
+        }
+    }
+
+    /// INS: insert the low `length` bits of AW into the bit field that starts
+    /// at bit `offset` (0-15) of the word at DS1:IY. The field may span two
+    /// words. Returns the offset of the next field; IY is advanced by 2 when
+    /// the field reaches into the next word.
+    pub fn insert_bit_field (&mut self, offset: u8, length: u8) -> u8 {
+        let offset = (offset & 0x0F) as u32;
+        let length = (length & 0x0F) as u32 + 1;
+        let spans = offset + length > 16;
+        let (ds1, iy) = (self.ds1(), self.iy());
+        let lo = self.read_segment_u16(ds1, iy) as u32;
+        let hi = if spans { self.read_segment_u16(ds1, iy.wrapping_add(2)) as u32 } else { 0 };
+        let mask = ((1u32 << length) - 1) << offset;
+        let field = ((hi << 16 | lo) & !mask) | (((self.aw() as u32) << offset) & mask);
+        self.write_segment_u16(ds1, iy, field as u16);
+        if spans {
+            self.write_segment_u16(ds1, iy.wrapping_add(2), (field >> 16) as u16);
+        }
+        self.advance_bit_field(offset, length, true)
+    }
+
+    /// EXT: load the bit field of `length` bits that starts at bit `offset`
+    /// (0-15) of the word at DS0:IX into AW, zero-extended. The field may span
+    /// two words. Returns the offset of the next field; IX is advanced by 2
+    /// when the field reaches into the next word.
+    pub fn extract_bit_field (&mut self, offset: u8, length: u8) -> u8 {
+        let offset = (offset & 0x0F) as u32;
+        let length = (length & 0x0F) as u32 + 1;
+        let lo = self.read_u16(self.ix()) as u32;
+        let hi = if offset + length > 16 { self.read_u16(self.ix().wrapping_add(2)) as u32 } else { 0 };
+        let field = ((hi << 16 | lo) >> offset) & ((1u32 << length) - 1);
+        self.set_aw(field as u16);
+        self.advance_bit_field(offset, length, false)
+    }
+
+    fn advance_bit_field (&mut self, offset: u32, length: u32, insert: bool) -> u8 {
+        let next = offset + length;
+        if next > 15 {
+            if insert {
+                self.set_iy(self.iy().wrapping_add(2));
+            } else {
+                self.set_ix(self.ix().wrapping_add(2));
+            }
+        }
+        (next & 0x0F) as u8
+    }
+
+}
@@ -0,0 +1,131 @@
+use crate::*;
+
+/// Interrupt vector raised by BRK 3 (single-byte breakpoint).
+pub const VECTOR_BREAKPOINT: u8 = 3;
+
+/// Interrupt vector raised by BRKV when V is set.
+pub const VECTOR_OVERFLOW: u8 = 4;
+
+/// Interrupt vector raised by CHKIND when the index is out of bounds.
+pub const VECTOR_ARRAY_BOUNDS: u8 = 5;
+
+/// Interrupt vector raised by undefined opcodes, if the trap is enabled.
+pub const VECTOR_INVALID_OPCODE: u8 = 6;
+
+impl CPU {
+
+    /// Enter an interrupt handler: push PSW, PS and PC, clear IE and BRK,
+    /// and load PS:PC from the vector table. Returns the cycle count.
+    pub fn interrupt (&mut self, vector: u8) -> u64 {
+        let (tc, ta) = self.read_vector(vector);
+        self.push_u16(self.psw());
+        self.set_ie(false);
+        self.set_brk(false);
+        self.push_u16(self.ps());
+        self.set_ps(tc);
+        self.push_u16(self.pc());
+        self.set_pc(ta);
+        self.flush_queue();
+        18
+    }
+
+    /// Read the PS and PC of an entry in the interrupt vector table.
+    pub fn read_vector (&mut self, vector: u8) -> (u16, u16) {
+        let offset = vector as u16 * 4;
+        let pc = self.read_segment_u16(0x0000, offset);
+        let ps = self.read_segment_u16(0x0000, offset + 2);
+        (ps, pc)
+    }
+
+    /// Suspend execution until an interrupt is accepted.
+    pub fn halt (&mut self) {
+        self.halted = true;
+    }
+
+    /// PREPARE: create a stack frame of `size` bytes for a procedure at
+    /// lexical nesting `level` (0-31), copying the enclosing frame pointers.
+    /// Returns the cycle count.
+    pub fn prepare (&mut self, size: u16, level: u8) -> u64 {
+        let level = level & 0x1F;
+        self.push_u16(self.bp());
+        let frame = self.sp();
+        if level > 0 {
+            for _ in 1..level {
+                self.set_bp(self.bp().wrapping_sub(2));
+                let data = self.read_segment_u16(self.ss(), self.bp());
+                self.push_u16(data);
+            }
+            self.push_u16(frame);
+        }
+        self.set_bp(frame);
+        self.set_sp(self.sp().wrapping_sub(size));
+        match level {
+            0 => 12,
+            1 => 18,
+            _ => 19 + 8 * (level as u64 - 1)
+        }
+    }
+
+    /// DISPOSE: release the stack frame created by PREPARE.
+    pub fn dispose (&mut self) {
+        self.set_sp(self.bp());
+        let bp = self.pop_u16();
+        self.set_bp(bp);
+    }
+
+}
+
+impl CPU {
+
+    /// Far call: push PS and PC, then load the new PS:PC.
+    pub fn call_far (&mut self, segment: u16, offset: u16) {
+        self.push_u16(self.ps());
+        self.push_u16(self.pc());
+        self.set_ps(segment);
+        self.set_pc(offset);
+    }
+
+    /// Return from a near (PC only) or far (PC and PS) procedure,
+    /// then release `pop` bytes of parameters from the stack.
+    pub fn ret (&mut self, far: bool, pop: u16) {
+        let pc = self.pop_u16();
+        self.set_pc(pc);
+        if far {
+            let ps = self.pop_u16();
+            self.set_ps(ps);
+        }
+        self.set_sp(self.sp().wrapping_add(pop));
+    }
+
+}
+
+/// Mnemonics of the conditional branches 70H-7FH, indexed by the low nibble
+/// of the opcode.
+pub const CONDITION_NAMES: [&str;16] = [
+    "BV", "BNV", "BC", "BNC", "BE", "BNE", "BNH", "BH",
+    "BN", "BP", "BPE", "BPO", "BLT", "BGE", "BLE", "BGT",
+];
+
+/// Mnemonics of E0H-E3H, indexed by the low bits of the opcode.
+pub const LOOP_NAMES: [&str;4] = ["DBNZNE", "DBNZE", "DBNZ", "BCWZ"];
+
+impl CPU {
+
+    /// Whether the condition of a conditional branch holds.
+    pub fn condition (&self, condition: u8) -> bool {
+        let result = match condition >> 1 {
+            0b000 => self.v(),
+            0b001 => self.cy(),
+            0b010 => self.z(),
+            0b011 => self.cy() || self.z(),
+            0b100 => self.s(),
+            0b101 => self.p(),
+            0b110 => self.s() != self.v(),
+            0b111 => self.z() || self.s() != self.v(),
+            _ => unreachable!()
+        };
+        // Odd conditions are the negation of the preceding even ones
+        result != (condition & 1 > 0)
+    }
+
+}
@@ -0,0 +1,228 @@
+use crate::*;
+use std::fmt::{Display, Formatter, Result};
+
+impl Display for Segment {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        f.write_str(match self {
+            Segment::DS0 => "DS0",
+            Segment::DS1 => "DS1",
+            Segment::PS  => "PS",
+            Segment::SS  => "SS",
+        })
+    }
+}
+
+/// Register/memory operand of a given width.
+struct Rm(bool, Operand);
+
+impl Display for Rm {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        let Rm(word, operand) = *self;
+        let (mode, mem, disp) = match operand {
+            Operand::Register(reg) => return f.write_str(register_name(word, reg)),
+            Operand::Memory { mode, mem, disp } => (mode, mem, disp)
+        };
+        let base = match (mode, mem) {
+            (0b00, 0b110) => return write!(f, "[{disp:04X}]"),
+            (_, 0b000) => "BW + IX",
+            (_, 0b001) => "BW + IY",
+            (_, 0b010) => "BP + IX",
+            (_, 0b011) => "BP + IY",
+            (_, 0b100) => "IX",
+            (_, 0b101) => "IY",
+            (_, 0b110) => "BP",
+            (_, 0b111) => "BW",
+            _ => unreachable!(),
+        };
+        match mode {
+            0b00 => write!(f, "[{base}]"),
+            0b01 => write!(f, "[{base} {:+}]", disp as i16),
+            _    => write!(f, "[{base} + {disp:04X}]"),
+        }
+    }
+}
+
+impl Display for Count {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        match self {
+            Count::One            => f.write_str("1"),
+            Count::CL             => f.write_str("CL"),
+            Count::Register(reg)  => f.write_str(register_name_u8(*reg)),
+            Count::Immediate(imm) => write!(f, "{imm:X}"),
+        }
+    }
+}
+
+/// Suffix of mnemonics operating on words.
+fn w (word: bool) -> &'static str {
+    if word { "W" } else { "" }
+}
+
+/// Suffix of mnemonics operating on words in memory, where the operand
+/// doesn't tell the width.
+fn mem_w (word: bool, rm: Operand) -> &'static str {
+    w(word && matches!(rm, Operand::Memory { .. }))
+}
+
+fn acc (word: bool) -> &'static str {
+    if word { "AW" } else { "AL" }
+}
+
+impl Display for Instruction {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        if f.width().is_some() {
+            // Pad the whole instruction, like a string
+            f.pad(&Unpadded(self).to_string())
+        } else {
+            Unpadded(self).fmt(f)
+        }
+    }
+}
+
+/// An instruction, ignoring the width of the format.
+struct Unpadded<'a>(&'a Instruction);
+
+impl Display for Unpadded<'_> {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        use Instruction::*;
+        match *self.0 {
+
+            Segment(segment) =>
+                write!(f, "{segment}:"),
+            Repeat { prefix, segment: Some(segment), op } =>
+                write!(f, "{} {segment}: {}", repeat_name(prefix), string_name(op).unwrap()),
+            Repeat { prefix, segment: None, op } =>
+                write!(f, "{} {}", repeat_name(prefix), string_name(op).unwrap()),
+            Prefix(prefix) =>
+                f.write_str(repeat_name(prefix)),
+
+            Alu { code, word, to_reg: true, reg, rm } =>
+                write!(f, "{}{} {}, {}", ALU_NAMES[code as usize], w(word), register_name(word, reg), Rm(word, rm)),
+            Alu { code, word, to_reg: false, reg, rm } =>
+                write!(f, "{}{} {}, {}", ALU_NAMES[code as usize], w(word), Rm(word, rm), register_name(word, reg)),
+            AluAcc { code, word, imm } =>
+                write!(f, "{}{} {}, {imm:X}", ALU_NAMES[code as usize], w(word), acc(word)),
+            AluImm { code, word, rm, imm } =>
+                write!(f, "{}{} {}, {imm:X}", ALU_NAMES[code as usize], w(word), Rm(word, rm)),
+            Test { word, reg, rm } =>
+                write!(f, "TEST{} {}, {}", w(word), Rm(word, rm), register_name(word, reg)),
+            TestAcc { word, imm } =>
+                write!(f, "TEST{} {}, {imm:X}", w(word), acc(word)),
+            IncDec { dec, word, rm } =>
+                write!(f, "{}{} {}", if dec { "DEC" } else { "INC" }, mem_w(word, rm), Rm(word, rm)),
+            Shift { code, word, rm, count } =>
+                write!(f, "{}{} {}, {count}", SHIFT_NAMES[code as usize], w(word), Rm(word, rm)),
+            Group1 { code: 0b000, word, rm, imm } =>
+                write!(f, "TEST{} {}, {imm:X}", w(word), Rm(word, rm)),
+            Group1 { code, word, rm, .. } =>
+                write!(f, "{}{} {}", GROUP1_NAMES[code as usize], w(word), Rm(word, rm)),
+            MulImm { reg, rm, imm } =>
+                write!(f, "MUL {}, {}, {imm:X}", register_name_u16(reg), Rm(true, rm)),
+
+            Adjust(op) => f.write_str(match op {
+                0x27 => "ADJ4A",
+                0x2F => "ADJ4S",
+                0x37 => "ADJBA",
+                0x3F => "ADJBS",
+                _ => unreachable!()
+            }),
+            Cvtbd => f.write_str("CVTBD"),
+            Cvtdb => f.write_str("CVTDB"),
+            Cvtbw => f.write_str("CVTBW"),
+            Cvtwl => f.write_str("CVTWL"),
+            BcdString(op) => f.write_str(match op {
+                0x20 => "ADD4S",
+                0x22 => "SUB4S",
+                0x26 => "CMP4S",
+                _ => unreachable!()
+            }),
+            RotateNibble { left, rm } =>
+                write!(f, "{} {}", if left { "ROL4" } else { "ROR4" }, Rm(false, rm)),
+            Bit { code, word, rm, bit } =>
+                write!(f, "{}{} {}, {bit}", BIT_NAMES[code as usize], w(word), Rm(word, rm)),
+            BitField { insert, offset, length } =>
+                write!(f, "{} {}, {length}", if insert { "INS" } else { "EXT" }, register_name_u8(offset)),
+            String(op) =>
+                f.write_str(string_name(op).unwrap()),
+
+            Mov { word, to_reg: true, reg, rm } =>
+                write!(f, "MOV {}, {}", register_name(word, reg), Rm(word, rm)),
+            Mov { word, to_reg: false, reg, rm } =>
+                write!(f, "MOV {}, {}", Rm(word, rm), register_name(word, reg)),
+            MovSegment { to_segment: true, sreg, rm } =>
+                write!(f, "MOV {}, {}", segment_register_name(sreg), Rm(true, rm)),
+            MovSegment { to_segment: false, sreg, rm } =>
+                write!(f, "MOV {}, {}", Rm(true, rm), segment_register_name(sreg)),
+            MovAcc { word, to_acc: true, addr } =>
+                write!(f, "MOV {}, [{addr:04X}]", acc(word)),
+            MovAcc { word, to_acc: false, addr } =>
+                write!(f, "MOV [{addr:04X}], {}", acc(word)),
+            MovImm { word, rm, imm } =>
+                write!(f, "MOV{} {}, {imm:X}", mem_w(word, rm), Rm(word, rm)),
+            LoadPointer { segment, reg, rm } =>
+                write!(f, "MOV {segment}, {}, {}", register_name_u16(reg), Rm(true, rm)),
+            Ldea { reg, rm } =>
+                write!(f, "LDEA {}, {}", register_name_u16(reg), Rm(true, rm)),
+            Xch { word, reg, rm } =>
+                write!(f, "XCH {}, {}", register_name(word, reg), Rm(word, rm)),
+            Nop => f.write_str("NOP"),
+            Push(rm) => write!(f, "PUSH {}", Rm(true, rm)),
+            Pop(rm)  => write!(f, "POP {}", Rm(true, rm)),
+            PushSegment(sreg) => write!(f, "PUSH {}", segment_register_name(sreg)),
+            PopSegment(sreg)  => write!(f, "POP {}", segment_register_name(sreg)),
+            PushImm(imm) => write!(f, "PUSH {imm:X}"),
+            PushPsw => f.write_str("PUSH PSW"),
+            PopPsw  => f.write_str("POP PSW"),
+            PushRegisters => f.write_str("PUSH R"),
+            PopRegisters  => f.write_str("POP R"),
+            MovPswAh => f.write_str("MOV PSW, AH"),
+            MovAhPsw => f.write_str("MOV AH, PSW"),
+            Trans => f.write_str("TRANS"),
+            In { word, port: Some(port) } => write!(f, "IN {}, {port:X}", acc(word)),
+            In { word, port: None }       => write!(f, "IN {}, DW", acc(word)),
+            Out { word, port: Some(port) } => write!(f, "OUT {port:X}, {}", acc(word)),
+            Out { word, port: None }       => write!(f, "OUT DW, {}", acc(word)),
+
+            Branch { condition, disp } =>
+                write!(f, "{} {disp}", CONDITION_NAMES[condition as usize]),
+            Loop { code, disp } =>
+                write!(f, "{} {disp}", LOOP_NAMES[code as usize]),
+            BranchRelative(disp) => write!(f, "BR {disp}"),
+            CallRelative(disp)   => write!(f, "CALL {disp}"),
+            BranchFar { segment, offset } => write!(f, "BR {segment:04X}:{offset:04X}"),
+            CallFar { segment, offset }   => write!(f, "CALL {segment:04X}:{offset:04X}"),
+            BranchIndirect(rm)    => write!(f, "BR {}", Rm(true, rm)),
+            CallIndirect(rm)      => write!(f, "CALL {}", Rm(true, rm)),
+            BranchFarIndirect(rm) => write!(f, "BR FAR {}", Rm(true, rm)),
+            CallFarIndirect(rm)   => write!(f, "CALL FAR {}", Rm(true, rm)),
+            Ret { far, pop: Some(pop) } => write!(f, "{} {pop:X}", if far { "RETF" } else { "RET" }),
+            Ret { far, pop: None }      => f.write_str(if far { "RETF" } else { "RET" }),
+            Reti => f.write_str("RETI"),
+            Brk(vector) => write!(f, "BRK {vector:X}"),
+            Brkv => f.write_str("BRKV"),
+            Halt => f.write_str("HALT"),
+            Poll => f.write_str("POLL"),
+            Prepare { size, level } => write!(f, "PREPARE {size:X}, {level:X}"),
+            Dispose => f.write_str("DISPOSE"),
+            Chkind { reg, rm } => write!(f, "CHKIND {}, {}", register_name_u16(reg), Rm(true, rm)),
+            Brkxa(vector) => write!(f, "BRKXA {vector:X}"),
+            Retxa(vector) => write!(f, "RETXA {vector:X}"),
+
+            NotCy  => f.write_str("NOT1 CY"),
+            ClrCy  => f.write_str("CLR1 CY"),
+            SetCy  => f.write_str("SET1 CY"),
+            Di     => f.write_str("DI"),
+            Ei     => f.write_str("EI"),
+            ClrDir => f.write_str("CLR1 DIR"),
+            SetDir => f.write_str("SET1 DIR"),
+
+            Coprocessor { op, code, rm } =>
+                write!(f, "{} {code:X}, {}", if op >= 0xD8 { "FPO1" } else { "FPO2" }, Rm(true, rm)),
+            Undefined(op) =>
+                write!(f, "(undefined {op:02X})"),
+            UndefinedExtended(op) =>
+                write!(f, "(undefined 0F {op:02X})"),
+
+        }
+    }
+}
@@ -0,0 +1,426 @@
+//! DMA unit: four channels transferring between memory and the devices on
+//! the bus, programmed either as a uPD71071 or, when SCTL selects it, as a
+//! uPD71037 (compatible with the 8237). Transfers take place between
+//! instructions, stealing bus cycles from the CPU.
+
+use crate::*;
+
+/// Mode bit: transfer words (uPD71071 only)
+const MODE_WORD: u8 = 0x01;
+/// Mode bit: reload the base address and count at terminal count
+const MODE_AUTOINITIALIZE: u8 = 0x10;
+/// Mode bit: decrement the address
+const MODE_DECREMENT: u8 = 0x20;
+
+/// Control bit: channel 0 to channel 1 memory-to-memory transfers
+const CONTROL_MEMORY: u8 = 0x01;
+/// Control bit: channel 0 keeps its address in memory-to-memory transfers
+const CONTROL_HOLD: u8 = 0x02;
+/// Control bit: no transfers
+const CONTROL_DISABLE: u8 = 0x04;
+
+/// Transfers allowed for a single request, in case a device never stops
+/// requesting in demand mode
+const MAX_TRANSFERS: usize = 0x20000;
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct Channel {
+    base_address: u32,
+    address:      u32,
+    base_count:   u16,
+    count:        u16,
+    /// Bit 0: words; bits 3-2: verify (00), device to memory (01) or
+    /// memory to device (10); bit 4: autoinitialize; bit 5: decrement;
+    /// bits 7-6: demand (00), single (01), block (10) or cascade (11)
+    mode:         u8,
+}
+
+impl Channel {
+
+    /// Transfer mode: 0 demand, 1 single, 2 block, 3 cascade
+    fn transfer_mode (&self) -> u8 {
+        self.mode >> 6
+    }
+
+    /// Step the address past a transfer of `size` bytes.
+    fn step_address (&mut self, size: u32) {
+        self.address = if self.mode & MODE_DECREMENT != 0 {
+            self.address.wrapping_sub(size)
+        } else {
+            self.address.wrapping_add(size)
+        } & 0xFFFFFF;
+    }
+
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dmau {
+    channels:  [Channel;4],
+    /// uPD71071 channel register: channel that registers 2-6 and A access
+    selected:  u8,
+    /// uPD71071 channel register: write only the base registers
+    base_only: bool,
+    /// uPD71071 initialize register: 16-bit data bus
+    wide:      bool,
+    /// uPD71071 device control register
+    control:   u16,
+    /// 8237 command register
+    command:   u8,
+    /// Channels that reached terminal count since the status was read
+    status:    u8,
+    /// Software DMA requests
+    requests:  u8,
+    /// Masked channels
+    mask:      u8,
+    /// Data of the last memory-to-memory transfer
+    temporary: u16,
+    /// 8237 byte pointer flip-flop: the next access is the upper byte
+    flip_flop: bool,
+    /// Channel serviced last, the lowest priority when priorities rotate
+    last:      u8,
+}
+
+impl Default for Dmau {
+    fn default () -> Self {
+        Self {
+            channels: [Channel::default();4], selected: 0, base_only: false, wide: false,
+            control: 0x0000, command: 0x00, status: 0x00, requests: 0x00, mask: 0x0F,
+            temporary: 0x0000, flip_flop: false, last: 3,
+        }
+    }
+}
+
+impl Dmau {
+
+    /// Control bits of the register set in use: memory-to-memory, hold,
+    /// disable, and whether priorities rotate.
+    fn control (&self, i8237: bool) -> (u8, bool) {
+        if i8237 {
+            (self.command & 0x07, self.command & 0x10 != 0)
+        } else {
+            (self.control as u8 & 0x07, self.control & 0x20 != 0)
+        }
+    }
+
+    /// Read a register, with the register set of the uPD71071 or the 8237.
+    pub fn read (&mut self, register: u8, i8237: bool) -> u8 {
+        if i8237 {
+            return self.read_8237(register)
+        }
+        let channel = &self.channels[self.selected as usize];
+        let (address, count) = if self.base_only {
+            (channel.base_address, channel.base_count)
+        } else {
+            (channel.address, channel.count)
+        };
+        match register {
+            0x0 => (self.wide as u8) << 1,
+            0x1 => (self.base_only as u8) << 4 | 1 << self.selected,
+            0x2 => count as u8,
+            0x3 => (count >> 8) as u8,
+            0x4 => address as u8,
+            0x5 => (address >> 8) as u8,
+            0x6 => (address >> 16) as u8,
+            0x8 => self.control as u8,
+            0x9 => (self.control >> 8) as u8,
+            0xA => channel.mode,
+            0xB => self.read_status(),
+            0xC => self.temporary as u8,
+            0xD => (self.temporary >> 8) as u8,
+            0xE => self.requests,
+            0xF => self.mask,
+            _ => 0xFF
+        }
+    }
+
+    /// Write a register, with the register set of the uPD71071 or the 8237.
+    pub fn write (&mut self, register: u8, data: u8, i8237: bool) {
+        if i8237 {
+            return self.write_8237(register, data)
+        }
+        let base_only = self.base_only;
+        let channel = &mut self.channels[self.selected as usize];
+        match register {
+            0x0 => if data & 0x01 != 0 {
+                *self = Self::default();
+            } else {
+                self.wide = data & 0x02 != 0;
+            },
+            0x1 => {
+                self.selected = data & 3;
+                self.base_only = data & 0x04 != 0;
+            },
+            0x2 | 0x3 => {
+                let shift = (register - 2) * 8;
+                let set = |count: u16| (count & !(0xFF << shift)) | (data as u16) << shift;
+                channel.base_count = set(channel.base_count);
+                if !base_only {
+                    channel.count = set(channel.count);
+                }
+            },
+            0x4..=0x6 => {
+                let shift = (register as u32 - 4) * 8;
+                let set = |address: u32| (address & !(0xFF << shift)) | (data as u32) << shift;
+                channel.base_address = set(channel.base_address);
+                if !base_only {
+                    channel.address = set(channel.address);
+                }
+            },
+            0x8 => self.control = (self.control & 0xFF00) | data as u16,
+            0x9 => self.control = (self.control & 0x00FF) | (data as u16) << 8,
+            0xA => channel.mode = data,
+            0xE => self.requests = data & 0x0F,
+            0xF => self.mask = data & 0x0F,
+            _ => {}
+        }
+    }
+
+    /// Status: channels that reached terminal count (bits 0-3, cleared by
+    /// reading) and software requests (bits 4-7).
+    fn read_status (&mut self) -> u8 {
+        let status = self.status | self.requests << 4;
+        self.status = 0;
+        status
+    }
+
+    fn read_8237 (&mut self, register: u8) -> u8 {
+        match register {
+            0x0..=0x7 => {
+                let channel = &self.channels[register as usize / 2];
+                let value = if register & 1 == 0 { channel.address as u16 } else { channel.count };
+                let byte = if self.flip_flop { (value >> 8) as u8 } else { value as u8 };
+                self.flip_flop = !self.flip_flop;
+                byte
+            },
+            0x8 => self.read_status(),
+            0xD => self.temporary as u8,
+            _ => 0xFF
+        }
+    }
+
+    fn write_8237 (&mut self, register: u8, data: u8) {
+        let index = (data & 3) as usize;
+        match register {
+            0x0..=0x7 => {
+                let shift = if self.flip_flop { 8 } else { 0 };
+                self.flip_flop = !self.flip_flop;
+                let channel = &mut self.channels[register as usize / 2];
+                let set = |value: u16| (value & !(0xFF << shift)) | (data as u16) << shift;
+                if register & 1 == 0 {
+                    // Bits 16-23 keep what was set in uPD71071 mode
+                    let address = set(channel.base_address as u16) as u32;
+                    channel.base_address = (channel.base_address & 0xFF0000) | address;
+                    channel.address = channel.base_address;
+                } else {
+                    channel.base_count = set(channel.base_count);
+                    channel.count = channel.base_count;
+                }
+            },
+            0x8 => self.command = data,
+            0x9 => if data & 0x04 != 0 {
+                self.requests |= 1 << index
+            } else {
+                self.requests &= !(1 << index)
+            },
+            0xA => if data & 0x04 != 0 {
+                self.mask |= 1 << index
+            } else {
+                self.mask &= !(1 << index)
+            },
+            // Same layout as the uPD71071, except for the channel in bits 1-0
+            0xB => self.channels[index].mode = data & 0xFC,
+            0xC => self.flip_flop = false,
+            0xD => {
+                let channels = self.channels;
+                *self = Self { channels, ..Self::default() };
+            },
+            0xE => self.mask = 0x00,
+            0xF => self.mask = data & 0x0F,
+            _ => {}
+        }
+    }
+
+    /// Unmasked channel with the highest priority among `requests`.
+    fn next_channel (&self, requests: u8, rotate: bool) -> Option<usize> {
+        let first = if rotate { (self.last + 1) & 3 } else { 0 };
+        (0..4).map(|i| ((first + i) & 3) as usize)
+            .find(|index| requests & !self.mask & (1 << index) != 0)
+    }
+
+    pub(crate) fn save_state (&self, state: &mut StateWriter) {
+        for channel in self.channels.iter() {
+            state.u32(channel.base_address);
+            state.u32(channel.address);
+            state.u16(channel.base_count);
+            state.u16(channel.count);
+            state.u8(channel.mode);
+        }
+        for value in [self.selected, self.command, self.status, self.requests, self.mask, self.last] {
+            state.u8(value);
+        }
+        for value in [self.control, self.temporary] {
+            state.u16(value);
+        }
+        for value in [self.base_only, self.wide, self.flip_flop] {
+            state.bool(value);
+        }
+    }
+
+    pub(crate) fn load_state (state: &mut StateReader) -> Result<Self, StateError> {
+        let mut dmau = Self::default();
+        for channel in dmau.channels.iter_mut() {
+            *channel = Channel {
+                base_address: state.u32()?,
+                address:      state.u32()?,
+                base_count:   state.u16()?,
+                count:        state.u16()?,
+                mode:         state.u8()?,
+            };
+        }
+        for value in [
+            &mut dmau.selected, &mut dmau.command, &mut dmau.status, &mut dmau.requests,
+            &mut dmau.mask, &mut dmau.last
+        ] {
+            *value = state.u8()?;
+        }
+        dmau.control   = state.u16()?;
+        dmau.temporary = state.u16()?;
+        dmau.base_only = state.bool()?;
+        dmau.wide      = state.bool()?;
+        dmau.flip_flop = state.bool()?;
+        if dmau.selected > 3 || dmau.last > 3 {
+            return Err(StateError::Format)
+        }
+        Ok(dmau)
+    }
+
+}
+
+impl CPU {
+
+    /// DMA unit
+    pub fn dmau (&self) -> &Dmau {
+        &self.dmau
+    }
+
+    /// Whether the DMA unit uses the register set of the 8237.
+    pub(crate) fn dma_8237 (&self) -> bool {
+        self.internal_register(SCTL) & 0x02 != 0
+    }
+
+    /// Serve the DMA request with the highest priority, from a device on
+    /// the bus or from software: one transfer in single mode, transfers
+    /// for as long as the request lasts in demand mode, or until terminal
+    /// count in block mode and for software requests.
+    pub(crate) fn service_dma (&mut self) {
+        if self.internal_register(OPSEL) & Unit::Dmau.enable() == 0 {
+            return
+        }
+        let i8237 = self.dma_8237();
+        let (control, rotate) = self.dmau.control(i8237);
+        if control & CONTROL_DISABLE != 0 {
+            return
+        }
+        let requests = (self.bus.dma_requests() | self.dmau.requests) & 0x0F;
+        let Some(index) = self.dmau.next_channel(requests, rotate) else {
+            return
+        };
+        self.dmau.last = index as u8;
+        let bit = 1 << index;
+        if index == 0 && control & CONTROL_MEMORY != 0 {
+            // Memory to memory, from channel 0 to channel 1, until the
+            // count of channel 1 runs out
+            for _ in 0..MAX_TRANSFERS {
+                if self.dma_memory_transfer(control & CONTROL_HOLD != 0, i8237) {
+                    self.dmau.requests &= !bit;
+                    break
+                }
+            }
+            return
+        }
+        let software = self.dmau.requests & bit != 0;
+        let mode = self.dmau.channels[index].transfer_mode();
+        for _ in 0..MAX_TRANSFERS {
+            if mode == 3 || self.dma_transfer(index, i8237) {
+                break
+            }
+            let requested = self.bus.dma_requests() & bit != 0;
+            match mode {
+                0 if software || requested => {},
+                2 => {},
+                _ => break
+            }
+        }
+    }
+
+    /// Transfer a byte or word between memory and the device of a channel.
+    /// Returns whether the count ran out.
+    fn dma_transfer (&mut self, index: usize, i8237: bool) -> bool {
+        let channel = self.dmau.channels[index];
+        let size = if channel.mode & MODE_WORD != 0 && !i8237 { 2 } else { 1 };
+        let addr = channel.address & 0xFFFFFF;
+        for i in 0..size {
+            let addr = (addr + i) & 0xFFFFFF;
+            match (channel.mode >> 2) & 3 {
+                1 => {
+                    let data = self.bus.dma_read(index as u8);
+                    self.write_physical(addr, data);
+                },
+                2 => {
+                    let data = self.bus.read(addr);
+                    self.bus.dma_write(index as u8, data);
+                },
+                _ => {}
+            }
+        }
+        self.dma_cycle(addr, size == 2);
+        self.dma_advance(index, size)
+    }
+
+    /// Copy a byte or word from the address of channel 0 to the address of
+    /// channel 1. Returns whether the count of channel 1 ran out.
+    fn dma_memory_transfer (&mut self, hold: bool, i8237: bool) -> bool {
+        let [source, destination] = [0, 1].map(|index| self.dmau.channels[index]);
+        let size = if source.mode & MODE_WORD != 0 && !i8237 { 2 } else { 1 };
+        let mut data = 0u16;
+        for i in 0..size {
+            let byte = self.bus.read((source.address + i) & 0xFFFFFF);
+            self.write_physical((destination.address + i) & 0xFFFFFF, byte);
+            data |= (byte as u16) << (i * 8);
+        }
+        self.dmau.temporary = data;
+        self.dma_cycle(source.address & 0xFFFFFF, size == 2);
+        self.dma_cycle(destination.address & 0xFFFFFF, size == 2);
+        // Terminal count only comes from channel 1
+        let source = &mut self.dmau.channels[0];
+        if !hold {
+            source.step_address(size);
+        }
+        source.count = source.count.wrapping_sub(1);
+        self.dma_advance(1, size)
+    }
+
+    /// Step the address and count of a channel after a transfer, and
+    /// signal terminal count once the count runs out. Returns whether
+    /// it did.
+    fn dma_advance (&mut self, index: usize, size: u32) -> bool {
+        let channel = &mut self.dmau.channels[index];
+        channel.step_address(size);
+        let terminal = channel.count == 0;
+        channel.count = channel.count.wrapping_sub(1);
+        if terminal {
+            let bit = 1 << index;
+            if channel.mode & MODE_AUTOINITIALIZE != 0 {
+                channel.address = channel.base_address;
+                channel.count = channel.base_count;
+            } else {
+                self.dmau.mask |= bit;
+            }
+            self.dmau.status |= bit;
+            self.dmau.requests &= !bit;
+            self.bus.dma_terminal_count(index as u8);
+        }
+        terminal
+    }
+
+}
@@ -8,18 +8,18 @@ impl CPU {
         for row in start..=end {
             print!("\n{}{:6X}|", if row == self.ps {">"} else {" "}, row);
             for col in 0..0x10 {
-                print!(" {:02x}", self.memory[row as usize * 0x10 + col]);
+                print!(" {:02x}", self.peek_byte(row as u32 * 0x10 + col));
             }
         }
     }
 
     pub fn dump_state (&self, pc: u16) {
-        print!("                  AW   BW   CW   DW   DS0  DS1  BP   IX   IY   SS   SP   PS   PC   \n");
-        print!("                  {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X}:{:04X} {:04X}:{:04X}\n",
+        println!("                  AW   BW   CW   DW   DS0  DS1  BP   IX   IY   SS   SP   PS   PC   ");
+        println!("                  {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X} {:04X}:{:04X} {:04X}:{:04X}",
             self.aw(), self.bw(), self.cw(), self.dw(),
             self.ds0(), self.ds1(), self.bp(), self.ix(), self.iy(),
             self.ss(), self.sp(), self.ps(), pc);
-        print!("                  PSW={:04X} {} {} {} {} {} {} {} {} {}\n",
+        println!("                  PSW={:04X} {} {} {} {} {} {} {} {} {}",
             self.psw(),
             if self.v()   { "V  " } else { "   " },
             if self.dir() { "DIR" } else { "   " },
@@ -32,14 +32,12 @@ impl CPU {
             if self.cy()  { "CY " } else { "   " },);
     }
 
-    pub fn dump_instruction (&self, addr: u32, name: &str, bytes: &[u8]) {
-        print!("\n{:10} {addr:05X}  {name:15}  {:02X?}",
-            self.clock,
-            &bytes);
+    pub fn dump_instruction (&self, addr: u32, instruction: &Instruction, bytes: &[u8]) {
+        print!("\n{:10} {addr:05X}  {instruction:15}  {bytes:02X?}", self.clock);
     }
 
     pub fn dump_stack (&self, rows: usize) {
-        self.dump_segment(self.ss().saturating_sub(1), self.sp(), 4)
+        self.dump_segment(self.ss().saturating_sub(1), self.sp(), rows as u16)
     }
 
     pub fn dump_segment (&self, segment: u16, offset: u16, count: u16) {
@@ -47,7 +45,7 @@ impl CPU {
             let start = (((segment as usize * 0x10) + offset as usize) / 0x10 + row as usize) * 0x10;
             print!("\n{:6X}|", start);
             for col in 0..0x10 {
-                print!(" {:02x}", self.memory()[start + col]);
+                print!(" {:02x}", self.peek_byte((start + col) as u32));
             }
         }
     }
@@ -57,7 +55,7 @@ impl CPU {
             let offset = start + i as usize * per_row as usize;
             print!("\n{:6X}|", offset);
             for j in 0..per_row {
-                print!(" {:02x}", self.memory()[offset as usize + j as usize]);
+                print!(" {:02x}", self.peek_byte((offset + j as usize) as u32));
             }
         }
     }
@@ -0,0 +1,58 @@
+use crate::*;
+use std::fmt::{Display, Formatter, Result};
+
+/// Error creating a [CPU].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The memory image doesn't fit in memory
+    ImageTooBig { size: usize, capacity: usize },
+}
+
+impl Display for Error {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::ImageTooBig { size, capacity } =>
+                write!(f, "memory image too big (0x{size:X}/0x{capacity:X} bytes)"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Reason why [CPU::step] didn't complete an instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// Undefined opcode, or undefined form of an opcode, while the invalid
+    /// opcode trap is disabled. PC is left pointing at the instruction.
+    Undefined(u8),
+    /// Undefined opcode of the 0FH map, by its second byte, while the
+    /// invalid opcode trap is disabled. PC is left pointing at the 0FH.
+    UndefinedExtended(u8),
+    /// Instruction that isn't emulated, such as a coprocessor operation.
+    /// PC is left pointing at the instruction.
+    Unimplemented(Instruction),
+    /// The instruction accessed memory that the bus doesn't map, at this
+    /// address. The instruction has completed, reading whatever the bus
+    /// returned.
+    Fault(u32),
+    /// HALT has suspended execution until an interrupt is accepted.
+    Halted,
+    /// A breakpoint was reached at this address. The instruction there
+    /// executes on the next step.
+    Breakpoint(u32),
+}
+
+impl Display for Stop {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::Undefined(op)              => write!(f, "undefined opcode {op:02X}"),
+            Self::UndefinedExtended(op)      => write!(f, "undefined opcode 0F {op:02X}"),
+            Self::Unimplemented(instruction) => write!(f, "unimplemented instruction {instruction}"),
+            Self::Fault(addr)                => write!(f, "unmapped memory access at {addr:05X}"),
+            Self::Halted                     => write!(f, "halted"),
+            Self::Breakpoint(addr)           => write!(f, "breakpoint at {addr:05X}"),
+        }
+    }
+}
+
+impl std::error::Error for Stop {}
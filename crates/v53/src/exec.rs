@@ -0,0 +1,459 @@
+use crate::*;
+
+impl CPU {
+
+    /// Execute a decoded instruction and return its cycle count, for aligned
+    /// operands without wait states and with a full prefetch queue.
+    /// PC must already point past the instruction. Undefined and
+    /// unimplemented instructions return without side effects.
+    pub fn execute (&mut self, instruction: Instruction) -> Result<u64, Stop> {
+        use Instruction::*;
+        Ok(match instruction {
+
+            Segment(segment) => {
+                self.segment = Some(segment);
+                2
+            },
+            Repeat { prefix, segment, op } => self.repeat(prefix, segment, op),
+            Prefix(_) => 2,
+
+            Alu { code, word, to_reg, reg, rm } => {
+                let cost = if to_reg || code == ALU_CMP {
+                    self.operand_cost(rm, 2, 6)
+                } else {
+                    self.operand_cost(rm, 2, 7)
+                };
+                let reg_value = self.get_register(word, reg);
+                let rm_value  = self.read_operand(word, rm);
+                if to_reg {
+                    let result = self.alu(code, word, reg_value, rm_value);
+                    if code != ALU_CMP {
+                        self.set_register(word, reg, result);
+                    }
+                } else {
+                    let result = self.alu(code, word, rm_value, reg_value);
+                    if code != ALU_CMP {
+                        self.write_operand(word, rm, result);
+                    }
+                }
+                cost
+            },
+            AluAcc { code, word, imm } => {
+                let result = self.alu(code, word, self.get_register(word, 0b000), imm);
+                if code != ALU_CMP {
+                    self.set_register(word, 0b000, result);
+                }
+                2
+            },
+            AluImm { code, word, rm, imm } => {
+                let cost = if code == ALU_CMP {
+                    self.operand_cost(rm, 2, 6)
+                } else {
+                    self.operand_cost(rm, 2, 7)
+                };
+                let value  = self.read_operand(word, rm);
+                let result = self.alu(code, word, value, imm);
+                if code != ALU_CMP {
+                    self.write_operand(word, rm, result);
+                }
+                cost
+            },
+            Test { word, reg, rm } => {
+                let cost  = self.operand_cost(rm, 2, 6);
+                let value = self.read_operand(word, rm);
+                self.alu(ALU_AND, word, value, self.get_register(word, reg));
+                cost
+            },
+            TestAcc { word, imm } => {
+                self.alu(ALU_AND, word, self.get_register(word, 0b000), imm);
+                2
+            },
+            IncDec { dec, word, rm } => {
+                let cost   = self.operand_cost(rm, 2, 7);
+                let value  = self.read_operand(word, rm);
+                let result = self.inc_dec(word, value, dec);
+                self.write_operand(word, rm, result);
+                cost
+            },
+            Shift { code, word, rm, count } => {
+                let bits = self.count(count);
+                // Shifts by 1 have a fixed cost, others take one extra cycle per bit
+                let per_bit = if count == Count::One { 0 } else { bits as u64 };
+                let cost   = self.operand_cost(rm, 2, 7) + per_bit;
+                let value  = self.read_operand(word, rm);
+                let result = self.shift(code, word, value, bits);
+                self.write_operand(word, rm, result);
+                cost
+            },
+            Group1 { code, word, rm, imm } => self.group1(code, word, rm, imm),
+            MulImm { reg, rm, imm } => {
+                let cost   = self.operand_cost(rm, 16, 20);
+                let src    = self.read_operand(true, rm) as i16 as i32;
+                let result = src * imm as i16 as i32;
+                let fits   = result == result as i16 as i32;
+                self.set_register_u16(reg, result as u16);
+                self.set_cy(!fits);
+                self.set_v(!fits);
+                cost
+            },
+            Adjust(op) => match op {
+                0x27 => { self.adj4a(); 3 },
+                0x2F => { self.adj4s(); 3 },
+                0x37 => { self.adjba(); 7 },
+                0x3F => { self.adjbs(); 7 },
+                _ => unreachable!(),
+            },
+            Cvtbd => {
+                self.cvtbd();
+                15
+            },
+            Cvtdb => {
+                self.cvtdb();
+                7
+            },
+            Cvtbw => {
+                self.set_ah(if self.al() & B7 > 0 { 0xFF } else { 0x00 });
+                2
+            },
+            Cvtwl => {
+                self.set_dw(if self.aw() & W15 > 0 { 0xFFFF } else { 0x0000 });
+                4
+            },
+            BcdString(arg) => {
+                let (sub, store) = match arg {
+                    0x20 => (false, true),
+                    0x22 => (true, true),
+                    0x26 => (true, false),
+                    _ => unreachable!(),
+                };
+                7 + 19 * self.bcd_string(sub, store) as u64
+            },
+            RotateNibble { left, rm } => {
+                let cost   = self.operand_cost(rm, 13, 21);
+                let value  = self.read_operand(false, rm) as u8;
+                let result = if left { self.rol4(value) } else { self.ror4(value) };
+                self.write_operand(false, rm, result as u16);
+                cost
+            },
+            Bit { code, word, rm, bit } => {
+                let cost = if code == 0b00 {
+                    self.operand_cost(rm, 3, 8)
+                } else {
+                    self.operand_cost(rm, 5, 14)
+                };
+                let extra  = matches!(bit, Count::Immediate(_)) as u64;
+                let bit    = self.count(bit);
+                let value  = self.read_operand(word, rm);
+                let result = self.bit_op(code, word, value, bit);
+                if code != 0b00 {
+                    self.write_operand(word, rm, result);
+                }
+                cost + extra
+            },
+            BitField { insert, offset, length } => {
+                let length = self.count(length);
+                let start  = self.get_register_u8(offset);
+                let next = if insert {
+                    self.insert_bit_field(start, length)
+                } else {
+                    self.extract_bit_field(start, length)
+                };
+                self.set_register_u8(offset, next);
+                if insert { 31 } else { 26 }
+            },
+            String(op) => self.string_op(op),
+
+            Mov { word, to_reg, reg, rm } => {
+                if to_reg {
+                    let cost = self.operand_cost(rm, 2, 5);
+                    let value = self.read_operand(word, rm);
+                    self.set_register(word, reg, value);
+                    cost
+                } else {
+                    let cost = self.operand_cost(rm, 2, 3);
+                    self.write_operand(word, rm, self.get_register(word, reg));
+                    cost
+                }
+            },
+            MovSegment { to_segment, sreg, rm } => {
+                if to_segment {
+                    let cost = self.operand_cost(rm, 2, 5);
+                    let value = self.read_operand(true, rm);
+                    self.set_segment_register(sreg, value);
+                    cost
+                } else {
+                    let cost = self.operand_cost(rm, 2, 3);
+                    self.write_operand(true, rm, self.get_segment_register(sreg));
+                    cost
+                }
+            },
+            MovAcc { word, to_acc, addr } => {
+                if to_acc {
+                    let value = if word { self.read_u16(addr) } else { self.read_u8(addr) as u16 };
+                    self.set_register(word, 0b000, value);
+                    5
+                } else {
+                    let value = self.get_register(word, 0b000);
+                    if word { self.write_u16(addr, value) } else { self.write_u8(addr, value as u8) }
+                    3
+                }
+            },
+            MovImm { word, rm, imm } => {
+                let cost = self.operand_cost(rm, 2, 3);
+                self.write_operand(word, rm, imm);
+                cost
+            },
+            LoadPointer { segment, reg, rm } => {
+                let cost    = self.operand_cost(rm, 10, 10);
+                let (offset, base) = self.read_operand_pair(rm);
+                self.set_register_u16(reg, offset);
+                match segment {
+                    crate::Segment::DS1 => self.set_ds1(base),
+                    crate::Segment::DS0 => self.set_ds0(base),
+                    _ => unreachable!(),
+                }
+                cost
+            },
+            Ldea { reg, rm } => {
+                let (_, addr) = self.operand_address(rm).unwrap();
+                self.set_register_u16(reg, addr);
+                2
+            },
+            Xch { word, reg, rm } => {
+                let cost  = self.operand_cost(rm, 3, 8);
+                let value = self.read_operand(word, rm);
+                self.write_operand(word, rm, self.get_register(word, reg));
+                self.set_register(word, reg, value);
+                cost
+            },
+            Nop => 1,
+            Push(rm) => {
+                let cost  = self.operand_cost(rm, 3, 9);
+                let value = self.read_operand(true, rm);
+                self.push_u16(value);
+                cost
+            },
+            Pop(rm) => {
+                let value = self.pop_u16();
+                self.write_operand(true, rm, value);
+                5
+            },
+            PushSegment(sreg) => {
+                self.push_u16(self.get_segment_register(sreg));
+                3
+            },
+            PopSegment(sreg) => {
+                let value = self.pop_u16();
+                self.set_segment_register(sreg, value);
+                5
+            },
+            PushImm(imm) => {
+                self.push_u16(imm);
+                7
+            },
+            PushPsw => {
+                self.push_u16(self.psw());
+                5
+            },
+            PopPsw => {
+                self.pop_psw();
+                5
+            },
+            PushRegisters => {
+                self.push_registers();
+                35
+            },
+            PopRegisters => {
+                self.pop_registers();
+                43
+            },
+            MovPswAh => {
+                // Only S, Z, AC, P and CY are loaded
+                let flags = (self.ah() & 0b11010101) as u16 | W1;
+                self.set_psw((self.psw() & 0xFF00) | flags);
+                2
+            },
+            MovAhPsw => {
+                self.set_ah((self.psw() as u8 & 0b11010101) | B1);
+                2
+            },
+            Trans => {
+                self.translate();
+                9
+            },
+            In { word, port } => {
+                let port = port.map_or(self.dw(), u16::from);
+                let value = if word {
+                    self.input_u16(port)
+                } else {
+                    self.input_u8(port) as u16
+                };
+                self.set_register(word, 0b000, value);
+                if word { 7 } else { 5 }
+            },
+            Out { word, port } => {
+                let port = port.map_or(self.dw(), u16::from);
+                if word {
+                    self.output_u16(port, self.aw());
+                    5
+                } else {
+                    self.output_u8(port, self.al());
+                    3
+                }
+            },
+
+            Branch { condition, disp } => {
+                if self.condition(condition) {
+                    self.jump_i8(disp);
+                    6
+                } else {
+                    3
+                }
+            },
+            Loop { code, disp } => {
+                let taken = if code == 0b11 {
+                    self.cw() == 0
+                } else {
+                    self.set_cw(self.cw().wrapping_sub(1));
+                    self.cw() != 0 && match code {
+                        0b00 => !self.z(),
+                        0b01 => self.z(),
+                        _    => true,
+                    }
+                };
+                if taken {
+                    self.jump_i8(disp);
+                    6
+                } else {
+                    3
+                }
+            },
+            BranchRelative(disp) => {
+                self.jump_i16(disp);
+                7
+            },
+            CallRelative(disp) => {
+                self.push_u16(self.pc());
+                self.jump_i16(disp);
+                7
+            },
+            BranchFar { segment, offset } => {
+                self.set_ps(segment);
+                self.set_pc(offset);
+                7
+            },
+            CallFar { segment, offset } => {
+                self.call_far(segment, offset);
+                15
+            },
+            BranchIndirect(rm) => {
+                let cost = self.operand_cost(rm, 7, 11);
+                let pc = self.read_operand(true, rm);
+                self.set_pc(pc);
+                cost
+            },
+            CallIndirect(rm) => {
+                let cost = self.operand_cost(rm, 10, 15);
+                let pc = self.read_operand(true, rm);
+                self.push_u16(self.pc());
+                self.set_pc(pc);
+                cost
+            },
+            BranchFarIndirect(rm) | CallFarIndirect(rm) => {
+                let call    = matches!(instruction, CallFarIndirect(_));
+                let cost    = self.operand_cost(rm, 15, 15);
+                let (offset, segment) = self.read_operand_pair(rm);
+                if call {
+                    self.call_far(segment, offset);
+                } else {
+                    self.set_ps(segment);
+                    self.set_pc(offset);
+                }
+                cost
+            },
+            Ret { far, pop } => {
+                self.ret(far, pop.unwrap_or(0));
+                match (far, pop.is_some()) {
+                    (false, false) => 10,
+                    (false, true)  => 12,
+                    (true,  _)     => 15,
+                }
+            },
+            Reti => {
+                let pc = self.pop_u16();
+                let ps = self.pop_u16();
+                self.set_pc(pc);
+                self.set_ps(ps);
+                self.pop_psw();
+                13
+            },
+            Brk(vector) => self.interrupt(vector),
+            Brkv => if self.v() { 2 + self.interrupt(VECTOR_OVERFLOW) } else { 3 },
+            Halt => {
+                self.halt();
+                2
+            },
+            // No coprocessor is attached
+            Poll => 2,
+            Prepare { size, level } => self.prepare(size, level),
+            Dispose => {
+                self.dispose();
+                6
+            },
+            Chkind { reg, rm } => {
+                let (lower, upper) = self.read_operand_pair(rm);
+                let (lower, upper) = (lower as i16, upper as i16);
+                let index = self.get_register_u16(reg) as i16;
+                if index < lower || index > upper {
+                    20 + self.interrupt(VECTOR_ARRAY_BOUNDS)
+                } else {
+                    18
+                }
+            },
+            Brkxa(vector) => {
+                self.branch_xa(vector, true);
+                12
+            },
+            Retxa(vector) => {
+                self.branch_xa(vector, false);
+                12
+            },
+
+            NotCy => { self.set_cy(!self.cy()); 2 },
+            ClrCy => { self.set_cy(false); 2 },
+            SetCy => { self.set_cy(true); 2 },
+            Di => { self.set_ie(false); 2 },
+            Ei => { self.set_ie(true); 2 },
+            ClrDir => { self.set_dir(false); 2 },
+            SetDir => { self.set_dir(true); 2 },
+
+            Coprocessor { .. } => return Err(Stop::Unimplemented(instruction)),
+            Undefined(op) => return Err(Stop::Undefined(op)),
+            UndefinedExtended(op) => return Err(Stop::UndefinedExtended(op)),
+        })
+    }
+
+    /// Cycle count of an instruction with a register/memory operand:
+    /// `reg` for a register, `mem` for memory. Wait states and odd addresses
+    /// are accounted by the memory accesses themselves.
+    fn operand_cost (&self, rm: Operand, reg: u64, mem: u64) -> u64 {
+        if self.operand_address(rm).is_some() { mem } else { reg }
+    }
+
+    /// Pop the PSW. Bits 15-12 and 1 always read as 1, bits 5 and 3 as 0.
+    fn pop_psw (&mut self) {
+        let value = self.pop_u16();
+        self.set_psw((value & 0b0000111111010101) | W15 | W14 | W13 | W12 | W1);
+    }
+
+    /// Value of a shift count, bit number or bit field length.
+    fn count (&self, count: Count) -> u8 {
+        match count {
+            Count::One => 1,
+            Count::CL => self.cl(),
+            Count::Register(reg) => self.get_register_u8(reg),
+            Count::Immediate(imm) => imm,
+        }
+    }
+
+}
@@ -9,9 +9,9 @@ macro_rules! define_flag {
             }
             pub fn $setter (&mut self, value: bool) {
                 if value {
-                    self.psw = self.psw | (1 << $bit)
+                    self.psw |= 1 << $bit
                 } else {
-                    self.psw = self.psw & (!(1 << $bit))
+                    self.psw &= !(1 << $bit)
                 }
             }
         }
@@ -74,6 +74,16 @@ impl CPU {
         self.set_z(result == 0);
         self.set_s(result >> 15 == 1);
     }
+    /// Set parity, zero, and sign flags from byte result.
+    pub fn set_pzs_u8 (&mut self, result: u8) {
+        self.set_p(determine_parity(result));
+        self.set_z(result == 0);
+        self.set_s(result >> 7 == 1);
+    }
+    /// Set parity, zero, and sign flags from byte or word result.
+    pub fn set_pzs_sized (&mut self, result: u16, word: bool) {
+        if word { self.set_pzs(result) } else { self.set_pzs_u8(result as u8) }
+    }
     /// Set parity, zero, sign, carry, and overflow flags from word result.
     pub fn set_pzscyv (&mut self, result: u16, carry: bool, overflow: bool) {
         self.set_p(determine_parity(result as u8));
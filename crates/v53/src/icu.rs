@@ -0,0 +1,293 @@
+//! Interrupt control unit: a uPD71059, compatible with the 8259A in 8086
+//! mode. It prioritizes eight interrupt request inputs (IR0-IR7) and
+//! supplies the vector of the one the CPU accepts.
+
+use crate::*;
+
+/// How the CPU gets the vector of an acknowledged interrupt.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Acknowledge {
+    /// The ICU supplies this vector
+    Vector(u8),
+    /// The slave controller cascaded on this input supplies the vector
+    Cascade(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Icu {
+    /// Levels of the inputs
+    lines: u8,
+    /// Interrupt request register: inputs waiting for service
+    irr: u8,
+    /// In-service register: inputs being serviced
+    isr: u8,
+    /// Interrupt mask register, set by OCW1
+    imr: u8,
+    /// Next initialization word expected: 2 to 4 while initializing,
+    /// 0 once initialized
+    init: u8,
+    /// Whether the ICU has been initialized since reset
+    ready: bool,
+    /// ICW1: inputs are level-triggered instead of edge-triggered
+    level: bool,
+    /// ICW1: no slaves, so ICW3 is skipped
+    single: bool,
+    /// ICW1: ICW4 follows
+    icw4: bool,
+    /// ICW2: upper 5 bits of the vectors
+    base: u8,
+    /// ICW3: inputs with a slave controller cascaded on them
+    slaves: u8,
+    /// ICW4: in-service bits are cleared on acknowledge
+    auto_eoi: bool,
+    /// ICW4: special fully nested mode, in which a slave can interrupt
+    /// while another of its inputs is in service
+    nested: bool,
+    /// OCW2: priorities rotate on automatic end of interrupt
+    rotate_auto_eoi: bool,
+    /// OCW2: input with the lowest priority
+    lowest: u8,
+    /// OCW3: special mask mode, in which masked in-service inputs don't
+    /// hold off lower priorities
+    special_mask: bool,
+    /// OCW3: register 0 reads the in-service instead of the request register
+    read_isr: bool,
+    /// OCW3: the next read of register 0 polls
+    poll: bool,
+}
+
+impl Default for Icu {
+    fn default () -> Self {
+        Self {
+            lines: 0x00, irr: 0x00, isr: 0x00, imr: 0x00,
+            init: 0, ready: false, level: false, single: true, icw4: false,
+            base: 0x00, slaves: 0x00, auto_eoi: false, nested: false,
+            rotate_auto_eoi: false, lowest: 7, special_mask: false,
+            read_isr: false, poll: false,
+        }
+    }
+}
+
+impl Icu {
+
+    /// Drive an input high or low. In edge-triggered mode a rising edge
+    /// requests an interrupt; in either mode, a low input withdraws it.
+    pub fn set_line (&mut self, line: u8, high: bool) {
+        let mask = 1 << (line & 7);
+        if high {
+            if self.level || self.lines & mask == 0 {
+                self.irr |= mask;
+            }
+            self.lines |= mask;
+        } else {
+            self.lines &= !mask;
+            self.irr &= !mask;
+        }
+    }
+
+    /// Inputs in order of decreasing priority.
+    fn priorities (&self) -> impl Iterator<Item = u8> {
+        let highest = (self.lowest + 1) & 7;
+        (0..8).map(move |i| (highest + i) & 7)
+    }
+
+    /// In-service input with the highest priority.
+    fn highest_in_service (&self) -> Option<u8> {
+        self.priorities().find(|level| self.isr & (1 << level) != 0)
+    }
+
+    /// Request that will be acknowledged next, if any.
+    fn pending (&self) -> Option<u8> {
+        if !self.ready || self.init != 0 {
+            return None
+        }
+        let requests = self.irr & !self.imr;
+        // In special mask mode, masked inputs in service don't hold off others
+        let isr = if self.special_mask { self.isr & !self.imr } else { self.isr };
+        for level in self.priorities() {
+            let mask = 1 << level;
+            if isr & mask != 0 {
+                // A slave may request again while in service
+                let again = self.nested && !self.single && self.slaves & mask != 0;
+                return (again && requests & mask != 0).then_some(level)
+            }
+            if requests & mask != 0 {
+                return Some(level)
+            }
+        }
+        None
+    }
+
+    /// Whether the INT output to the CPU is active.
+    pub fn interrupt (&self) -> bool {
+        self.pending().is_some()
+    }
+
+    /// Interrupt acknowledge cycle: put the highest priority request in
+    /// service and tell where its vector comes from.
+    pub fn acknowledge (&mut self) -> Option<Acknowledge> {
+        let level = self.pending()?;
+        self.service(level);
+        Some(if !self.single && self.slaves & (1 << level) != 0 {
+            Acknowledge::Cascade(level)
+        } else {
+            Acknowledge::Vector(self.base | level)
+        })
+    }
+
+    fn service (&mut self, level: u8) {
+        let mask = 1 << level;
+        if !self.level {
+            self.irr &= !mask;
+        }
+        if !self.auto_eoi {
+            self.isr |= mask;
+        } else if self.rotate_auto_eoi {
+            self.lowest = level;
+        }
+    }
+
+    /// Read register 0 (request, in-service or poll) or 1 (mask).
+    pub fn read (&mut self, register: u8) -> u8 {
+        if register & 1 == 1 {
+            self.imr
+        } else if self.poll {
+            self.poll = false;
+            match self.pending() {
+                Some(level) => { self.service(level); 0x80 | level },
+                None => 0x00
+            }
+        } else if self.read_isr {
+            self.isr
+        } else {
+            self.irr
+        }
+    }
+
+    /// Write register 0 (ICW1, OCW2, OCW3) or 1 (ICW2-4, OCW1).
+    pub fn write (&mut self, register: u8, data: u8) {
+        if register & 1 == 0 {
+            if data & 0x10 != 0 {
+                self.icw1(data)
+            } else if data & 0x08 != 0 {
+                self.ocw3(data)
+            } else {
+                self.ocw2(data)
+            }
+        } else {
+            match self.init {
+                2 => {
+                    self.base = data & 0xF8;
+                    self.init = if !self.single { 3 } else if self.icw4 { 4 } else { 0 };
+                },
+                3 => {
+                    self.slaves = data;
+                    self.init = if self.icw4 { 4 } else { 0 };
+                },
+                4 => {
+                    self.auto_eoi = data & 0x02 != 0;
+                    self.nested = data & 0x10 != 0;
+                    self.init = 0;
+                },
+                _ => {
+                    self.imr = data;
+                    return
+                }
+            }
+            self.ready |= self.init == 0;
+        }
+    }
+
+    /// Start initialization, resetting the mask, the in-service
+    /// register, the edge detectors and the priorities.
+    fn icw1 (&mut self, data: u8) {
+        *self = Self {
+            lines: self.lines,
+            init: 2,
+            level: data & 0x08 != 0,
+            single: data & 0x02 != 0,
+            icw4: data & 0x01 != 0,
+            ..Self::default()
+        };
+        // Inputs already high only request when level-triggered
+        if self.level {
+            self.irr = self.lines;
+        }
+    }
+
+    fn ocw2 (&mut self, data: u8) {
+        let level = data & 7;
+        match data >> 5 {
+            // Non-specific EOI
+            0b001 => if let Some(level) = self.highest_in_service() {
+                self.isr &= !(1 << level);
+            },
+            // Specific EOI
+            0b011 => self.isr &= !(1 << level),
+            // Rotate on non-specific EOI
+            0b101 => if let Some(level) = self.highest_in_service() {
+                self.isr &= !(1 << level);
+                self.lowest = level;
+            },
+            // Set or clear rotate on automatic EOI
+            0b100 => self.rotate_auto_eoi = true,
+            0b000 => self.rotate_auto_eoi = false,
+            // Rotate on specific EOI
+            0b111 => {
+                self.isr &= !(1 << level);
+                self.lowest = level;
+            },
+            // Set priority
+            0b110 => self.lowest = level,
+            _ => {}
+        }
+    }
+
+    fn ocw3 (&mut self, data: u8) {
+        if data & 0x40 != 0 {
+            self.special_mask = data & 0x20 != 0;
+        }
+        if data & 0x02 != 0 {
+            self.read_isr = data & 0x01 != 0;
+        }
+        self.poll = data & 0x04 != 0;
+    }
+
+    pub(crate) fn save_state (&self, state: &mut StateWriter) {
+        for value in [self.lines, self.irr, self.isr, self.imr, self.init, self.base, self.slaves, self.lowest] {
+            state.u8(value);
+        }
+        for value in [
+            self.ready, self.level, self.single, self.icw4, self.auto_eoi, self.nested,
+            self.rotate_auto_eoi, self.special_mask, self.read_isr, self.poll
+        ] {
+            state.bool(value);
+        }
+    }
+
+    pub(crate) fn load_state (state: &mut StateReader) -> Result<Self, StateError> {
+        let mut icu = Self {
+            lines:  state.u8()?,
+            irr:    state.u8()?,
+            isr:    state.u8()?,
+            imr:    state.u8()?,
+            init:   state.u8()?,
+            base:   state.u8()?,
+            slaves: state.u8()?,
+            lowest: state.u8()?,
+            ..Self::default()
+        };
+        for flag in [
+            &mut icu.ready, &mut icu.level, &mut icu.single, &mut icu.icw4, &mut icu.auto_eoi,
+            &mut icu.nested, &mut icu.rotate_auto_eoi, &mut icu.special_mask, &mut icu.read_isr,
+            &mut icu.poll
+        ] {
+            *flag = state.bool()?;
+        }
+        if icu.init > 4 || icu.lowest > 7 {
+            return Err(StateError::Format)
+        }
+        Ok(icu)
+    }
+
+}
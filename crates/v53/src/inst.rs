@@ -1,2258 +1,494 @@
 use crate::*;
 
-pub fn v53_instruction (cpu: &mut CPU, op: u8) -> (
-    String,
-    Vec<u8>,
-    Box<dyn Fn(&mut CPU)->u64>
-) {
-    match op {
-
-        0x00 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg_dst = reg;
-                let reg_src = mem;
-                (
-                    format!("ADD {}, {}", register_name_u8(reg_dst), register_name_u8(reg_src)),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU| {
-                        let src = cpu.get_register_u8(reg_src);
-                        let dst = cpu.get_register_u8(reg_dst);
-                        let (result, carry) = dst.overflowing_add(src);
-                        let (_, overflow) = (dst as i8).overflowing_add(src as i8);
-                        cpu.set_register_u8(reg_dst, result);
-                        cpu.set_pzscyv(result as u16, carry, overflow);
-                        2
-                    })
-                )
-            } else {
-                (
-                    format!("ADD mem, {}", register_name_u8(reg)),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU| {
-                        let src  = cpu.get_register_u8(reg);
-                        let addr = cpu.memory_address(mode, mem);
-                        let dst  = cpu.read_u8(addr);
-                        let (result, carry) = dst.overflowing_add(src);
-                        let (_, overflow) = (dst as i8).overflowing_add(src as i8);
-                        cpu.write_u8(addr, result);
-                        cpu.set_pzscyv(result as u16, carry, overflow);
-                        if addr % 2 == 0 { 7 } else { 11 }
-                    })
-                )
-            }
-        },
-
-        0x01 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            (format!("ADDW mem, reg"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                let src  = cpu.get_register_u16(reg);
-                let addr = cpu.memory_address(mode, mem);
-                let dst  = cpu.read_u16(addr);
-                let (result, carry) = dst.overflowing_add(src);
-                let (_, overflow) = (dst as i16).overflowing_add(src as i16);
-                cpu.write_u16(addr, result);
-                cpu.set_pzscyv(result, carry, overflow);
-                if addr % 2 == 0 { 7 } else { 11 }
-            }))
-        },
-
-        0x02 => unimplemented!("ADD b, t, rm"),
-        0x03 => unimplemented!("ADD w, t, rm"),
-        0x04 => unimplemented!("ADD b, ia"),
-
-        0x05 => {
-            let word = cpu.next_u16();
-            let [lo, hi] = word.to_le_bytes();
-            (format!("ADD AW, {word}"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                let (result, carry) = cpu.aw().overflowing_add(word);
-                let (_, overflow) = (cpu.aw() as i16).overflowing_add(word as i16);
-                cpu.set_aw(result);
-                cpu.set_pzscyv(result, carry, overflow);
-                2
-            }))
-        },
-
-        0x06 => (format!("PUSH DS1"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.ds1();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x07 => (format!("POP DS1"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let value = cpu.pop_u16();
-            cpu.set_ds1(value);
-            if cpu.pc() % 2 == 1 { 7 } else { 5 }
-        })),
-
-        0x08 => unimplemented!("Byte bitwise OR to memory from register"),
-        0x09 => unimplemented!("Word bitwise OR to memory from register"),
-
-        0x0A => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                (format!("OR"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let src = cpu.get_register_u8(mem);
-                    let dst = cpu.get_register_u8(reg);
-                    let result = dst | src;
-                    cpu.set_register_u8(reg, result);
-                    cpu.set_pzs(result as u16);
-                    2
-                }))
-            } else {
-                (format!("OR"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    let src  = cpu.read_u8(addr);
-                    let dst  = cpu.get_register_u8(reg);
-                    let result = dst | src;
-                    cpu.set_register_u8(reg, result);
-                    cpu.set_pzs(result as u16);
-                    6
-                }))
-            }
-        },
-
-        0x0B => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                (format!("OR"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let result = cpu.get_register_u16(reg) | cpu.get_register_u16(mem);
-                    cpu.set_register_u16(reg, result);
-                    cpu.set_pzs(result);
-                    2
-                }))
-            } else {
-                (format!("OR"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    let src  = cpu.read_u16(addr);
-                    let dst  = cpu.get_register_u16(reg);
-                    let result = dst | src;
-                    cpu.set_register_u16(reg, result);
-                    cpu.set_pzs(result);
-                    if addr % 2 == 0 { 6 } else { 8 }
-                }))
-            }
-        },
-
-        0x0C => unimplemented!("Bitwise OR b ia"),
-        0x0D => unimplemented!("Bitwise OR w ia"),
-
-        0x0E => (format!("PUSH PS"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.ps();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-
-        0x0F => {
-            let arg = cpu.next_u8();
-            let addr = cpu.next_u8();
-            match arg {
-
-                0xE0 => (format!("BRKXA {addr:02X}"), vec![op, arg, addr], Box::new(move |cpu: &mut CPU|{
-                    let addr = addr as u32;//cpu.next_u8() as u32;
-                    //let addr = cpu.next_u8() as u32;
-                    //panic!("{addr} {:x?}", &cpu.memory[addr*4..addr*4+4]);
-                    cpu.dump_interrupt_vector_table();
-                    cpu.pc = u16::from_le_bytes([
-                        cpu.get_byte(addr * 4 + 0),
-                        cpu.get_byte(addr * 4 + 1),
-                    ]);
-                    cpu.ps = u16::from_le_bytes([
-                        cpu.get_byte(addr * 4 + 2),
-                        cpu.get_byte(addr * 4 + 3),
-                    ]);
-                    cpu.set_xa(true);
-                    println!("\n==========BRKXA {:04X} {:x} {:x} {:x}", addr*4, cpu.pc, cpu.ps, cpu.program_address());
-                    // TODO: set XA (internal I/O address: FF80H)
-                    12
-                })),
-
-                0xF0 => (format!("RETXA {addr:02X}"), vec![op, arg, addr], Box::new(move |cpu: &mut CPU|{
-                    let addr = addr as u32;
-                    //cpu.next_u8() as u32;
-                    cpu.pc = u16::from_le_bytes([
-                        cpu.get_byte(addr * 4 + 0),
-                        cpu.get_byte(addr * 4 + 1),
-                    ]);
-                    cpu.ps = u16::from_le_bytes([
-                        cpu.get_byte(addr * 4 + 2),
-                        cpu.get_byte(addr * 4 + 3),
-                    ]);
-                    cpu.set_xa(false);
-                    // TODO: reset XA
-                    12
-                })),
-
-                _ => unimplemented!("unimplemented Group 3 instruction {arg}")
-            }
-        },
-
-        0x10 => unimplemented!("ADDC"),
-        0x11 => unimplemented!("ADDC"),
-        0x12 => unimplemented!("ADDC"),
-        0x13 => unimplemented!("ADDC"),
-        0x14 => unimplemented!("ADDC"),
-        0x15 => unimplemented!("ADDC"),
-
-        0x16 => (format!("PUSH SS"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.ss();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x17 => (format!("POP SS"),  vec![op], Box::new(move |cpu: &mut CPU| {
-            let value = cpu.pop_u16();
-            cpu.set_ss(value);
-            if cpu.pc() % 2 == 1 { 7 } else { 5 }
-        })),
-
-        0x18 => unimplemented!("SUBC mem, reg"),
-        0x19 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            (format!("SUBW"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let addr = cpu.memory_address(mode, mem);
-                let dst  = cpu.read_u16(addr);
-                let src  = cpu.get_register_u16(reg);
-                let cy   = cpu.cy() as u16;
-                let (result, carry) = dst.overflowing_sub(src + cy);
-                let (_, overflow) = (dst as i16).overflowing_sub(src as i16 + cy as i16);
-                cpu.write_u16(addr, result);
-                cpu.set_pzscyv(result as u16, carry, overflow);
-                if addr % 2 == 1 { 11 } else { 7 }
-            }))
-        },
-        0x1A => unimplemented!("SUBC reg, mem"),
-        0x1B => unimplemented!("SUBCW reg, mem"),
-        0x1C => unimplemented!("SUBC acc, imm"),
-        0x1D => unimplemented!("SUBCW acc, imm"),
-
-        0x1E => (format!("PUSH DS0"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.ds0();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x1F => (format!("POP DS0"),  vec![op], Box::new(move |cpu: &mut CPU| {
-            let value = cpu.pop_u16();
-            cpu.set_ds0(value);
-            if cpu.pc() % 2 == 1 { 7 } else { 5 }
-        })),
-
-        0x20 => unimplemented!("AND mem, reg"),
-        0x21 => unimplemented!("ANDW mem, reg"),
-        0x22 => unimplemented!("AND reg, mem"),
-        0x23 => unimplemented!("ANDW reg, mem"),
-        0x24 => {
-            let byte = cpu.next_u8();
-            (format!("AND AL, {byte}"), vec![op, byte], Box::new(move |cpu: &mut CPU|{
-                let result = cpu.al() & byte;
-                cpu.set_al(result);
-                cpu.set_pzs(result as u16);
-                2
-            }))
-        },
-        0x25 => unimplemented!("ANDW acc, imm"),
-
-        0x26 => (format!("DS1:"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.segment = Some(Segment::DS1);
-            2
-        })),
-
-        0x27 => unimplemented!("ADJ4A"),
-
-        0x28 => unimplemented!("SUB b f rm"),
-
-        0x29 => unimplemented!("SUB w f rm"),
-
-        0x2A => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg1 = register_name_u8(reg);
-                let reg2 = register_name_u8(mem);
-                (format!("SUB {reg1}, {reg2}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let src = cpu.get_register_u8(mem);
-                    let dst = cpu.get_register_u8(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                    cpu.set_register_u8(reg, result);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    2
-                }))
-            } else {
-                let name = register_name_u8(reg);
-                (format!("SUB {name}, mem"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    let src  = cpu.read_u8(addr);
-                    let dst  = cpu.get_register_u8(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                    cpu.set_register_u8(reg, result);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    if addr % 2 == 0 { 6 } else { 8 }
-                }))
-            }
-        },
-
-        0x2B => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg1 = register_name_u16(reg);
-                let reg2 = register_name_u16(mem);
-                (format!("SUB {reg1}, {reg2}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let src = cpu.get_register_u16(mem);
-                    let dst = cpu.get_register_u16(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i16).overflowing_sub(src as i16);
-                    cpu.set_register_u16(reg, result);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    2
-                }))
-            } else {
-                let name = register_name_u16(reg);
-                (format!("SUB {name}, mem"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    let src  = cpu.read_u16(addr);
-                    let dst  = cpu.get_register_u16(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i16).overflowing_sub(src as i16);
-                    cpu.set_register_u16(reg, result);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    if addr % 2 == 0 { 6 } else { 8 }
-                }))
-            }
-        },
-
-        0x2C => unimplemented!("SUB b, ia"),
-        0x2D => unimplemented!("SUB w, ia"),
-
-        0x2E => (format!("PS:"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.segment = Some(Segment::PS);
-            2
-        })),
-
-        0x2F => unimplemented!("ADJ4S"),
-
-        0x30 => unimplemented!("XOR"),
-        0x31 => unimplemented!("XOR"),
-        0x32 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg_dst = reg;
-                let reg_src = mem;
-                (
-                    format!("XOR {}, {}", register_name_u8(reg_dst), register_name_u8(reg_src)),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU|{
-                        let src = cpu.get_register_u8(mem);
-                        let dst = cpu.get_register_u8(reg);
-                        let result = dst ^ src;
-                        cpu.set_register_u8(reg, result);
-                        cpu.set_pzs(result as u16);
-                        2
-                    })
-                )
-            } else {
-                (
-                    format!("XOR {}, mem", register_name_u8(reg)),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU|{
-                        let addr = cpu.memory_address(mode, mem);
-                        let src  = cpu.read_u8(addr);
-                        let dst = cpu.get_register_u8(reg);
-                        let result = dst ^ src;
-                        cpu.set_register_u8(reg, result);
-                        cpu.set_pzs(result as u16);
-                        if addr % 2 == 0 { 6 } else { 8 }
-                    })
-                )
-            }
-        },
-
-        0x33 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg_dst = reg;
-                let reg_src = mem;
-                (
-                    format!("XOR {}, {}", register_name_u16(reg_dst), register_name_u16(reg_src)),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU|{
-                        let result = cpu.get_register_u16(reg) ^ cpu.get_register_u16(mem);
-                        cpu.set_register_u16(reg, result);
-                        cpu.set_pzs(result);
-                        2
-                    })
-                )
-            } else {
-                (
-                    format!("XOR {}, mem", register_name_u16(reg)),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU|{
-                        let addr   = cpu.memory_address(mode, mem);
-                        let result = cpu.get_register_u16(reg) ^ cpu.read_u16(addr);
-                        cpu.set_register_u16(reg, result);
-                        cpu.set_pzs(result);
-                        if addr % 2 == 0 { 6 } else { 8 }
-                    })
-                )
-            }
-        },
-
-        0x34 => unimplemented!("XOR"),
-        0x35 => unimplemented!("XOR"),
-
-        0x36 => (format!("SS:"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.segment = Some(Segment::PS);
-            2
-        })),
-
-        0x37 => unimplemented!("ADJBA"),
-
-        0x38 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            (format!("CMP"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                if mode == 0b11 {
-                    let src = cpu.get_register_u8(reg);
-                    let dst = cpu.get_register_u8(mem);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    2
-                } else {
-                    let src  = cpu.get_register_u8(reg);
-                    let addr = cpu.memory_address(mode, mem);
-                    let dst  = cpu.read_u8(addr);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    if addr % 2 == 0 {
-                        6
-                    } else {
-                        8
-                    }
-                }
-            }))
-        },
-
-        0x39 => unimplemented!("Compare memory with word"),
-
-        0x3A => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg1 = register_name_u8(reg);
-                let reg2 = register_name_u8(mem);
-                (format!("CMP {reg1}, {reg2}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let src = cpu.get_register_u8(mem);
-                    let dst = cpu.get_register_u8(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    2
-                }))
-            } else {
-                let name = register_name_u8(reg);
-                (format!("CMP mem, {name}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    let src  = cpu.read_u8(addr);
-                    let dst  = cpu.get_register_u8(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    if addr % 2 == 0 {
-                        6
-                    } else {
-                        8
-                    }
-                }))
-            }
-        },
-
-        0x3B => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg1 = register_name_u16(reg);
-                let reg2 = register_name_u16(mem);
-                (format!("CMPW {reg1}, {reg2}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let src = cpu.get_register_u16(mem);
-                    let dst = cpu.get_register_u16(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i16).overflowing_sub(src as i16);
-                    cpu.set_pzscyv(result, carry, overflow);
-                    2
-                }))
-            } else {
-                let name = register_name_u16(reg);
-                (format!("CMPW mem, {name}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    let src  = cpu.read_u16(addr);
-                    let dst  = cpu.get_register_u16(reg);
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i16).overflowing_sub(src as i16);
-                    cpu.set_pzscyv(result, carry, overflow);
-                    if addr % 2 == 0 {
-                        6
-                    } else {
-                        8
-                    }
-                }))
-            }
-        },
-
-        0x3C => {
-            let byte = cpu.next_u8();
-            (format!("CMP w ia"), vec![op, byte], Box::new(move |cpu: &mut CPU|{
-                let (result, carry) = cpu.al().overflowing_sub(byte);
-                let (_, overflow) = (cpu.al() as i8).overflowing_sub(byte as i8);
-                cpu.set_pzscyv(result as u16, carry, overflow);
-                2
-            }))
-        },
-
-        0x3D => {
-            let word = cpu.next_u16();
-            let [lo, hi] = word.to_le_bytes();
-            (format!("CMP w ia"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                let (result, carry) = cpu.aw().overflowing_sub(word);
-                let (_, overflow) = (cpu.aw() as i16).overflowing_sub(word as i16);
-                cpu.set_pzscyv(result, carry, overflow);
-                2
-            }))
-        },
-
-        0x3E => (format!("DS0:"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.segment = Some(Segment::DS0);
-            2
-        })),
-
-        0x3F => unimplemented!("ADJBS"),
-
-        0x40 => (format!("INC AW"), vec![op], Box::new(inc_aw)),
-        0x41 => (format!("INC CW"), vec![op], Box::new(inc_cw)),
-        0x42 => (format!("INC DW"), vec![op], Box::new(inc_dw)),
-        0x43 => (format!("INC BW"), vec![op], Box::new(inc_bw)),
-
-        0x44 => (format!("INC SP"), vec![op], Box::new(inc_sp)),
-        0x45 => (format!("INC BP"), vec![op], Box::new(inc_bp)),
-        0x46 => (format!("INC IX"), vec![op], Box::new(inc_ix)),
-        0x47 => (format!("INC IY"), vec![op], Box::new(inc_iy)),
-
-        0x48 => (format!("DEC AW"), vec![op], Box::new(dec_aw)),
-        0x49 => (format!("DEC CW"), vec![op], Box::new(dec_cw)),
-        0x4A => (format!("DEC DW"), vec![op], Box::new(dec_dw)),
-        0x4B => (format!("DEC BW"), vec![op], Box::new(dec_bw)),
-
-        0x4C => (format!("DEC SP"), vec![op], Box::new(dec_sp)),
-        0x4D => (format!("DEC BP"), vec![op], Box::new(dec_bp)),
-        0x4E => (format!("DEC IX"), vec![op], Box::new(dec_ix)),
-        0x4F => (format!("DEC IY"), vec![op], Box::new(dec_iy)),
-
-        0x50 => (format!("PUSH AW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.aw();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x51 => (format!("PUSH CW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.cw();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x52 => (format!("PUSH DW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.dw();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x53 => (format!("PUSH BW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.bw();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-
-        0x54 => (format!("PUSH SP"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.sp();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x55 => (format!("PUSH BP"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.bp();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x56 => (format!("PUSH IX"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.ix();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-        0x57 => (format!("PUSH IY"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.iy();
-            cpu.push_u16(value);
-            if cpu.sp() % 2 == 0 { 5 } else { 3 }
-        })),
-
-        0x58 => (format!("POP AW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.pop_u16();
-            cpu.set_aw(value);
-            if cpu.sp() % 2 == 1 { 7 } else { 5 }
-        })),
-        0x59 => (format!("POP CW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.pop_u16();
-            cpu.set_cw(value);
-            if cpu.sp() % 2 == 1 { 7 } else { 5 }
-        })),
-        0x5A => (format!("POP DW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.pop_u16();
-            cpu.set_dw(value);
-            if cpu.sp() % 2 == 1 { 7 } else { 5 }
-        })),
-        0x5B => (format!("POP BW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.pop_u16();
-            cpu.set_bw(value);
-            if cpu.sp() % 2 == 1 { 7 } else { 5 }
-        })),
-
-        0x5C => (format!("POP SP"), vec![op], Box::new(move |cpu: &mut CPU|{
-            // preserve old stack position to determine cycle count
-            let addr = cpu.sp();
-            let value = cpu.pop_u16();
-            cpu.set_sp(value);
-            if addr % 2 == 1 { 7 } else { 5 }
-        })),
-        0x5D => (format!("POP BP"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.pop_u16();
-            cpu.set_bp(value);
-            if cpu.sp() % 2 == 1 { 7 } else { 5 }
-        })),
-        0x5E => (format!("POP IX"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.pop_u16();
-            cpu.set_ix(value);
-            if cpu.sp() % 2 == 1 { 7 } else { 5 }
-        })),
-        0x5F => (format!("POP IY"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let value = cpu.pop_u16();
-            cpu.set_iy(value);
-            if cpu.sp() % 2 == 1 { 7 } else { 5 }
-        })),
-
-        0x60 => unimplemented!("PUSH R"),
-        0x61 => unimplemented!("POP R"),
-        0x62 => unimplemented!("CHKIND"),
-        0x63 => unimplemented!("UNDEF"),
-        0x64 => unimplemented!("REPNC"),
-        0x65 => unimplemented!("REPC"),
-        0x66 => unimplemented!("FPO2"),
-        0x67 => unimplemented!("FPO2"),
-        0x68 => unimplemented!("PUSH"),
-        0x69 => unimplemented!("MUL"),
-        0x6A => unimplemented!("PUSH"),
-        0x6B => unimplemented!("MUL"),
-        0x6C => unimplemented!("INM"),
-        0x6D => unimplemented!("INM"),
-
-        0x6E => (format!("OUTM"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let data = cpu.read_u8(cpu.ix() as u32);
-            cpu.output_u8(cpu.dw(), data);
-            if cpu.dir() {
-                cpu.ix = cpu.ix - 1;
-            } else {
-                cpu.ix = cpu.ix + 1;
-            }
-            let rep = 1; // TODO
-            8 * rep - 2
-        })),
-
-        0x6F => (format!("OUTMW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let data = cpu.read_u16(cpu.ix() as u32);
-            cpu.output_u16(cpu.dw(), data);
-            if cpu.dir() {
-                cpu.ix = cpu.ix - 2;
-            } else {
-                cpu.ix = cpu.ix + 2;
-            }
-            let rep = 1; // TODO
-            if (cpu.dw % 2 == 1) && (cpu.ix % 2 == 1) {
-                14 * rep - 2
-            } else if cpu.dw % 2 == 1 {
-                12 * rep - 2
-            } else if cpu.ix % 2 == 1 {
-                10 * rep - 2
-            } else {
-                8 * rep - 2
-            }
-        })),
-
-        0x70 => {
-            let arg = cpu.next_i8();
-            (format!("BV {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU|{
-                if cpu.v() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x71 => {
-            let arg = cpu.next_i8();
-            (format!("BNV {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU|{
-                if !cpu.v() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x72 => {
-            let arg = cpu.next_i8();
-            (format!("BC {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU|{
-                if cpu.cy() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x73 => {
-            let arg = cpu.next_i8();
-            (format!("BNC {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                if !cpu.cy() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x74 => {
-            let arg = cpu.next_i8();
-            (format!("BE {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                if cpu.z() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x75 => {
-            let arg = cpu.next_i8();
-            (format!("BNE {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                if !cpu.z() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x76 => {
-            let arg = cpu.next_i8();
-            (format!("BNH {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                if cpu.z() || cpu.cy() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x77 => {
-            let arg = cpu.next_i8();
-            (format!("BNH {arg}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                if !(cpu.z() || cpu.cy()) { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x78 => {
-            let arg = cpu.next_i8();
-            (format!("BN {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if cpu.s() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x79 => {
-            let arg = cpu.next_i8();
-            (format!("BP {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if !cpu.s() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x7A => {
-            let arg = cpu.next_i8();
-            (format!("BPE {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if cpu.p() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x7B => {
-            let arg = cpu.next_i8();
-            (format!("BPE {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if !cpu.p() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x7C => {
-            let arg = cpu.next_i8();
-            (format!("BLT {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if cpu.s() ^ cpu.z() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x7D => {
-            let arg = cpu.next_i8();
-            (format!("BGE {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if !(cpu.s() ^ cpu.v()) { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x7E => {
-            let arg = cpu.next_i8();
-            (format!("BLT {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if cpu.z() || (cpu.s() ^ cpu.v()) { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x7F => {
-            let arg = cpu.next_i8();
-            (format!("BLT {arg}"), vec![op, arg as u8], Box::new(move|cpu: &mut CPU|{
-                if !(cpu.z() || (cpu.s() ^ cpu.v())) { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0x80 => { // TODO: ensure no sign extension
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, _) => unimplemented!("ADD"),
-                (0b001, _) => unimplemented!("OR"),
-                (0b010, _) => unimplemented!("ADDC"),
-                (0b011, _) => unimplemented!("SUB"),
-                (0b100, _) => unimplemented!("AND"),
-                (0b101, _) => unimplemented!("SUB"),
-                (0b110, _) => unimplemented!("XOR"),
-                (0b111, 0b11) => {
-                    let name = register_name_u8(mem);
-                    let src  = cpu.next_u8();
-                    (format!("CMP {name}, {src}"), vec![op, arg, src], Box::new(move|cpu: &mut CPU|{
-                        let dst = cpu.get_register_u8(mem);
-                        let (result, carry) = dst.overflowing_sub(src);
-                        let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                        cpu.set_pzscyv(result as u16, carry, overflow);
-                        2
-                    }))
-                },
-                (0b111, _) => (format!("CMP"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    let dst = cpu.read_u8(addr);
-                    let src = cpu.next_u8();
-                    let (result, carry) = dst.overflowing_sub(src);
-                    let (_, overflow) = (dst as i8).overflowing_sub(src as i8);
-                    cpu.set_pzscyv(result as u16, carry, overflow);
-                    if addr % 2 == 0 { 6 } else { 8 }
-                })),
-                _ => unreachable!()
-            }
-        },
-
-        0x81 => { // TODO: ensure no sign extension
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, 0b11) => {
-                    let src = cpu.next_u16() as i16;
-                    let [lo, hi] = src.to_le_bytes();
-                    (
-                        format!("ADDW {}, {src:04X}", register_name_u16(mem)),
-                        vec![op, arg, lo, hi],
-                        Box::new(move |cpu: &mut CPU|{
-                            let dst = cpu.get_register_u16(mem) as i16;
-                            let (result, carry) = (dst as u16).overflowing_add(src as u16);
-                            let (_, overflow) = dst.overflowing_add(src);
-                            cpu.set_register_u16(mem, result);
-                            cpu.set_pzscyv(result, carry, overflow);
-                            2
-                        })
-                    )
-                },
-                (0b001, _) => unimplemented!("ORW"),
-                (0b010, _) => unimplemented!("ADDCW"),
-                (0b011, _) => unimplemented!("SUBW"),
-                (0b100, _) => unimplemented!("ANDW"),
-                (0b101, _) => unimplemented!("SUBW"),
-                (0b110, _) => unimplemented!("XORW"),
-                (0b111, _) => unimplemented!("CMPW"),
-                _ => unreachable!()
-            }
-        },
-
-        0x82 => { // TODO: ensure sign extension
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, _) => unimplemented!("ADD"),
-                (0b001, _) => unimplemented!("OR"),
-                (0b010, _) => unimplemented!("ADDC"),
-                (0b011, _) => unimplemented!("SUB"),
-                (0b100, _) => unimplemented!("AND"),
-                (0b101, _) => unimplemented!("SUB"),
-                (0b110, _) => unimplemented!("XOR"),
-                (0b111, _) => unimplemented!("CMP"),
-                _ => unreachable!()
-            }
-        },
-
-        0x83 => { // TODO: ensure sign extension
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, 0b11) => {
-                    let src = cpu.next_u8() as i16;
-                    let [lo, hi] = src.to_le_bytes();
-                    (format!("ADDW {}, {src:04X}", register_name_u16(mem)), vec![op, arg, lo, hi], Box::new(move |cpu: &mut CPU|{
-                        let dst = cpu.get_register_u16(mem) as i16;
-                        let (result, carry) = (dst as u16).overflowing_add(src as u16);
-                        let (_, overflow) = dst.overflowing_add(src);
-                        cpu.set_register_u16(mem, result);
-                        cpu.set_pzscyv(result, carry, overflow);
-                        2
-                    }))
-                },
-                (0b000, _)    => {
-                    let src = cpu.next_u8() as i16;
-                    let [lo, hi] = src.to_le_bytes();
-                    (format!("ADDW {}, mem", register_name_u16(mem)), vec![op, arg, lo, hi], Box::new(move |cpu: &mut CPU|{
-                        let addr = cpu.memory_address(mode, mem);
-                        let dst = cpu.read_u16(addr);
-                        let (result, carry) = (dst as u16).overflowing_add(src as u16);
-                        let (_, overflow) = dst.overflowing_add(src as u16);
-                        cpu.set_register_u16(mem, result);
-                        cpu.set_pzscyv(result, carry, overflow);
-                        if addr % 2 == 0 { 6 } else { 8 }
-                    }))
-                },
-                (0b001, _) => (format!("ORW"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    unimplemented!()
-                })),
-                (0b010, _) => (format!("ADDCW"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    unimplemented!()
-                })),
-                (0b011, _) => (format!("SUBW"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    unimplemented!()
-                })),
-                (0b100, _) => (format!("ANDW"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    unimplemented!()
-                })),
-                (0b101, _) => (format!("SUBW"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    unimplemented!()
-                })),
-                (0b110, _) => (format!("XORW"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    unimplemented!()
-                })),
-                (0b111, 0b11) => {
-                    let src = cpu.next_u8();
-                    (format!("CMPW {}, {src:04X}", register_name_u16(mem)), vec![op, arg, src],
-                        Box::new(move |cpu: &mut CPU|{
-                            let dst = cpu.get_register_u16(mem) as i16;
-                            let (result, carry) = (dst as u16).overflowing_sub(src as u16);
-                            let (_, overflow) = dst.overflowing_sub(src as i16);
-                            cpu.set_pzscyv(result, carry, overflow);
-                            2
-                        }))
-                },
-                (0b111, _) => {
-                    let src = cpu.next_u8();
-                    (format!("CMPW {}, mem", register_name_u16(mem)), vec![op, arg, src],
-                        Box::new(move |cpu: &mut CPU|{
-                            let addr = cpu.memory_address(mode, mem);
-                            let dst = cpu.read_u16(addr);
-                            let (result, carry) = (dst as u16).overflowing_sub(src as u16);
-                            let (_, overflow) = dst.overflowing_sub(src as u16);
-                            cpu.set_pzscyv(result, carry, overflow);
-                            if addr % 2 == 0 { 6 } else { 8 }
-                        }))
-                },
-                _ => unreachable!()
-            }
-        },
-
-        0x84 => unimplemented!("TEST"),
-
-        0x85 => unimplemented!("TEST"),
-
-        0x86 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            (format!("XCH"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let get_register = cpu.get_register_u8(reg);
-                let addr           = cpu.memory_address(mode, mem);
-                let memory_value   = cpu.read_u8(addr);
-                cpu.set_register_u8(reg, memory_value);
-                cpu.write_u8(addr, get_register);
-                if addr % 2 == 1 { 12 } else { 8 }
-            }))
-        }
-
-        0x87 => unimplemented!("XCH"),
-
-        0x88 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            let name = register_name_u8(reg);
-            (format!("MOV mem, {name}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let addr = cpu.memory_address(mode, mem);
-                let val = cpu.get_register_u8(reg);
-                cpu.write_u8(addr, val);
-                if addr % 2 == 0 { 3 } else { 5 }
-            }))
-        },
-
-        0x89 => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            let name = register_name_u16(reg);
-            (format!("MOVW mem, {name}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let addr = cpu.memory_address(mode, mem);
-                let val = cpu.get_register_u16(reg);
-                cpu.write_u16(addr, val);
-                if addr % 2 == 0 { 3 } else { 5 }
-            }))
-        },
-
-        0x8A => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg1 = register_name_u8(reg);
-                let reg2 = register_name_u8(mem);
-                (format!("MOV {reg1}, {reg2}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    cpu.set_register_u8(reg, cpu.get_register_u8(mem));
-                    2
-                }))
-            } else {
-                let name = register_name_u16(reg);
-                (format!("MOV {name}, mem"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let address = cpu.memory_address(mode, mem);
-                    let value = cpu.read_u8(address);
-                    cpu.set_register_u8(reg, value);
-                    5
-                }))
-            }
-        },
-
-        0x8B => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            if mode == 0b11 {
-                let reg1 = register_name_u16(reg);
-                let reg2 = register_name_u16(mem);
-                (format!("MOVW {reg1}, {reg2}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    cpu.set_register_u16(reg, cpu.get_register_u16(mem));
-                    2
-                }))
-            } else {
-                let name = register_name_u16(reg);
-                (format!("MOVW {name}, mem"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let address = cpu.memory_address(mode, mem);
-                    let value = cpu.read_u16(address);
-                    cpu.set_register_u16(reg, value);
-                    if address % 2 == 1 { 7 } else { 5 }
-                }))
-            }
-        },
-
-        0x8C => {
-            let [arg, mode, sreg, mem] = get_mode_sreg_mem(cpu);
-            let name = segment_register_name(sreg);
-            (format!("MOV mem, {name}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let value = cpu.get_segment_register(sreg);
-                if mode == 0b11 {
-                    cpu.set_register_u16(mem, value);
-                    2
-                } else {
-                    let addr = cpu.memory_address(mode, mem);
-                    cpu.write_u16(addr, value);
-                    if addr % 2 == 0 { 3 } else { 5 }
-                }
-            }))
-        },
-
-        0x8D => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            let dst = register_name_u16(reg);
-            let (src, mut bytes, compute_address) = cpu.parse_effective_address(mode, mem);
-            bytes.insert(0, arg);
-            bytes.insert(0, op);
-            (format!("LDEA {dst}, {src}"), bytes, Box::new(move |cpu: &mut CPU|{
-                let address = compute_address(cpu);
-                cpu.set_register_u16(reg, address);
-                2
-            }))
-        },
-
-        0x8E => {
-            let [arg, mode, sreg, mem] = get_mode_sreg_mem(cpu);
-            let name = segment_register_name(sreg);
-            if mode == 0b11 {
-                let src = register_name_u16(mem);
-                (format!("MOVW {name}, {src}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let src = cpu.get_register_u16(mem);
-                    cpu.set_segment_register(sreg, src);
-                    2
-                }))
-            } else {
-                let (src, mut bytes, compute_address) = cpu.parse_effective_address(mode, mem);
-                bytes.insert(0, arg);
-                bytes.insert(0, op);
-                unimplemented!();
-            }
-        },
-
-        0x8F => {
-            let [arg, mode, reg, mem] = get_mode_reg_mem(cpu);
-            (format!("POP"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let addr = cpu.memory_address(mode, mem);
-                let data = cpu.pop_u16();
-                cpu.write_u16(addr, data);
-                if addr % 2 == 1 { 9 } else { 5 }
-            }))
-        },
-
-        0x90 => (format!("NOP"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let cw = cpu.cw();
-            let aw = cpu.aw();
-            cpu.set_cw(aw);
-            cpu.set_aw(cw);
-            1
-        })),
-
-        0x91 => (format!("XCH DW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let cw = cpu.cw();
-            let aw = cpu.aw();
-            cpu.set_cw(aw);
-            cpu.set_aw(cw);
-            3
-        })),
-
-        0x92 => (format!("XCH DW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let dw = cpu.dw();
-            let aw = cpu.aw();
-            cpu.set_dw(aw);
-            cpu.set_aw(dw);
-            3
-        })),
-
-        0x93 => (format!("XCH BW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let bw = cpu.bw();
-            let aw = cpu.aw();
-            cpu.set_bw(aw);
-            cpu.set_aw(bw);
-            3
-        })),
-
-        0x94 => (format!("XCH SP"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let sp = cpu.sp();
-            let aw = cpu.aw();
-            cpu.set_sp(aw);
-            cpu.set_aw(sp);
-            3
-        })),
-
-        0x95 => (format!("XCH BP"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let bp = cpu.bp();
-            let aw = cpu.aw();
-            cpu.set_bp(aw);
-            cpu.set_aw(bp);
-            3
-        })),
-
-        0x96 => (format!("XCH IX"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let ix = cpu.ix();
-            let aw = cpu.aw();
-            cpu.set_ix(aw);
-            cpu.set_aw(ix);
-            3
-        })),
-
-        0x97 => (format!("XCH IY"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let iy = cpu.iy();
-            let aw = cpu.aw();
-            cpu.set_iy(aw);
-            cpu.set_aw(iy);
-            3
-        })),
-
-        0x98 => (format!("CVTBW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let msb = cpu.al() & B7;
-            if msb > 0 {
-                cpu.set_ah(0b11111111)
-            } else {
-                cpu.set_ah(0b00000000)
-            }
-            2
-        })),
-
-        0x99 => unimplemented!("CVTBL"),
-        0x9A => unimplemented!("CALL"),
-        0x9B => (format!("POLL"), vec![op], Box::new(move |cpu: &mut CPU|{
-            // TODO: coprocessor
-            2
-        })),
-
-        0x9C => (format!("PUSH PSW"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let data = cpu.psw();
-            cpu.push_u16(data);
-            if cpu.sp() % 2 == 0 { 5 } else { 9 }
-        })),
-
-        0x9D => (format!("POP PSW"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let value = cpu.pop_u16();
-            cpu.set_psw(value);
-            if cpu.pc() % 2 == 1 { 7 } else { 5 }
-        })),
-
-        0x9E => (format!("MOV PSW, AH"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_psw(((cpu.ah() & 0b11010111) | 0b00000010) as u16);
-            2
-        })),
-
-        0x9F => (format!("MOV AH, PSW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_ah(((cpu.psw() & 0b11010111) | 0b00000010) as u8);
-            2
-        })),
-
-        0xA0 => {
-            let addr = cpu.next_u16();
-            let [lo, hi] = addr.to_le_bytes();
-            (format!("MOV AL, {addr:04X}"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                let value = cpu.read_u8(addr as u32);
-                cpu.set_al(value);
-                5
-            }))
-        },
-
-        0xA1 => {
-            let addr = cpu.next_u16();
-            let [lo, hi] = addr.to_le_bytes();
-            (format!("MOV AL, {addr:04X}"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                let value = cpu.read_u16(addr as u32);
-                cpu.set_aw(value);
-                if addr % 2 == 1 { 7 } else { 5 }
-            }))
-        },
-
-        0xA2 => {
-            let addr = cpu.next_u16();
-            let [lo, hi] = addr.to_le_bytes();
-            (format!("MOV {addr:04X}, AL"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                cpu.write_u8(addr as u32, cpu.al());
-                3
-            }))
-        },
-
-        0xA3 => {
-            let addr = cpu.next_u16();
-            let [lo, hi] = addr.to_le_bytes();
-            (format!("MOV {addr:04X}, AW"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                cpu.write_u16(addr as u32, cpu.aw());
-                if addr % 2 == 1 { 5 } else { 3 }
-            }))
-        },
-
-        0xA4 => unimplemented!("MOVBK b"),
-
-        0xA5 => (format!("MOVBKW"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let dst = cpu.ds1() as u32 * 0x10 + cpu.iy() as u32;
-            let src = cpu.effective_address(cpu.ix() as u32);
-            println!("{:04X} {src:04X}", cpu.cw());
-            cpu.set_byte(dst + 0, cpu.get_byte(src + 0));
-            cpu.set_byte(dst + 1, cpu.get_byte(src + 1));
-            if cpu.dir() {
-                cpu.set_ix(cpu.ix() - 2);
-                cpu.set_iy(cpu.iy() - 2);
-            } else {
-                cpu.set_ix(cpu.ix() + 2);
-                cpu.set_iy(cpu.iy() + 2);
-            }
-            if (dst % 2 == 0) && (src % 2 == 0) {
-                6
-            } else if (dst % 2 == 1) && (src % 2 == 1) {
-                10
-            } else {
-                8
-            }
-        })),
-
-        0xA6 => unimplemented!("CMPBK"),
-        0xA7 => unimplemented!("CMPBK"),
-        0xA8 => unimplemented!("TEST"),
-        0xA9 => unimplemented!("TEST"),
-
-        0xAA => (format!("STM"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let iy = cpu.iy();
-            let segment = cpu.segment;
-            cpu.segment = Some(Segment::DS1);
-            cpu.write_u8(iy as u32, cpu.al());
-            cpu.segment = segment;
-            cpu.set_iy(if cpu.dir() {
-                iy.overflowing_sub(1).0
-            } else {
-                iy.overflowing_add(1).0
-            });
-            if iy % 2 == 0 { 3 } else { 5 }
-        })),
-
-        0xAB => (format!("STMW"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let iy = cpu.iy();
-            let segment = cpu.segment;
-            cpu.segment = Some(Segment::DS1);
-            cpu.write_u16(iy as u32, cpu.aw());
-            cpu.segment = segment;
-            cpu.set_iy(if cpu.dir() {
-                iy.overflowing_sub(2).0
-            } else {
-                iy.overflowing_add(2).0
-            });
-            if iy % 2 == 0 { 3 } else { 5 }
-        })),
-
-        0xAC => (format!("LDM"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let data = cpu.read_u8(cpu.ix() as u32);
-            cpu.set_al(data);
-            if cpu.dir() {
-                cpu.ix = cpu.ix - 1;
-            } else {
-                cpu.ix = cpu.ix + 1;
-            }
-            5
-        })),
+/// Maximum length of an instruction, in bytes. A repeat prefix and a segment
+/// override decoded together with their string instruction count toward it.
+pub const MAX_INSTRUCTION_LENGTH: usize = 6;
+
+/// A decoded instruction. Register numbers are as encoded in the instruction
+/// (see [register_name]); `word` selects between byte and word operands;
+/// `code` is the operation selected by the opcode or by the reg field of the
+/// ModRM byte, indexing the name tables of the respective module.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// Segment override prefix (26H, 2EH, 36H, 3EH)
+    Segment(Segment),
+    /// Repeat prefix (F2H, F3H, 64H, 65H) and the string instruction it
+    /// repeats, optionally with a segment override in between
+    Repeat { prefix: u8, segment: Option<Segment>, op: u8 },
+    /// Prefix that doesn't affect the following instruction: a repeat prefix
+    /// before anything but a string instruction, or BUSLOCK (F0H)
+    Prefix(u8),
+
+    /// ADD, OR, ADDC, SUBC, AND, SUB, XOR or CMP between a register/memory
+    /// operand and a register (00H-3BH); `to_reg` if the register is the
+    /// destination
+    Alu { code: u8, word: bool, to_reg: bool, reg: u8, rm: Operand },
+    /// The same operations between the accumulator and an immediate
+    AluAcc { code: u8, word: bool, imm: u16 },
+    /// The same operations between a register/memory operand and an
+    /// immediate (80H-83H)
+    AluImm { code: u8, word: bool, rm: Operand, imm: u16 },
+    /// TEST register/memory operand, register (84H, 85H)
+    Test { word: bool, reg: u8, rm: Operand },
+    /// TEST accumulator, immediate (A8H, A9H)
+    TestAcc { word: bool, imm: u16 },
+    /// INC or DEC of a word register (40H-4FH) or register/memory operand
+    /// (FEH, FFH)
+    IncDec { dec: bool, word: bool, rm: Operand },
+    /// Shift or rotate (C0H, C1H, D0H-D3H)
+    Shift { code: u8, word: bool, rm: Operand, count: Count },
+    /// TEST rm, imm; NOT; NEG; MULU; MUL; DIVU or DIV (F6H, F7H).
+    /// `imm` is only used by TEST.
+    Group1 { code: u8, word: bool, rm: Operand, imm: u16 },
+    /// MUL reg16, rm16, imm (69H, 6BH)
+    MulImm { reg: u8, rm: Operand, imm: u16 },
+    /// ADJ4A (27H), ADJ4S (2FH), ADJBA (37H) or ADJBS (3FH)
+    Adjust(u8),
+    /// CVTBD (D4H)
+    Cvtbd,
+    /// CVTDB (D5H)
+    Cvtdb,
+    /// CVTBW (98H)
+    Cvtbw,
+    /// CVTWL (99H)
+    Cvtwl,
+    /// ADD4S, SUB4S or CMP4S (0F 20, 0F 22, 0F 26)
+    BcdString(u8),
+    /// ROL4 (0F 28) or ROR4 (0F 2A)
+    RotateNibble { left: bool, rm: Operand },
+    /// TEST1, CLR1, SET1 or NOT1 with the bit number in CL or an immediate
+    /// (0F 10-1F)
+    Bit { code: u8, word: bool, rm: Operand, bit: Count },
+    /// INS or EXT, with the offset in byte register `offset` and the length
+    /// in a byte register or an immediate (0F 31, 0F 33, 0F 39, 0F 3B)
+    BitField { insert: bool, offset: u8, length: Count },
+    /// Block transfer/string instruction without a repeat prefix
+    String(u8),
+
+    /// MOV between a register/memory operand and a register (88H-8BH)
+    Mov { word: bool, to_reg: bool, reg: u8, rm: Operand },
+    /// MOV between a register/memory operand and a segment register (8CH, 8EH)
+    MovSegment { to_segment: bool, sreg: u8, rm: Operand },
+    /// MOV between the accumulator and a direct address (A0H-A3H)
+    MovAcc { word: bool, to_acc: bool, addr: u16 },
+    /// MOV register (B0H-BFH) or register/memory operand (C6H, C7H), immediate
+    MovImm { word: bool, rm: Operand, imm: u16 },
+    /// MOV DS1 (C4H) or DS0 (C5H), reg16, mem32: load a far pointer
+    LoadPointer { segment: Segment, reg: u8, rm: Operand },
+    /// LDEA reg16, mem (8DH)
+    Ldea { reg: u8, rm: Operand },
+    /// XCH register, register/memory operand (86H, 87H, 91H-97H)
+    Xch { word: bool, reg: u8, rm: Operand },
+    /// NOP (90H)
+    Nop,
+    /// PUSH word register (50H-57H) or register/memory operand (FFH)
+    Push(Operand),
+    /// POP word register (58H-5FH) or register/memory operand (8FH)
+    Pop(Operand),
+    /// PUSH segment register (06H, 0EH, 16H, 1EH)
+    PushSegment(u8),
+    /// POP segment register (07H, 17H, 1FH)
+    PopSegment(u8),
+    /// PUSH imm16 (68H) or sign-extended imm8 (6AH)
+    PushImm(u16),
+    /// PUSH PSW (9CH)
+    PushPsw,
+    /// POP PSW (9DH)
+    PopPsw,
+    /// PUSH R (60H)
+    PushRegisters,
+    /// POP R (61H)
+    PopRegisters,
+    /// MOV PSW, AH (9EH)
+    MovPswAh,
+    /// MOV AH, PSW (9FH)
+    MovAhPsw,
+    /// TRANS (D7H)
+    Trans,
+    /// IN accumulator from an immediate port (E4H, E5H) or from DW (ECH, EDH)
+    In { word: bool, port: Option<u8> },
+    /// OUT accumulator to an immediate port (E6H, E7H) or to DW (EEH, EFH)
+    Out { word: bool, port: Option<u8> },
+
+    /// Conditional branch (70H-7FH), condition in the low nibble of the opcode
+    Branch { condition: u8, disp: i8 },
+    /// DBNZNE, DBNZE, DBNZ or BCWZ (E0H-E3H), `code` in the low bits of the opcode
+    Loop { code: u8, disp: i8 },
+    /// BR near-label (E9H) or short-label (EBH)
+    BranchRelative(i16),
+    /// CALL near-proc (E8H)
+    CallRelative(i16),
+    /// BR far-label (EAH)
+    BranchFar { segment: u16, offset: u16 },
+    /// CALL far-proc (9AH)
+    CallFar { segment: u16, offset: u16 },
+    /// BR regptr16/memptr16 (FFH)
+    BranchIndirect(Operand),
+    /// CALL regptr16/memptr16 (FFH)
+    CallIndirect(Operand),
+    /// BR memptr32 (FFH)
+    BranchFarIndirect(Operand),
+    /// CALL memptr32 (FFH)
+    CallFarIndirect(Operand),
+    /// RET near (C2H, C3H) or far (CAH, CBH), with an optional pop value
+    Ret { far: bool, pop: Option<u16> },
+    /// RETI (CFH)
+    Reti,
+    /// BRK 3 (CCH) or BRK imm8 (CDH)
+    Brk(u8),
+    /// BRKV (CEH)
+    Brkv,
+    /// HALT (F4H)
+    Halt,
+    /// POLL (9BH)
+    Poll,
+    /// PREPARE imm16, imm8 (C8H)
+    Prepare { size: u16, level: u8 },
+    /// DISPOSE (C9H)
+    Dispose,
+    /// CHKIND reg16, mem32 (62H)
+    Chkind { reg: u8, rm: Operand },
+    /// BRKXA imm8 (0F E0)
+    Brkxa(u8),
+    /// RETXA imm8 (0F F0)
+    Retxa(u8),
+
+    /// NOT1 CY (F5H)
+    NotCy,
+    /// CLR1 CY (F8H)
+    ClrCy,
+    /// SET1 CY (F9H)
+    SetCy,
+    /// DI (FAH)
+    Di,
+    /// EI (FBH)
+    Ei,
+    /// CLR1 DIR (FCH)
+    ClrDir,
+    /// SET1 DIR (FDH)
+    SetDir,
+
+    /// Floating point operation for a coprocessor: FPO1 (D8H-DFH) or
+    /// FPO2 (66H, 67H), with the operation code in the reg field
+    Coprocessor { op: u8, code: u8, rm: Operand },
+    /// Undefined opcode, or undefined form of an opcode
+    Undefined(u8),
+    /// Undefined opcode of the 0FH map, by its second byte
+    UndefinedExtended(u8),
+}
 
-        0xAD => (format!("LDMW"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let data = cpu.read_u16(cpu.ix() as u32);
-            cpu.aw = data;
-            if cpu.dir() {
-                cpu.ix = cpu.ix - 2;
-            } else {
-                cpu.ix = cpu.ix + 2;
-            }
-            if cpu.ix % 2 == 1 { 7 } else { 5 }
-        })),
+/// Source of a shift count, bit number or bit field length.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Count {
+    /// Constant 1
+    One,
+    /// Register CL
+    CL,
+    /// Byte register, as encoded in the instruction
+    Register(u8),
+    /// Immediate byte
+    Immediate(u8),
+}
 
-        0xAE => unimplemented!("CMPM"),
-        0xAF => unimplemented!("CMPM"),
+impl Instruction {
 
-        0xB0 => {
-            let arg = cpu.next_u8();
-            (format!("MOV AL, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_al(arg);
-                2
-            }))
-        },
-        0xB1 => {
-            let arg = cpu.next_u8();
-            (format!("MOV CL, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_cl(arg);
-                2
-            }))
-        },
-        0xB2 => {
-            let arg = cpu.next_u8();
-            (format!("MOV DL, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_dl(arg);
-                2
-            }))
-        },
-        0xB3 => {
-            let arg = cpu.next_u8();
-            (format!("MOV BL, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_bl(arg);
-                2
-            }))
-        },
+    /// Decode the instruction at the start of `bytes`. Returns the instruction
+    /// and its length, or `None` if `bytes` ends before the instruction does.
+    pub fn decode (bytes: &[u8]) -> Option<(Self, usize)> {
+        let mut decoder = Decoder { bytes, length: 0 };
+        let instruction = decoder.instruction()?;
+        Some((instruction, decoder.length))
+    }
 
-        0xB4 => {
-            let arg = cpu.next_u8();
-            (format!("MOV AH, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_ah(arg);
-                2
-            }))
-        },
-        0xB5 => {
-            let arg = cpu.next_u8();
-            (format!("MOV CH, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_ch(arg);
-                2
-            }))
-        },
-        0xB6 => {
-            let arg = cpu.next_u8();
-            (format!("MOV DH, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_dh(arg);
-                2
-            }))
-        },
-        0xB7 => {
-            let arg = cpu.next_u8();
-            (format!("MOV BH, {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU| {
-                cpu.set_bh(arg);
-                2
-            }))
-        },
-        0xB8 => {
-            let word = cpu.next_u16();
-            let [lo, hi] = word.to_le_bytes();
-            (format!("MOV AW, {word:04X}"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                cpu.set_aw(word);
-                2
-            }))
-        },
-        0xB9 => {
-            let word = cpu.next_u16();
-            let [lo, hi] = word.to_le_bytes();
-            (format!("MOV CW, {word:04X}"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                cpu.set_cw(word);
-                2
-            }))
-        },
-        0xBA => {
-            let word = cpu.next_u16();
-            let [lo, hi] = word.to_le_bytes();
-            (format!("MOV DW, {word:04X}"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                cpu.set_dw(word);
-                2
-            }))
-        },
-        0xBB => {
-            let word = cpu.next_u16();
-            let [lo, hi] = word.to_le_bytes();
-            (format!("MOV BW, {word:04X}"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU|{
-                cpu.set_bw(word);
-                2
-            }))
-        },
+}
 
-        0xBC => {
-            let arg = cpu.next_u16();
-            let [lo, hi] = arg.to_le_bytes();
-            (format!("MOV SP, {arg:04X}"), vec![op, lo, hi], Box::new(move|cpu: &mut CPU|{
-                cpu.set_sp(arg);
-                2
-            }))
-        },
-        0xBD => {
-            let arg = cpu.next_u16();
-            let [lo, hi] = arg.to_le_bytes();
-            (format!("MOV BP, {arg:04X}"), vec![op, lo, hi], Box::new(move|cpu: &mut CPU|{
-                cpu.set_bp(arg);
-                2
-            }))
-        },
-        0xBE => {
-            let arg = cpu.next_u16();
-            let [lo, hi] = arg.to_le_bytes();
-            (format!("MOV IX, {arg:04X}"), vec![op, lo, hi], Box::new(move|cpu: &mut CPU|{
-                cpu.set_ix(arg);
-                2
-            }))
-        },
-        0xBF => {
-            let arg = cpu.next_u16();
-            let [lo, hi] = arg.to_le_bytes();
-            (format!("MOV IY, {arg:04X}"), vec![op, lo, hi], Box::new(move|cpu: &mut CPU|{
-                cpu.set_iy(arg);
-                2
-            }))
-        },
+/// Segment selected by a segment override prefix.
+pub fn segment_prefix (op: u8) -> Option<Segment> {
+    match op {
+        0x26 => Some(Segment::DS1),
+        0x2E => Some(Segment::PS),
+        0x36 => Some(Segment::SS),
+        0x3E => Some(Segment::DS0),
+        _ => None
+    }
+}
 
-        0xC0 => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, _) => {
-                    unimplemented!("rol");
-                },
-                (0b001, _) => {
-                    unimplemented!("ror");
-                },
-                (0b010, _) => {
-                    unimplemented!("rolc");
-                },
-                (0b011, _) => {
-                    unimplemented!("rorc");
-                },
-                (0b100, 0b11) => {
-                    let imm = cpu.next_u8();
-                    let name = register_name_u8(mem);
-                    (
-                        format!("SHL {name}, {imm:02X}"),
-                        vec![op, arg, imm],
-                        Box::new(move |cpu: &mut CPU|{
-                            let source    = cpu.get_register_u8(mem);
-                            let msb       = source & B7;
-                            let shifted   = source << 1;
-                            let msb_after = shifted >> 7;
-                            cpu.set_register_u8(mem, shifted);
-                            cpu.set_cy(msb > 0);
-                            cpu.set_v(msb != msb_after);
-                            2 + imm as u64
-                        })
-                    )
-                },
-                (0b100, _) => {
-                    unimplemented!("shl mem");
-                },
-                (0b101, _) => {
-                    unimplemented!("shr");
-                },
-                (0b110, _) => {
-                    panic!("invalid shift code 0b110");
-                },
-                (0b111, _) => {
-                    unimplemented!("shra");
-                },
-                _ => {
-                    unreachable!("shift code {code:b}");
-                }
-            }
-        },
+/// Reads instruction bytes and keeps count of them.
+struct Decoder<'a> {
+    bytes:  &'a [u8],
+    length: usize,
+}
 
-        0xC1 => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match code {
-                0b000 => {
-                    unimplemented!("rol");
-                },
-                0b001 => {
-                    unimplemented!("ror");
-                },
-                0b010 => {
-                    unimplemented!("rolc");
-                },
-                0b011 => {
-                    unimplemented!("rorc");
-                },
-                0b100 => {
-                    unimplemented!("shl");
-                },
-                0b101 => {
-                    unimplemented!("shr");
-                },
-                0b110 => {
-                    panic!("invalid shift code 0b110");
-                },
-                0b111 => {
-                    unimplemented!("shra");
-                },
-                _ => {
-                    unreachable!("shift code {code:b}");
-                }
-            }
-        },
+impl<'a> Decoder<'a> {
 
-        0xC2 => {
-            let pop = cpu.next_u16();
-            let [lo, hi] = pop.to_le_bytes();
-            unimplemented!("RET {pop}")
-        },
+    fn u8 (&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.length)?;
+        self.length += 1;
+        Some(byte)
+    }
 
-        0xC3 => (format!("RET"), vec![op], Box::new(move |cpu: &mut CPU| {
-            let pc = cpu.pop_u16();
-            cpu.set_pc(pc);
-            if pc % 2 == 1 { 12 } else { 10 }
-        })),
+    fn i8 (&mut self) -> Option<i8> {
+        self.u8().map(|byte| byte as i8)
+    }
 
-        0xC4 => (format!("MOV DS1, AW"), vec![op], Box::new(move |cpu: &mut CPU| {
-            cpu.ds1 = cpu.aw;
-            if cpu.aw % 2 == 0 { 10 } else { 14 }
-        })),
+    fn u16 (&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
 
-        0xC5 => (format!("MOV DS0, AW"), vec![op], Box::new(move |cpu: &mut CPU| {
-            cpu.ds0 = cpu.aw;
-            if cpu.aw % 2 == 0 { 10 } else { 14 }
-        })),
+    fn i16 (&mut self) -> Option<i16> {
+        self.u16().map(|word| word as i16)
+    }
 
-        0xC6 => {
-            let arg  = cpu.next_u8();
-            (format!("MOV"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let mode = (arg & B_MODE) >> 6;
-                let code = (arg & B_REG)  >> 3;
-                if code != 0b000 {
-                    panic!();
-                }
-                let mem  = (arg & B_MEM)  >> 0;
-                let addr = cpu.memory_address(mode, mem);
-                let imm  = cpu.next_u8();
-                cpu.write_u8(addr, imm);
-                3
-            }))
-        },
+    /// Immediate byte or word.
+    fn imm (&mut self, word: bool) -> Option<u16> {
+        if word { self.u16() } else { self.u8().map(u16::from) }
+    }
 
-        0xC7 => unimplemented!("MOV mw imm"),
-        0xC8 => unimplemented!("PREPARE"),
-        0xC9 => unimplemented!("DISPOSE"),
-        0xCA => unimplemented!("RET"),
-        0xCB => unimplemented!("RET"),
-        0xCC => unimplemented!("BRK"),
+    /// Immediate byte, sign-extended to a word.
+    fn imm_i8 (&mut self) -> Option<u16> {
+        self.i8().map(|byte| byte as i16 as u16)
+    }
 
-        0xCD => {
-            let arg = cpu.next_u8();
-            (format!("BRK {arg:02X}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                let ta = cpu.read_u16(arg as u32 * 4);
-                let tc = cpu.read_u16(arg as u32 * 4 + 2);
-                cpu.push_u16(cpu.psw());
-                cpu.set_ie(false);
-                cpu.set_brk(false);
-                cpu.push_u16(cpu.ps());
-                cpu.set_ps(tc);
-                cpu.push_u16(cpu.pc());
-                cpu.set_pc(ta);
-                if cpu.pc() % 2 == 1 { 24 } else { 18 }
-            }))
-        },
+    /// ModRM byte, split into its mode, reg and mem fields.
+    fn modrm (&mut self) -> Option<(u8, u8, u8)> {
+        let arg = self.u8()?;
+        Some(((arg & B_MODE) >> 6, (arg & B_REG) >> 3, arg & B_MEM))
+    }
 
-        0xCE => unimplemented!("BRKV"),
+    /// Register or memory operand of a ModRM byte, reading any displacement.
+    /// 8-bit displacements are sign-extended.
+    fn operand (&mut self, mode: u8, mem: u8) -> Option<Operand> {
+        let disp = match (mode, mem) {
+            (0b11, _) => return Some(Operand::Register(mem)),
+            (0b00, 0b110) | (0b10, _) => self.u16()?,
+            (0b01, _) => self.imm_i8()?,
+            _ => 0,
+        };
+        Some(Operand::Memory { mode, mem, disp })
+    }
 
-        0xCF => (format!("RETI"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let pc = cpu.pop_u16();
-            let ps = cpu.pop_u16();
-            let psw = cpu.pop_u16();
-            cpu.set_pc(pc);
-            cpu.set_ps(ps);
-            cpu.set_psw(psw);
-            if cpu.pc() % 2 == 1 { 19 } else { 13 }
-        })),
+    /// ModRM byte with a register/memory operand, returning the reg field
+    /// and the operand.
+    fn reg_operand (&mut self) -> Option<(u8, Operand)> {
+        let (mode, reg, mem) = self.modrm()?;
+        Some((reg, self.operand(mode, mem)?))
+    }
 
-        0xD0 => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match code {
-                0b000 => {
-                    unimplemented!("rol");
-                },
-                0b001 => {
-                    unimplemented!("ror");
-                },
-                0b010 => {
-                    unimplemented!("rolc");
-                },
-                0b011 => {
-                    unimplemented!("rorc");
-                },
-                0b100 => {
-                    unimplemented!("shl");
-                },
-                0b101 => {
-                    unimplemented!("shr");
-                },
-                0b110 => {
-                    panic!("invalid shift code 0b110");
-                },
-                0b111 => {
-                    unimplemented!("shra");
-                },
-                _ => {
-                    unreachable!("shift code {code:b}");
+    fn instruction (&mut self) -> Option<Instruction> {
+        use Instruction::*;
+        let op   = self.u8()?;
+        let word = op & B0 > 0;
+        Some(match op {
+            0x00..=0x3F if op & 0b111 < 0b100 => {
+                let (reg, rm) = self.reg_operand()?;
+                Alu { code: (op >> 3) & 0b111, word, to_reg: op & B1 > 0, reg, rm }
+            },
+            0x00..=0x3F if op & 0b111 < 0b110 => {
+                AluAcc { code: (op >> 3) & 0b111, word, imm: self.imm(word)? }
+            },
+            0x06 | 0x0E | 0x16 | 0x1E => PushSegment((op >> 3) & 0b11),
+            0x07 | 0x17 | 0x1F => PopSegment((op >> 3) & 0b11),
+            0x0F => self.extended()?,
+            0x26 | 0x2E | 0x36 | 0x3E => Segment(segment_prefix(op)?),
+            0x27 | 0x2F | 0x37 | 0x3F => Adjust(op),
+            0x40..=0x4F => IncDec { dec: op & B3 > 0, word: true, rm: Operand::Register(op & B_MEM) },
+            0x50..=0x57 => Push(Operand::Register(op & B_MEM)),
+            0x58..=0x5F => Pop(Operand::Register(op & B_MEM)),
+            0x60 => PushRegisters,
+            0x61 => PopRegisters,
+            0x62 => match self.reg_operand()? {
+                (reg, rm @ Operand::Memory { .. }) => Chkind { reg, rm },
+                _ => Undefined(op),
+            },
+            0x63 | 0xD6 | 0xF1 => Undefined(op),
+            0x64 | 0x65 | 0xF2 | 0xF3 => self.repeat(op)?,
+            0x66 | 0x67 | 0xD8..=0xDF => {
+                let (code, rm) = self.reg_operand()?;
+                Coprocessor { op, code, rm }
+            },
+            0x68 => PushImm(self.u16()?),
+            0x69 | 0x6B => {
+                let (reg, rm) = self.reg_operand()?;
+                let imm = if op == 0x69 { self.u16()? } else { self.imm_i8()? };
+                MulImm { reg, rm, imm }
+            },
+            0x6A => PushImm(self.imm_i8()?),
+            0x6C..=0x6F | 0xA4..=0xA7 | 0xAA..=0xAF => String(op),
+            0x70..=0x7F => Branch { condition: op & 0x0F, disp: self.i8()? },
+            0x80..=0x83 => {
+                let (code, rm) = self.reg_operand()?;
+                let imm = match op {
+                    0x81 => self.u16()?,
+                    0x83 => self.imm_i8()?,
+                    _    => self.u8()? as u16,
+                };
+                AluImm { code, word, rm, imm }
+            },
+            0x84 | 0x85 => {
+                let (reg, rm) = self.reg_operand()?;
+                Test { word, reg, rm }
+            },
+            0x86 | 0x87 => {
+                let (reg, rm) = self.reg_operand()?;
+                Xch { word, reg, rm }
+            },
+            0x88..=0x8B => {
+                let (reg, rm) = self.reg_operand()?;
+                Mov { word, to_reg: op & B1 > 0, reg, rm }
+            },
+            0x8C | 0x8E => {
+                let (sreg, rm) = self.reg_operand()?;
+                MovSegment { to_segment: op == 0x8E, sreg: sreg & 0b11, rm }
+            },
+            0x8D => match self.reg_operand()? {
+                (reg, rm @ Operand::Memory { .. }) => Ldea { reg, rm },
+                _ => Undefined(op),
+            },
+            0x8F => Pop(self.reg_operand()?.1),
+            0x90 => Nop,
+            0x91..=0x97 => Xch { word: true, reg: 0b000, rm: Operand::Register(op & B_MEM) },
+            0x98 => Cvtbw,
+            0x99 => Cvtwl,
+            0x9A => {
+                let offset = self.u16()?;
+                CallFar { segment: self.u16()?, offset }
+            },
+            0x9B => Poll,
+            0x9C => PushPsw,
+            0x9D => PopPsw,
+            0x9E => MovPswAh,
+            0x9F => MovAhPsw,
+            0xA0..=0xA3 => MovAcc { word, to_acc: op & B1 == 0, addr: self.u16()? },
+            0xA8 | 0xA9 => TestAcc { word, imm: self.imm(word)? },
+            0xB0..=0xBF => {
+                let word = op & B3 > 0;
+                MovImm { word, rm: Operand::Register(op & B_MEM), imm: self.imm(word)? }
+            },
+            0xC0 | 0xC1 | 0xD0..=0xD3 => {
+                let (code, rm) = self.reg_operand()?;
+                if code == 0b110 {
+                    return Some(Undefined(op))
                 }
-            }
-        },
-
-        0xD1 => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, _) => unimplemented!("rol"),
-                (0b001, _) => unimplemented!("ror"),
-                (0b010, _) => {
-                    (format!("ROLC"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                        let source = get_source_word(cpu, arg);
-                        let cy  = cpu.cy() as u16;
-                        let msb = (source & W15) >> 15;
-                        let nsb = (source & W14) >> 14;
-                        let rotated = source << 1 | cy;
-                        set_source_word(cpu, arg, rotated);
-                        cpu.set_cy(msb > 0);
-                        cpu.set_v(msb != nsb);
-                        2
-                    }))
-                },
-                (0b011, _) => unimplemented!("rorc"),
-                (0b100, _) => {
-                    (format!("SHL"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                        let source = get_source_word(cpu, arg);
-                        let msb       = source & W15;
-                        let shifted   = source << 1;
-                        let msb_after = shifted >> 15;
-                        set_source_word(cpu, arg, shifted);
-                        cpu.set_cy(msb > 0);
-                        cpu.set_v(msb != msb_after);
-                        2
-                    }))
-                },
-                (0b101, _) => {
-                    (format!("SHR"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                        let source = get_source_word(cpu, arg);
-                        let msb       = source & W15;
-                        let shifted   = source >> 1;
-                        let msb_after = shifted >> 15;
-                        set_source_word(cpu, arg, shifted);
-                        cpu.set_cy(msb > 0);
-                        cpu.set_v(msb != msb_after);
-                        2
-                    }))
-                },
-                (0b110, _) => panic!("invalid shift code 0b110"),
-                (0b111, _) => unimplemented!("shra"),
-                _ => unreachable!("shift code {code:b}"),
-            }
-        },
-
-        0xD2 => unimplemented!("SHIFT b, port"),
-        0xD3 => unimplemented!("SHIFT w, port"),
-        0xD4 => unimplemented!("CVTBD"),
-        0xD5 => unimplemented!("CVTDB"),
-        0xD6 => unimplemented!("UNDEF"),
-        0xD7 => unimplemented!("TRANS"),
-        0xD8 => unimplemented!("FPO1"),
-        0xD9 => unimplemented!("FPO1"),
-        0xDA => unimplemented!("FPO1"),
-        0xDB => unimplemented!("FPO1"),
-        0xDC => unimplemented!("FPO1"),
-        0xDD => unimplemented!("FPO1"),
-        0xDE => unimplemented!("FPO1"),
-        0xDF => unimplemented!("FPO1"),
-
-        0xE0 => {
-            let arg = cpu.next_i8();
-            (format!("DBNZNE {arg:02X}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                let value           = cpu.cw();
-                let (result, carry) = value.overflowing_sub(1);
-                let (_, overflow)   = (value as i8).overflowing_sub(1);
-                cpu.set_cw(result);
-                cpu.set_pzscyv(result, carry, overflow);
-                if !cpu.z() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0xE1 => {
-            let arg = cpu.next_i8();
-            (format!("DBNZE {arg:02X}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                let value           = cpu.cw();
-                let (result, carry) = value.overflowing_sub(1);
-                let (_, overflow)   = (value as i8).overflowing_sub(1);
-                cpu.set_cw(result);
-                cpu.set_pzscyv(result, carry, overflow);
-                if cpu.z() { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0xE2 => {
-            let arg = cpu.next_i8();
-            (format!("DBNZ {arg:02X}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                let value           = cpu.cw();
-                let (result, carry) = value.overflowing_sub(1);
-                let (_, overflow)   = (value as i8).overflowing_sub(1);
-                cpu.set_cw(result);
-                cpu.set_pzscyv(result, carry, overflow);
-                if cpu.cw() > 0 { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0xE3 => {
-            let arg = cpu.next_i8();
-            (format!("BCWZ {arg:02X}"), vec![op, arg as u8], Box::new(move |cpu: &mut CPU| {
-                if cpu.cw() == 0 { cpu.jump_i8(arg); 6 } else { 3 }
-            }))
-        },
-
-        0xE4 => {
-            let addr = cpu.next_u8();
-            (format!("IN {addr:04X}"), vec![op, addr], Box::new(move |cpu: &mut CPU| {
-                let data = cpu.input_u8(addr as u32);
-                cpu.set_al(data);
-                5
-            }))
-        },
-
-        0xE5 => {
-            let addr = cpu.next_u16();
-            let [lo, hi] = addr.to_le_bytes();
-            (format!("INW"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU| {
-                let data = cpu.input_u16(addr as u32);
-                cpu.set_aw(data);
-                7
-            }))
-        },
-
-        0xE6 => {
-            let addr = cpu.next_u8();
-            (format!("OUT"), vec![op, addr], Box::new(move |cpu: &mut CPU| {
-                let data = cpu.al();
-                cpu.output_u8(addr as u16, data);
-                3
-            }))
-        },
-
-        0xE7 => {
-            let addr = cpu.next_u16();
-            let [lo, hi] = addr.to_le_bytes();
-            (format!("OUTW"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU| {
-                cpu.output_u16(addr, cpu.aw);
-                5
-            }))
-        },
-
-        0xE8 => {
-            let displace = cpu.next_i16();
-            let [lo, hi] = displace.to_le_bytes();
-            (format!("CALLD"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU| {
-                cpu.push_u16(cpu.pc);
-                cpu.jump_i16(displace);
-                if cpu.pc % 1 == 0 { 7 } else { 9 }
-            }))
-        },
-
-        0xE9 => {
-            let displace = cpu.next_i16();
-            let [lo, hi] = displace.to_le_bytes();
-            (format!("BR"), vec![op, lo, hi], Box::new(move |cpu: &mut CPU| {
-                cpu.jump_i16(displace);
-                7
-            }))
-        },
-
-        0xEA => {
-            let offset     = cpu.next_u16();
-            let [olo, ohi] = offset.to_le_bytes();
-            let segment    = cpu.next_u16();
-            let [slo, shi] = segment.to_le_bytes();
-            (format!("BR {segment:04X}:{offset:04X}"), vec![op, olo, ohi, slo, shi],
-                Box::new(move |cpu: &mut CPU| {
-                    cpu.set_pc(offset);
-                    cpu.set_ps(segment);
-                    7
-                }))
-        },
-
-        0xEB => {
-            let displace = cpu.next_i8();
-            (format!("BR"), vec![op, displace as u8], Box::new(move |cpu: &mut CPU|{
-                cpu.jump_i8(displace);
-                7
-            }))
-        },
-
-        0xEC => (format!("IN"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let addr = cpu.dw;
-            let data = cpu.input_u8(addr as u32);
-            cpu.set_al(data);
-            5
-        })),
-
-        0xED => (format!("INW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            let addr = cpu.dw;
-            let data = cpu.input_u16(addr as u32);
-            cpu.set_aw(data);
-            7
-        })),
-
-        0xEE => (format!("OUT DW, AL"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.output_u8(cpu.dw(), cpu.al());
-            3
-        })),
-
-        0xEF => (format!("OUTW DW, AW"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.output_u16(cpu.dw(), cpu.aw());
-            5
-        })),
-
-        0xF0 => unimplemented!("BUSLOCK"),
-        0xF1 => unimplemented!("UNDEFINED"),
-        0xF2 => unimplemented!("REPNE"),
-
-        0xF3 => {
-            let arg = cpu.next_u8();
-            let name = match arg {
-                0xA4 => "MOVBK",
-                0xA5 => "MOVBKW",
-                0xAC => "LDM",
-                0xAD => "LDMW",
-                0xAA => "STM",
-                0xAB => "STMW",
-                0x6E => "OUTM",
-                0x6F => "OUTMW",
-                0x6C => "INM",
-                0x6D => "INMW",
-                0xA6 => "CMPBK",
-                0xA7 => "CMPBKW",
-                0xAE => "CMPM",
-                0xAF => "CMPMW",
-                _ => panic!("invalid instruction after REP")
-            };
-            (format!("REP {name}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                if cpu.cw() == 0 {
-                    cpu.set_pc(cpu.pc() + 1);
-                } else {
-                    let op = arg;
-                    if (op == 0xA4) || (op == 0xA5) ||        // MOVBK
-                       (op == 0xAC) || (op == 0xAD) ||        // LDM
-                       (op == 0xAA) || (op == 0xAB) ||        // STM
-                       (op == 0x6E) || (op == 0x6F) ||        // OUTM
-                       (op == 0x6C) || (op == 0x6D)           // INM
-                    {
-                        // repeat while cw != 0
-                        cpu.opcode = op;
-                        while cpu.cw() != 0 {
-                            let (_, _, instruction) = v53_instruction(cpu, op);
-                            let ticks = instruction(cpu);
-                            cpu.clock += ticks;
-                            cpu.set_cw(cpu.cw() - 1);
-                        }
-                    } else if (op == 0xA6) || (op == 0xA7) || // CMPBK
-                        (op == 0xAE) || (op == 0xAF)          // CMPM
-                    {
-                        cpu.opcode = op;
-                        unimplemented!("REPZ/REPE {:x}", op);
-                        // repeat while cw != 0 && z == 0
-                    } else {
-                        panic!("invalid instruction after REP")
-                    }
+                let count = match op & 0xFE {
+                    0xC0 => Count::Immediate(self.u8()?),
+                    0xD0 => Count::One,
+                    _    => Count::CL,
+                };
+                Shift { code, word, rm, count }
+            },
+            0xC2 | 0xC3 | 0xCA | 0xCB => Ret {
+                far: op & B3 > 0,
+                pop: if op & B0 == 0 { Some(self.u16()?) } else { None }
+            },
+            0xC4 | 0xC5 => match self.reg_operand()? {
+                (reg, rm @ Operand::Memory { .. }) => LoadPointer {
+                    segment: if op == 0xC4 { crate::Segment::DS1 } else { crate::Segment::DS0 },
+                    reg,
+                    rm
+                },
+                _ => Undefined(op),
+            },
+            0xC6 | 0xC7 => match self.reg_operand()? {
+                (0b000, rm) => MovImm { word, rm, imm: self.imm(word)? },
+                _ => Undefined(op),
+            },
+            0xC8 => Prepare { size: self.u16()?, level: self.u8()? },
+            0xC9 => Dispose,
+            0xCC => Brk(VECTOR_BREAKPOINT),
+            0xCD => Brk(self.u8()?),
+            0xCE => Brkv,
+            0xCF => Reti,
+            // The second byte of CVTBD and CVTDB is always 0AH and is ignored
+            0xD4 => { self.u8()?; Cvtbd },
+            0xD5 => { self.u8()?; Cvtdb },
+            0xD7 => Trans,
+            0xE0..=0xE3 => Loop { code: op & 0b11, disp: self.i8()? },
+            0xE4 | 0xE5 => In { word, port: Some(self.u8()?) },
+            0xE6 | 0xE7 => Out { word, port: Some(self.u8()?) },
+            0xE8 => CallRelative(self.i16()?),
+            0xE9 => BranchRelative(self.i16()?),
+            0xEA => {
+                let offset = self.u16()?;
+                BranchFar { segment: self.u16()?, offset }
+            },
+            0xEB => BranchRelative(self.i8()? as i16),
+            0xEC | 0xED => In { word, port: None },
+            0xEE | 0xEF => Out { word, port: None },
+            0xF0 => Prefix(op),
+            0xF4 => Halt,
+            0xF5 => NotCy,
+            0xF6 | 0xF7 => {
+                let (code, rm) = self.reg_operand()?;
+                match code {
+                    0b000 => Group1 { code, word, rm, imm: self.imm(word)? },
+                    0b001 => Undefined(op),
+                    _     => Group1 { code, word, rm, imm: 0 },
                 }
-                2
-            }))
-        },
-
-        0xF4 => unimplemented!("HALT"),
-        0xF5 => unimplemented!("NOT1"),
-
-        0xF6 => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, 0b11) => {
-                    let imm = cpu.next_u8();
-                    let name = register_name_u8(mem);
-                    (
-                        format!("TEST {name}, {imm:02X}"),
-                        vec![op, arg, imm],
-                        Box::new(move |cpu: &mut CPU|{
-                            unimplemented!()
-                        })
-                    )
-                },
-                (0b000, _) => {
-                    let disp = cpu.next_u16();
-                    let [lo, hi] = disp.to_le_bytes();
-                    let imm = cpu.next_u8();
-                    (
-                        format!("TEST mem, {imm:02X}"),
-                        vec![op, arg, imm],
-                        Box::new(move |cpu: &mut CPU|{
-                            let address = match mem {
-                                0b110 => disp,
-                                _ => unimplemented!("mode={mode:b} mem={mem:b}"),
-                            } as u32;
-                            let data = cpu.read_u8(address);
-                            let result = data & imm;
-                            cpu.set_pzs(result as u16);
-                            cpu.set_cy(false);
-                            cpu.set_v(false);
-                            2
-                        })
-                    )
-                },
-                (0b001, _) => panic!("undefined group1 instruction"),
-                (0b010, 0b11) => unimplemented!("not rm"),
-                (0b010, _) => unimplemented!("not rm"),
-                (0b011, 0b11) => unimplemented!("neg rm"),
-                (0b011, _) => unimplemented!("neg rm"),
-                (0b100, 0b11) => {
-                    (format!("MULU {arg:02X}"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                        let src = cpu.al() as u16;
-                        let dst = cpu.get_register_u8((arg & B_REG) >> 3) as u16;
-                        let result = src * dst;
-                        cpu.set_aw(result);
-                        cpu.set_cy(result > 0xFF);
-                        cpu.set_v(result > 0xFF);
-                        8
-                    }))
-                },
-                (0b100, _) => {
-                    (format!("MULU {arg:02X}"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                        unimplemented!("mulu rm");
-                        0
-                    }))
-                },
-                (0b101, 0b11) => {
-                    (format!("MUL {arg:02X}"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                        unimplemented!("mul rm");
-                        0
-                    }))
-                },
-                (0b101, _) => {
-                    let arg = cpu.next_u8();
-                    (format!("MUL {arg:02X}"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                        unimplemented!("mul rm");
-                        0
-                    }))
-                },
-                (0b110, 0b11) => {
-                    (format!("DIVU {arg:02X}"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                        let src = cpu.aw();
-                        let dst = cpu.get_register_u16((arg & B_REG) >> 3) as u16;
-                        if src / dst > 0xff {
-                            panic!("divu overflow")
-                        } else {
-                            cpu.set_al((src / dst) as u8);
-                            cpu.set_ah((src % dst) as u8);
-                        }
-                        11
-                    }))
-                },
-                (0b110, _) => {
-                    (format!("DIVU {arg:02X}"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                        let src  = cpu.aw();
-                        let addr = cpu.memory_address(mode, mem);
-                        let dst  = cpu.read_u8(addr) as u16;
-                        if src / dst > 0xff {
-                            panic!("divu overflow")
-                        } else {
-                            cpu.set_al((src / dst) as u8);
-                            cpu.set_ah((src % dst) as u8);
-                        }
-                        15
-                    /*
-                        #define DIVUB
-                        uresult = Wreg(AW);
-                        uresult2 = uresult % tmp;
-                        if ((uresult /= tmp) > 0xff) {
-                            nec_interrupt(NEC_DIVIDE_VECTOR, BRK); break;
-                        } else {
-                            Breg(AL) = uresult;
-                            Breg(AH) = uresult2;
-                        }
-                    */
-                    }))
-                },
-                (0b111, 0b11) => (format!("DIV"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                    /*
-                        #define DIVB
-                        result = (int16_t)Wreg(AW);
-                        result2 = result % (int16_t)((int8_t)tmp);
-                        if ((result /= (int16_t)((int8_t)tmp)) > 0xff) {
-                            nec_interrupt(NEC_DIVIDE_VECTOR, BRK); break;
-                        } else {
-                            Breg(AL) = result;
-                            Breg(AH) = result2;
-                        }
-                    */
-                    let t = cpu.aw() as i16;
-                    let dst = cpu.get_register_u8((arg & B_REG) >> 3) as i16;
-                    if (((t / dst) > 0) && ((t / dst) <= 0x7F)) ||
-                       (((t / dst) < 0) && ((t / dst) > (0 - 0x7F - 1)))
-                    {
-                        cpu.set_ah((t % dst) as u8);
-                        cpu.set_al((t / dst) as u8);
-                    } else {
-                        panic!()
-                    }
-                    //cpu.push_u16(cpu.psw());
-                    //cpu.set_ie(false);
-                    //cpu.set_brk(false);
-                    //cpu.push_u16(cpu.ps());
-                    //cpu.set_ps(u16::from_le_bytes([0x2, 0x3]));
-                    //cpu.push_u16(cpu.pc());
-                    //cpu.set_pc(u16::from_le_bytes([0x0, 0x1]));
-                    17
-                })),
-                (0b111, _) => (format!("DIV"), vec![op, arg], Box::new(move|cpu: &mut CPU|{
-                    let t    = cpu.aw() as i16;
-                    let addr = cpu.memory_address(mode, mem);
-                    let dst  = sign_extend_16(cpu.read_u8(addr) as u16, 8);
-                    println!("\n\naddr={addr:x} dst={dst:b} t={t:b}\n");
-                    cpu.dump();
-                    if (((t / dst) > 0) && ((t / dst) <= 0x7F)) ||
-                       (((t / dst) < 0) && ((t / dst) > (0 - 0x7F - 1)))
-                    {
-                        cpu.set_ah((t % dst) as u8);
-                        cpu.set_al((t / dst) as u8);
-                    }
-                    //cpu.push_u16(cpu.psw());
-                    //cpu.set_ie(false);
-                    //cpu.set_brk(false);
-                    20
-                })),
-                _ => panic!("invalid group1 instruction {code:b}"),
-            }
-        },
-
-        0xF7 => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, _) => unimplemented!("testw rm"),
-                (0b001, _) => panic!("undefined group1 instruction"),
-                (0b010, _) => unimplemented!("notw rm"),
-                (0b011, _) => unimplemented!("negw rm"),
-                (0b100, _) => unimplemented!("muluw rm"),
-                (0b101, _) => unimplemented!("mulw rm"),
-                (0b110, _) => {
-                    /*
-                    #define DIVUW
-                      uresult = (((uint32_t)Wreg(DW)) << 16) | Wreg(AW);
-                      uresult2 = uresult % tmp;
-                      if ((uresult /= tmp) > 0xffff) {
-                        nec_interrupt(NEC_DIVIDE_VECTOR, BRK); break;
-                      } else {
-                        Wreg(AW)=uresult;
-                        Wreg(DW)=uresult2;
-                      }
-                    */
-                    unimplemented!("divuw rm")
-                },
-
-                (0b111, 0b11) => (
-                    /*
-                    #define DIVW
-                      result = ((uint32_t)Wreg(DW) << 16) + Wreg(AW);
-                      result2 = result % (int32_t)((int16_t)tmp);
-                      if ((result /= (int32_t)((int16_t)tmp)) > 0xffff) {
-                        nec_interrupt(NEC_DIVIDE_VECTOR, BRK); break;
-                      } else {
-                        Wreg(AW)=result;
-                        Wreg(DW)=result2;
-                      }
-                    */
-                    format!("DIVW {}", register_name_u16(mem)),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU|{
-                        let divisor   = cpu.get_register_u16(mem) as u32;
-                        let dividend  = ((cpu.dw() as u32) << 16) + cpu.aw() as u32;
-                        let remainder = dividend % divisor;
-                        let result    = dividend / divisor; 
-                        if result > 0xffff {
-                            panic!("divide error")
-                        } else {
-                            cpu.set_aw(result.try_into().expect("u32 didn't fit in u16"));
-                            cpu.set_dw(remainder.try_into().expect("u32 didn't fit in u16"));
-                        }
-                        24
-                    })
-                ),
-
-                (0b111, _) => (
-                    format!("DIVW mem"),
-                    vec![op, arg],
-                    Box::new(move |cpu: &mut CPU|{
-                        let address  = cpu.memory_address(mode, mem);
-                        let divisor  = cpu.read_u16(address) as u32;
-                        let dividend  = ((cpu.dw() as u32) << 16) + cpu.aw() as u32;
-                        let remainder = dividend % divisor;
-                        let result    = dividend / divisor; 
-                        if result > 0xffff {
-                            panic!("divide error")
-                        } else {
-                            cpu.set_aw(result.try_into().expect("u32 didn't fit in u16"));
-                            cpu.set_dw(remainder.try_into().expect("u32 didn't fit in u16"));
-                        }
-                        24
-                    })
-                ),
-
-                _ => panic!("invalid group1 instruction {code:b}"),
-            }
-        },
-
-        0xF8 => (format!("CLR1 CY"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_cy(false);
-            2
-        })),
-
-        0xF9 => (format!("SET1 CY"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_cy(true);
-            2
-        })),
-
-        0xFA => (format!("DI"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_ie(false);
-            2
-        })),
-
-        0xFB => (format!("EI"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_ie(true);
-            2
-        })),
-
-        0xFC => (format!("CLR1 DIR"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_dir(false);
-            2
-        })),
-
-        0xFD => (format!("SET1 DIR"), vec![op], Box::new(move |cpu: &mut CPU|{
-            cpu.set_dir(true);
-            2
-        })),
-
-        0xFE => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match (code, mode) {
-                (0b000, 0b11) => {
-                    let reg_name = register_name_u8(mem);
-                    (format!("INC {reg_name}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                        let value = cpu.get_register_u8(mem);
-                        let (result, carry) = value.overflowing_add(1);
-                        let (_, overflow) = (value as i8).overflowing_add(1);
-                        //println!("\n\n==================={value} -> {result} {carry} {overflow}\n\n");
-                        cpu.set_register_u8(mem, result);
-                        cpu.set_pzscyv(result as u16, carry, overflow);
-                        2
-                    }))
-                },
-                (0b000, _) => {
-                    (format!("INC mem"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                        let addr = cpu.memory_address(mode, mem);
-                        let value = cpu.read_u8(addr);
-                        let (result, carry) = value.overflowing_add(1);
-                        let (_, overflow) = (value as i8).overflowing_add(1);
-                        cpu.write_u8(addr, result);
-                        cpu.set_pzscyv(result as u16, carry, overflow);
-                        if addr % 2 == 1 { 11 } else { 7 }
-                    }))
-                },
-                (0b001, _) => {
-                    unimplemented!("dec");
-                },
-                (0b010, _) => {
-                    unimplemented!()
-                },
-                (0b011, _) => {
-                    unimplemented!()
-                },
-                (0b100, _) => {
-                    unimplemented!("br");
-                },
-                (0b101, _) => {
-                    unimplemented!("br");
-                },
-                (0b110, _) => {
-                    unimplemented!("push");
-                },
-                (0b111, _) => {
-                    panic!("undefined instruction 0b111")
-                },
-                _ => {
-                    unreachable!("imm code {code:b}");
+            },
+            0xF8 => ClrCy,
+            0xF9 => SetCy,
+            0xFA => Di,
+            0xFB => Ei,
+            0xFC => ClrDir,
+            0xFD => SetDir,
+            0xFE | 0xFF => {
+                let (code, rm) = self.reg_operand()?;
+                let memory = matches!(rm, Operand::Memory { .. });
+                match (word, code) {
+                    (_, 0b000)     => IncDec { dec: false, word, rm },
+                    (_, 0b001)     => IncDec { dec: true, word, rm },
+                    (true, 0b010)  => CallIndirect(rm),
+                    (true, 0b011) if memory => CallFarIndirect(rm),
+                    (true, 0b100)  => BranchIndirect(rm),
+                    (true, 0b101) if memory => BranchFarIndirect(rm),
+                    (true, 0b110)  => Push(rm),
+                    _ => Undefined(op),
                 }
-            }
-        },
-
-        0xFF => {
-            let [arg, mode, code, mem] = get_mode_code_mem(cpu);
-            match code {
-                0b000 => {
-                    (format!("INCW mem"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                        let addr = cpu.memory_address(mode, mem);
-                        let value = cpu.read_u16(addr);
-                        let (result, carry) = value.overflowing_add(1);
-                        let (_, overflow) = (value as i16).overflowing_add(1);
-                        cpu.write_u16(addr, result);
-                        cpu.set_pzscyv(result as u16, carry, overflow);
-                        if addr % 2 == 1 { 11 } else { 7 }
-                    }))
-                },
-                0b001 => {
-                    unimplemented!("dec");
-                },
-                0b010 => (format!("CALL16 {arg}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-                    cpu.push_u16(cpu.pc());
-                    let pc = cpu.read_u16(addr);
-                    cpu.set_pc(pc);
-                    if addr % 2 == 0 { 15 } else { 23 }
-                })),
-                0b011 => (format!("CALL32 {arg}"), vec![op, arg], Box::new(move |cpu: &mut CPU|{
-                    let addr = cpu.memory_address(mode, mem);
-
-                    cpu.push_u16(cpu.ps());
-                    let ps = cpu.read_u16(addr + 2);
-                    cpu.set_ps(ps);
-
-                    cpu.push_u16(cpu.pc());
-                    let pc = cpu.read_u16(addr + 0);
-                    cpu.set_pc(pc);
+            },
+            _ => unreachable!("opcode {op:02X}"),
+        })
+    }
 
-                    if addr % 2 == 0 { 15 } else { 23 }
-                })),
-                0b100 => {
-                    unimplemented!("br");
-                },
-                0b101 => {
-                    unimplemented!("br");
-                },
-                0b110 => {
-                    unimplemented!("push");
-                },
-                0b111 => {
-                    panic!("undefined instruction 0b111")
-                },
-                _ => {
-                    unreachable!("imm code {code:b}");
-                }
-            }
-        },
+    /// Instructions with the 0FH prefix.
+    fn extended (&mut self) -> Option<Instruction> {
+        use Instruction::*;
+        let arg = self.u8()?;
+        Some(match arg {
+            0x10..=0x1F => {
+                let (_, rm) = self.reg_operand()?;
+                let bit = if arg & B3 > 0 { Count::Immediate(self.u8()?) } else { Count::CL };
+                Bit { code: (arg >> 1) & 0b11, word: arg & B0 > 0, rm, bit }
+            },
+            0x20 | 0x22 | 0x26 => BcdString(arg),
+            0x28 | 0x2A => RotateNibble { left: arg == 0x28, rm: self.reg_operand()?.1 },
+            0x31 | 0x33 | 0x39 | 0x3B => {
+                let (_, reg, mem) = self.modrm()?;
+                let length = if arg & B3 > 0 { Count::Immediate(self.u8()?) } else { Count::Register(reg) };
+                BitField { insert: arg & B1 == 0, offset: mem, length }
+            },
+            0xE0 => Brkxa(self.u8()?),
+            0xF0 => Retxa(self.u8()?),
+            _ => UndefinedExtended(arg),
+        })
+    }
 
+    /// A repeat prefix applies to the string instruction that follows,
+    /// possibly after a segment override. Before anything else, the prefix
+    /// is decoded on its own and the following bytes are left for the next
+    /// instruction.
+    fn repeat (&mut self, prefix: u8) -> Option<Instruction> {
+        let start = self.length;
+        let mut op = self.u8()?;
+        let segment = segment_prefix(op);
+        if segment.is_some() {
+            op = self.u8()?;
+        }
+        if string_name(op).is_some() {
+            Some(Instruction::Repeat { prefix, segment, op })
+        } else {
+            self.length = start;
+            Some(Instruction::Prefix(prefix))
+        }
     }
+
 }
@@ -0,0 +1,197 @@
+//! Internal I/O block: the registers of the V53 at IDB * 100H + E0H-FFH,
+//! and the mapping of I/O addresses to the peripheral units. Registers of
+//! functions that aren't emulated, such as bus sizing, standby and refresh,
+//! are held so that they read back what was written, but have no effect.
+
+use crate::*;
+
+/// Internal I/O address of the internal data block register (IDB). The
+/// internal I/O block is at IDB * 100H, and IDB itself is also always at
+/// FFFFH. It is FFH after reset.
+pub const IDB: u16 = 0xFFFF;
+
+/// Internal I/O address of the bus select register (BSEL).
+pub const BSEL: u16 = 0xFFE0;
+
+/// Internal I/O address of the bus address register (BADR).
+pub const BADR: u16 = 0xFFE1;
+
+/// Internal I/O address of the baud rate counter register (BRC).
+pub const BRC: u16 = 0xFFE9;
+
+/// Internal I/O address of the standby control register (SBCR).
+pub const SBCR: u16 = 0xFFF1;
+
+/// Internal I/O address of the refresh control register (REFC).
+pub const REFC: u16 = 0xFFF2;
+
+/// Internal I/O address of the upper byte of the addresses of the
+/// peripheral units (OPHA).
+pub const OPHA: u16 = 0xFFFC;
+
+/// Internal I/O address of the peripheral unit enable register (OPSEL).
+/// Bit 0 enables the DMA unit, bit 1 the ICU, bit 2 the TCU and bit 3 the SCU.
+pub const OPSEL: u16 = 0xFFFD;
+
+/// Internal I/O address of the system control register (SCTL), where the
+/// V40 and V50 have OPCN. Bit 0 (IOAG) places the registers of each unit
+/// at consecutive addresses; otherwise they are at even addresses. Bit 1
+/// gives the DMA unit the register set of the 8237 instead of the uPD71071.
+pub const SCTL: u16 = 0xFFFE;
+
+/// Internal I/O address of the lower byte of the address of the SCU (SULA).
+pub const SULA: u16 = 0xFFF8;
+
+/// Internal I/O address of the lower byte of the address of the TCU (TULA).
+pub const TULA: u16 = 0xFFF9;
+
+/// Internal I/O address of the lower byte of the address of the ICU (IULA).
+pub const IULA: u16 = 0xFFFA;
+
+/// Internal I/O address of the lower byte of the address of the DMA unit (DULA).
+pub const DULA: u16 = 0xFFFB;
+
+/// Peripheral unit of the V53, as selected by an I/O address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Unit {
+    /// Interrupt control unit
+    Icu,
+    /// Timer/counter unit
+    Tcu,
+    /// DMA unit
+    Dmau,
+    /// Serial control unit
+    Scu,
+}
+
+impl Unit {
+
+    /// Enable bit in OPSEL.
+    pub(crate) fn enable (&self) -> u8 {
+        match self {
+            Self::Icu => 0b0010,
+            Self::Tcu => 0b0100,
+            Self::Dmau => 0b0001,
+            Self::Scu => 0b1000,
+        }
+    }
+
+    /// Internal I/O address of the lower byte of the unit's address.
+    fn lower_address (&self) -> u16 {
+        match self {
+            Self::Icu => IULA,
+            Self::Tcu => TULA,
+            Self::Dmau => DULA,
+            Self::Scu => SULA,
+        }
+    }
+
+    /// Number of registers of the unit.
+    fn registers (&self) -> u16 {
+        match self {
+            Self::Icu => 2,
+            Self::Tcu => 4,
+            Self::Dmau => 16,
+            Self::Scu => 4,
+        }
+    }
+
+}
+
+/// Whether an offset in the internal I/O block holds a register:
+/// the page registers, XAM, and the system registers from FFE0H up.
+fn internal_register_exists (offset: u8) -> bool {
+    let port = 0xFF00 | offset as u16;
+    port < PAGE_REGISTERS + 2 * PAGES as u16 || port == XAM || [
+        BSEL, BADR, BRC, WMB0, WCY1, WCY0, WAC, TCKS, SBCR, REFC, WMB1, WCY2, WCY3, WCY4,
+        SULA, TULA, IULA, DULA, OPHA, OPSEL, SCTL, IDB
+    ].contains(&port)
+}
+
+impl CPU {
+
+    /// Offset in the internal I/O block of an I/O address, if the block,
+    /// as relocated by IDB, contains it.
+    pub fn internal_offset (&self, port: u16) -> Option<u8> {
+        let base = self.internal_register(IDB);
+        (port == IDB || (port >> 8) as u8 == base).then_some(port as u8)
+    }
+
+    pub(crate) fn read_internal (&self, offset: u8) -> u8 {
+        self.internal[offset as usize]
+    }
+
+    pub(crate) fn write_internal (&mut self, offset: u8, data: u8) {
+        if 0xFF00 | offset as u16 == XAM {
+            // Read-only: only BRKXA and RETXA change it
+        } else if internal_register_exists(offset) {
+            self.internal[offset as usize] = data
+        } else {
+            self.unused_writes.push((offset, data))
+        }
+    }
+
+    /// Offsets in the internal I/O block without a register, and the
+    /// values written to them since the last call. These writes are
+    /// otherwise ignored.
+    pub fn take_unused_writes (&mut self) -> Vec<(u8, u8)> {
+        std::mem::take(&mut self.unused_writes)
+    }
+
+    /// Peripheral unit and register number at an I/O address,
+    /// if an enabled unit is mapped there.
+    pub fn peripheral (&self, port: u16) -> Option<(Unit, u8)> {
+        let consecutive = self.internal_register(SCTL) & 1 == 1;
+        [Unit::Icu, Unit::Tcu, Unit::Dmau, Unit::Scu].into_iter().find_map(|unit| {
+            if self.internal_register(OPSEL) & unit.enable() == 0 {
+                return None
+            }
+            let lower = self.internal_register(unit.lower_address());
+            let base = u16::from_le_bytes([lower, self.internal_register(OPHA)]) & 0xFFFE;
+            let offset = port.wrapping_sub(base);
+            let register = if consecutive {
+                offset
+            } else if offset & 1 == 0 {
+                offset / 2
+            } else {
+                return None
+            };
+            (register < unit.registers()).then_some((unit, register as u8))
+        })
+    }
+
+    pub(crate) fn read_peripheral (&mut self, unit: Unit, register: u8) -> u8 {
+        match unit {
+            Unit::Icu => self.icu.read(register),
+            Unit::Tcu => {
+                self.update_tcu();
+                self.tcu.read(register)
+            },
+            Unit::Dmau => self.dmau.read(register, self.dma_8237()),
+            Unit::Scu => {
+                self.update_tcu();
+                let data = self.scu.read(register);
+                self.update_irq(SERIAL_IRQ);
+                data
+            },
+        }
+    }
+
+    pub(crate) fn write_peripheral (&mut self, unit: Unit, register: u8, data: u8) {
+        match unit {
+            Unit::Icu => self.icu.write(register, data),
+            Unit::Tcu => {
+                self.update_tcu();
+                self.tcu.write(register, data);
+                self.update_irq(TIMER_IRQ);
+            },
+            Unit::Dmau => self.dmau.write(register, data, self.dma_8237()),
+            Unit::Scu => {
+                self.update_tcu();
+                self.scu.write(register, data);
+                self.update_irq(SERIAL_IRQ);
+            },
+        }
+    }
+
+}
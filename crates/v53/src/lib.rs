@@ -1,21 +1,55 @@
-/// <https://datasheets.chipdb.org/NEC/V20-V30/U11301EJ5V0UMJ1.PDF>
+//! <https://datasheets.chipdb.org/NEC/V20-V30/U11301EJ5V0UMJ1.PDF>
 
 mod bit;
 mod reg;
 mod flag;
+mod alu;
+mod bcd;
+mod bitop;
+mod string;
+mod ctrl;
+mod transfer;
+mod xa;
+mod timing;
+mod io;
+mod icu;
+mod tcu;
+mod dma;
+mod scu;
 mod inst;
+mod exec;
+mod dasm;
 mod dump;
+mod error;
+mod json;
+mod vector;
+mod trace;
+mod state;
 #[cfg(test)] mod test;
 
-pub(crate) use self::{bit::*, reg::*, flag::*, inst::*};
+pub(crate) use self::{bit::*, reg::*, alu::*, bitop::*, string::*, ctrl::*, xa::*, timing::*};
+pub use self::{inst::*, error::*, vector::*, trace::*, state::*, io::*, icu::*, tcu::*, dma::*, scu::*};
+pub use mpcemu_core::{Bus, Memory, MemoryMap, Unmapped};
 
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 
 pub struct CPU {
-    memory:   [u8;0x100000],
-    extended: [u8;0xA0000],
-    ports:    [u8;0x10000],
+    /// Memory and devices outside of the CPU
+    bus:      Box<dyn Bus>,
+    /// Internal I/O registers (FF00H-FFFFH)
     internal: [u8;0x100],
+    /// Interrupt control unit
+    icu:      Icu,
+    /// Timer/counter unit
+    tcu:      Tcu,
+    /// DMA unit
+    dmau:     Dmau,
+    /// Serial control unit
+    scu:      Scu,
+    /// Writes to the internal I/O block where there is no register
+    unused_writes: Vec<(u8, u8)>,
+    /// Levels of the external interrupt request inputs (INTP0-INTP7)
+    irq_pins: u8,
 
     aw:  u16,
     bw:  u16,
@@ -36,14 +70,44 @@ pub struct CPU {
     iy:  u16,
 
     pub segment: Option<Segment>,
+    /// PC of the instruction being executed, from its first prefix, while
+    /// its prefixes are executed a step at a time
+    instruction_start: Option<u16>,
     opcode:      u8,
     pub clock:   u64,
 
-    outputs: BTreeMap<u16, Box<dyn Fn(&CPU)->()>>,
+    /// Number of instruction bytes in the prefetch queue, starting at PS:PC
+    queue: u16,
+    /// Clocks of the current instruction beyond its nominal cycle count:
+    /// prefetch stalls, wait states and split word transfers
+    pending: u64,
+    /// Clocks of the current instruction spent in bus cycles
+    bus_clocks: u64,
+
+    /// Vector of a maskable interrupt waiting to be accepted
+    interrupt_request: Option<u8>,
+    /// Whether HALT has suspended execution until the next interrupt
+    halted: bool,
+    /// Whether undefined opcodes raise the invalid opcode trap
+    trap_undefined: bool,
+    /// Addresses at which [CPU::step] stops before executing
+    breakpoints: BTreeSet<u32>,
+    /// Breakpoint just reported, which doesn't stop execution again
+    /// until PC leaves its address
+    breakpoint_hit: Option<u32>,
+    /// Physical addresses and values of memory writes, while logging
+    write_log: Option<Vec<(u32, u8)>>,
 }
 
+/// Size of the memory image accepted by [CPU::new].
+pub const MEMORY_SIZE: usize = 0x100000;
+
+/// Steps allowed by [CPU::step_instruction] for a single instruction,
+/// including prefixes and every iteration of a repeated string instruction.
+const MAX_STEPS: usize = 0x20000;
+
 /// Segment override
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Segment {
     /// Use data segment 0
     DS0,
@@ -55,21 +119,38 @@ pub enum Segment {
     SS,
 }
 
+/// Register or memory operand, as encoded by the mode and mem fields of a ModRM byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    /// Register number (byte or word register depending on instruction width)
+    Register(u8),
+    /// Memory operand, with displacement already read from the instruction stream
+    Memory { mode: u8, mem: u8, disp: u16 },
+}
+
 impl CPU {
 
-    pub fn new (image: Vec<u8>) -> Self {
-        let mut memory = [0x00;0x100000];
-        if image.len() > memory.len() {
-            panic!("Memory image too big (0x{:X}/0x{:X} bytes)", image.len(), memory.len());
+    /// Create a CPU attached to 1 MB of flat memory initialized from `image`.
+    pub fn new (image: Vec<u8>) -> Result<Self, Error> {
+        if image.len() > MEMORY_SIZE {
+            return Err(Error::ImageTooBig { size: image.len(), capacity: MEMORY_SIZE })
         }
-        for i in 0..image.len() {
-            memory[i] = image[i];
-        }
-        Self {
-            memory,
-            extended: [0x00;0xA0000],
-            ports:    [0x00;0x10000],
+        Ok(Self::with_bus(Box::new(Memory::new(MEMORY_SIZE, &image))))
+    }
+
+    /// Create a CPU attached to the given memory and devices.
+    /// Page registers start out mapping the first megabyte one to one,
+    /// and every bus cycle has the maximum of 7 wait states.
+    pub fn with_bus (bus: Box<dyn Bus>) -> Self {
+        let mut cpu = Self {
+            bus,
             internal: [0x00;0x100],
+            icu:      Icu::default(),
+            tcu:      Tcu::default(),
+            dmau:     Dmau::default(),
+            scu:      Scu::default(),
+            unused_writes: vec![],
+            irq_pins: 0x00,
             aw:       0x0000,
             bw:       0x0000,
             cw:       0x0000,
@@ -85,42 +166,241 @@ impl CPU {
             ix:       0x0000,
             iy:       0x0000,
             segment:  None,
+            instruction_start: None,
             opcode:   0xF1,
             clock:    0x0000,
-            outputs:  BTreeMap::new(),
+            queue:    0,
+            pending:  0,
+            bus_clocks: 0,
+            interrupt_request: None,
+            halted:   false,
+            trap_undefined: false,
+            breakpoints:    BTreeSet::new(),
+            breakpoint_hit: None,
+            write_log:      None,
+        };
+        cpu.internal[(IDB - 0xFF00) as usize] = 0xFF;
+        for page in 0..PAGES {
+            cpu.set_page_register(page, page as u16);
         }
-    }
-
-    /// Read and execute the next instruction in the program
-    pub fn step (&mut self, debug: bool) {
-        let (addr, pc, (name, bytes, instruction)) = self.fetch_instruction();
+        cpu.output_u8(WCY1, 0x77);
+        cpu.output_u8(WCY2, 0x77);
+        cpu
+    }
+
+    /// Read and execute the next instruction in the program,
+    /// or accept a pending interrupt if it's enabled.
+    /// Returns the reason if the instruction couldn't be completed,
+    /// or if the CPU is halted.
+    pub fn step (&mut self, debug: bool) -> Result<(), Stop> {
+        self.step_part(debug)?;
+        if self.halted {
+            return Err(Stop::Halted)
+        }
+        Ok(())
+    }
+
+    /// Like [CPU::step], without stopping when the step halted the CPU.
+    /// Returns whether an instruction was completed, or an interrupt
+    /// accepted, rather than a prefix or an iteration of a repetition.
+    fn step_part (&mut self, debug: bool) -> Result<bool, Stop> {
+        self.update_tcu();
+        self.service_dma();
+        if let Some(vector) = self.accept_interrupt() {
+            if debug {
+                print!("\n{:10} interrupt {vector:02X}", self.clock);
+            }
+            self.halted = false;
+            let cycles = self.interrupt(vector);
+            self.advance_clock(cycles);
+            return Ok(true)
+        }
+        if self.halted {
+            self.clock += 1;
+            return Err(Stop::Halted)
+        }
+        let addr = self.program_address();
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoint_hit = None;
+        } else if self.breakpoint_hit != Some(addr) {
+            self.breakpoint_hit = Some(addr);
+            return Err(Stop::Breakpoint(addr))
+        }
+        let (addr, pc, bytes, instruction, length) = self.fetch_instruction();
+        self.instruction_start.get_or_insert(pc);
         if debug {
             self.dump_state(pc);
-            self.dump_instruction(addr, &name, &bytes);
+            self.dump_instruction(addr, &instruction, &bytes[..length]);
+        }
+        let complete = self.execute_instruction(instruction);
+        if let Err(stop) = complete {
+            // Leave PC at the instruction that couldn't be executed
+            self.set_pc(pc);
+            if self.instruction_start == Some(pc) {
+                self.instruction_start = None;
+            }
+            self.cancel_instruction();
+            match stop {
+                Stop::Undefined(_) | Stop::UndefinedExtended(_) if self.trap_undefined => {
+                    self.segment = None;
+                    self.instruction_start = None;
+                    let cycles = self.interrupt(VECTOR_INVALID_OPCODE);
+                    self.advance_clock(cycles);
+                },
+                stop => return Err(stop)
+            }
+        }
+        if let Some(addr) = self.bus.take_fault() {
+            return Err(Stop::Fault(addr))
+        }
+        // A trapped undefined instruction is complete
+        Ok(complete.unwrap_or(true))
+    }
+
+    /// Step until a whole instruction has been executed, including its
+    /// prefixes and every iteration of a repeated string instruction,
+    /// or an interrupt has been accepted.
+    /// Halting is fine, but waiting for an interrupt while halted returns
+    /// [Stop::Halted].
+    pub fn step_instruction (&mut self) -> Result<(), Stop> {
+        for _ in 0..MAX_STEPS {
+            if self.step_part(false)? {
+                break
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop before executing the instruction at this address.
+    pub fn set_breakpoint (&mut self, addr: u32) {
+        self.breakpoints.insert(addr);
+    }
+
+    /// Remove a breakpoint set with [CPU::set_breakpoint].
+    pub fn clear_breakpoint (&mut self, addr: u32) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Whether undefined opcodes raise the invalid opcode trap (vector 6,
+    /// returning to the undefined instruction) instead of stopping with
+    /// [Stop::Undefined] or [Stop::UndefinedExtended].
+    pub fn set_trap_undefined (&mut self, value: bool) {
+        self.trap_undefined = value;
+    }
+
+    /// Request a maskable interrupt. It is accepted before the next
+    /// instruction, once IE is set and no prefix is pending.
+    pub fn request_interrupt (&mut self, vector: u8) {
+        self.interrupt_request = Some(vector);
+    }
+
+    /// Drive an interrupt request input (INTP0-INTP7) of the ICU high or low.
+    pub fn set_irq (&mut self, line: u8, high: bool) {
+        let mask = 1 << (line & 7);
+        self.irq_pins = if high { self.irq_pins | mask } else { self.irq_pins & !mask };
+        self.update_irq(line & 7);
+    }
+
+    /// Pass the level of an interrupt request input on to the ICU:
+    /// the external input, or the internal source sharing it.
+    pub(crate) fn update_irq (&mut self, line: u8) {
+        let internal = match line {
+            TIMER_IRQ => self.tcu.counter(0).out(),
+            SERIAL_IRQ => self.scu.interrupt(),
+            _ => false
+        };
+        self.icu.set_line(line, self.irq_pins & (1 << line) != 0 || internal);
+    }
+
+    /// Interrupt control unit
+    pub fn icu (&self) -> &Icu {
+        &self.icu
+    }
+
+    /// Whether the CPU is suspended by HALT, waiting for an interrupt.
+    pub fn halted (&self) -> bool {
+        self.halted
+    }
+
+    fn accept_interrupt (&mut self) -> Option<u8> {
+        // Not between an instruction and its prefixes
+        if !self.ie() || self.segment.is_some() || self.instruction_start.is_some() {
+            return None
+        }
+        if let Some(vector) = self.interrupt_request.take() {
+            return Some(vector)
+        }
+        match self.icu.acknowledge()? {
+            Acknowledge::Vector(vector) => Some(vector),
+            Acknowledge::Cascade(input) => Some(self.bus.interrupt_acknowledge(input)),
+        }
+    }
+
+    /// Bytes at PS:PC, read without side effects.
+    fn peek_bytes (&self) -> [u8;MAX_INSTRUCTION_LENGTH] {
+        let mut bytes = [0u8;MAX_INSTRUCTION_LENGTH];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.peek_byte(linear_address(self.ps, self.pc.wrapping_add(i as u16)));
         }
-        self.execute_instruction(instruction)
+        bytes
     }
 
+    /// Decode the instruction at PS:PC without fetching it.
+    /// Returns the instruction and its length.
+    pub fn peek_instruction (&self) -> (Instruction, usize) {
+        Instruction::decode(&self.peek_bytes())
+            .expect("instruction longer than MAX_INSTRUCTION_LENGTH")
+    }
+
+    /// Decode the instruction at PS:PC, take it from the prefetch queue and
+    /// advance PC past it. Returns the address and PC of the instruction, the
+    /// bytes it was decoded from, the instruction itself, and its length.
     pub fn fetch_instruction (&mut self) -> (
-        u32, u16, (String, Vec<u8>, Box<dyn Fn(&mut CPU)->u64>)
+        u32, u16, [u8;MAX_INSTRUCTION_LENGTH], Instruction, usize
     ) {
-        let addr   = self.program_address();
-        let pc     = self.pc();
-        let opcode = self.next_u8();
-        self.opcode = opcode;
-        (addr, pc, v53_instruction(self, opcode))
-    }
-
-    pub fn execute_instruction (&mut self, instruction: Box<dyn Fn(&mut CPU)->u64>) {
-        self.clock += instruction(self);
-        // Reset segment override, except if it was just set:
-        // FIXME: make this a part of instruction decoding
-        let opcode = self.opcode;
-        if !(
-            (opcode == 0x26) || (opcode == 0x2E) || (opcode == 0x36) || (opcode == 0x3E)
-        ) {
-            self.segment = None
+        let addr = self.program_address();
+        let pc   = self.pc();
+        let ps   = self.ps();
+        let address = |i: usize| linear_address(ps, pc.wrapping_add(i as u16));
+        let bytes = self.peek_bytes();
+        let (instruction, length) = Instruction::decode(&bytes)
+            .expect("instruction longer than MAX_INSTRUCTION_LENGTH");
+        // Only the bytes of the instruction are read from the bus
+        for i in 0..length {
+            self.get_byte(address(i));
+        }
+        self.dequeue(length as u16);
+        self.opcode = bytes[0];
+        self.set_pc(pc.wrapping_add(length as u16));
+        (addr, pc, bytes, instruction, length)
+    }
+
+    /// Execute a decoded instruction and add its cycles to the clock.
+    /// Returns whether the instruction is complete: not a prefix, nor an
+    /// iteration after which a repetition continues.
+    pub fn execute_instruction (&mut self, instruction: Instruction) -> Result<bool, Stop> {
+        let next = self.program_address();
+        let cycles = self.execute(instruction)?;
+        let addr = self.program_address();
+        // A repetition that continues has rewound PC
+        let repeating = matches!(instruction, Instruction::Repeat { .. }) && addr != next;
+        if addr != next {
+            if repeating {
+                // A repetition that continues keeps its bytes in the queue
+                self.queue += next.wrapping_sub(addr) as u16;
+            } else {
+                self.flush_queue();
+            }
+        }
+        self.advance_clock(cycles);
+        // Reset segment override, except if it was just set
+        // (a bus lock prefix doesn't consume it either)
+        let prefix = matches!(instruction, Instruction::Segment(_) | Instruction::Prefix(_));
+        if !prefix {
+            self.segment = None;
+            self.instruction_start = None;
         }
+        Ok(!prefix && !repeating)
     }
 
     /// Get the opcode that is currently being executed
@@ -129,230 +409,86 @@ impl CPU {
     }
 
     pub fn jump_i8 (&mut self, displace: i8) {
-        self.pc = ((self.pc as i16) + (displace as i16)) as u16;
+        self.jump_i16(displace as i16);
     }
 
+    /// Relative branch, which empties the prefetch queue
+    /// even if it lands on the next instruction.
     pub fn jump_i16 (&mut self, displace: i16) {
-        self.pc = ((self.pc as i16) + displace) as u16;
-    }
-
-    pub fn parse_effective_address (&mut self, mode: u8, mem: u8)
-        -> (String, Vec<u8>, Box<dyn Fn(&mut CPU)->u16>)
-    {
-        match mode {
-            0b00 => match mem {
-                0b000 => (
-                    "BW + IX".into(), vec![],
-                    Box::new(|cpu: &mut CPU|{cpu.bw() + cpu.ix()})
-                ),
-                0b001 => (
-                    "BW + IY".into(), vec![],
-                    Box::new(|cpu: &mut CPU|{cpu.bw() + cpu.iy()})
-                ),
-                0b010 => (
-                    "BP + IX".into(), vec![],
-                    Box::new(|cpu: &mut CPU|{cpu.bp() + cpu.ix()})
-                ),
-                0b011 => (
-                    "BP + IY".into(), vec![],
-                    Box::new(|cpu: &mut CPU|{cpu.bp() + cpu.iy()})
-                ),
-                0b100 => (
-                    "IX".into(), vec![],
-                    Box::new(|cpu: &mut CPU|{cpu.ix()})
-                ),
-                0b101 => (
-                    "IY".into(), vec![],
-                    Box::new(|cpu: &mut CPU|{cpu.iy()})
-                ),
-                0b110 => {
-                    let direct = self.next_u16();
-                    (
-                        format!("{direct}"), direct.to_le_bytes().to_vec(),
-                        Box::new(move |_|{direct})
-                    )
-                },
-                0b111 => (
-                    "BW".into(), vec![],
-                    Box::new(|cpu: &mut CPU|{cpu.bw()})
-                ),
-                _ => panic!("invalid memory inner mode {:b}", mem)
-            },
-            0b01 => {
-                let disp  = self.next_u8();
-                let bytes = vec![disp];
-                let disp  = disp as u16;
-                match mem {
-                    0b000 => (
-                        format!("BW + IX + {disp:02X}"), bytes,
-                        Box::new(move |cpu: &mut CPU|{cpu.bw() + cpu.ix() + disp})
-                    ),
-                    0b001 => (
-                        format!("BW + IY + {disp:02X}"), bytes,
-                        Box::new(move |cpu: &mut CPU|{cpu.bw() + cpu.iy() + disp})
-                    ),
-                    0b010 => (
-                        format!("BP + IX + {disp:02X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bp() + cpu.ix() + disp})
-                    ),
-                    0b011 => (
-                        format!("BP + IY + {disp:02X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bp() + cpu.iy() + disp})
-                    ),
-                    0b100 => (
-                        format!("IX + {disp:02X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.ix() + disp})
-                    ),
-                    0b101 => (
-                        format!("IY + {disp:02X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.iy() + disp})
-                    ),
-                    0b110 => (
-                        format!("BP + {disp:02X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bp() + disp})
-                    ),
-                    0b111 => (
-                        format!("BW + {disp:02X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bw() + disp})
-                    ),
-                    _ => panic!("invalid memory inner mode {:b}", mem)
-                }
-            },
-            0b10 => {
-                let disp  = self.next_u16();
-                let bytes = disp.to_le_bytes().to_vec();
-                match mem {
-                    0b000 => (
-                        format!("BW + IX + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bw() + cpu.ix() + disp})
-                    ),
-                    0b001 => (
-                        format!("BW + IY + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bw() + cpu.iy() + disp})
-                    ),
-                    0b010 => (
-                        format!("BP + IX + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bp() + cpu.ix() + disp})
-                    ),
-                    0b011 => (
-                        format!("BP + IY + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bp() + cpu.iy() + disp})
-                    ),
-                    0b100 => (
-                        format!("IX + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.ix() + disp})
-                    ),
-                    0b101 => (
-                        format!("IY + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.iy() + disp})
-                    ),
-                    0b110 => (
-                        format!("BP + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bp() + disp})
-                    ),
-                    0b111 => (
-                        format!("BW + {disp:04X}"), bytes,
-                        Box::new(move|cpu: &mut CPU|{cpu.bw() + disp})
-                    ),
-                    _ => panic!("invalid memory inner mode {:b}", mem)
-                }
+        self.pc = self.pc.wrapping_add(displace as u16);
+        self.flush_queue();
+    }
+
+    /// Default segment of a memory operand and its offset within it,
+    /// or `None` for registers. Addresses based on BP are in SS, the
+    /// others in DS0.
+    pub fn operand_address (&self, operand: Operand) -> Option<(Segment, u16)> {
+        let (mode, mem, disp) = match operand {
+            Operand::Register(_) => return None,
+            Operand::Memory { mode, mem, disp } => (mode, mem, disp)
+        };
+        if mode == 0b00 && mem == 0b110 {
+            return Some((Segment::DS0, disp))
+        }
+        let base = match mem {
+            0b000 => self.bw().wrapping_add(self.ix()),
+            0b001 => self.bw().wrapping_add(self.iy()),
+            0b010 => self.bp().wrapping_add(self.ix()),
+            0b011 => self.bp().wrapping_add(self.iy()),
+            0b100 => self.ix(),
+            0b101 => self.iy(),
+            0b110 => self.bp(),
+            0b111 => self.bw(),
+            _ => unreachable!("mem {mem:b}")
+        };
+        let segment = if matches!(mem, 0b010 | 0b011 | 0b110) { Segment::SS } else { Segment::DS0 };
+        Some((segment, base.wrapping_add(disp)))
+    }
+
+    /// Read a byte or word operand.
+    pub fn read_operand (&mut self, word: bool, operand: Operand) -> u16 {
+        match (operand, self.operand_address(operand)) {
+            (Operand::Register(reg), _) => self.get_register(word, reg),
+            (_, Some((segment, addr))) => {
+                let segment = self.operand_segment(segment);
+                if word { self.read_segment_u16(segment, addr) } else { self.read_segment_u8(segment, addr) as u16 }
             },
-            0b11 => panic!(),
             _ => unreachable!()
         }
     }
 
-    #[inline]
-    pub fn memory_address (&mut self, mode: u8, mem: u8) -> u32 {
-        match mode {
-            0b00 => self.memory_address_00(mem),
-            0b01 => {
-                let displace = self.next_u8();
-                self.memory_address_01(mem, displace)
-            },
-            0b10 => {
-                let displace = self.next_u16();
-                self.memory_address_10(mem, displace)
+    /// Write a byte or word operand.
+    pub fn write_operand (&mut self, word: bool, operand: Operand, value: u16) {
+        match (operand, self.operand_address(operand)) {
+            (Operand::Register(reg), _) => self.set_register(word, reg, value),
+            (_, Some((segment, addr))) => {
+                let segment = self.operand_segment(segment);
+                if word {
+                    self.write_segment_u16(segment, addr, value)
+                } else {
+                    self.write_segment_u8(segment, addr, value as u8)
+                }
             },
-            _ => panic!("invalid memory outer mode {:b}", mode)
-        }
-    }
-
-    //#[inline]
-    //pub fn memory_address_dasm (&mut self, dasm: &[u8], mode: u8, mem: u8) -> (u16, Vec<u8>) {
-        //let dasm = Vec::from(dasm);
-        //match mode {
-            //0b00 => self.memory_address_00(mem),
-            //0b01 => {
-                //let displace = self.next_u8();
-                //self.memory_address_01(mem, displace)
-            //},
-            //0b10 => {
-                //let displace = self.next_u16();
-                //self.memory_address_10(mem, displace)
-            //},
-            //_ => panic!("invalid memory outer mode {:b}", mode)
-        //}
-    //}
-
-    #[inline]
-    pub fn memory_address_00 (&mut self, mem: u8) -> u32 {
-        match mem {
-            0b000 => self.bw() as u32 + self.ix() as u32,
-            0b001 => self.bw() as u32 + self.iy() as u32,
-            0b010 => self.bp() as u32 + self.ix() as u32,
-            0b011 => self.bp() as u32 + self.iy() as u32,
-            0b100 => self.ix() as u32,
-            0b101 => self.iy() as u32,
-            0b110 => self.next_u16() as u32,
-            0b111 => self.bw() as u32,
-            _ => panic!("invalid memory inner mode {:b}", mem)
-        }
-    }
-
-    #[inline]
-    pub fn memory_address_01 (&self, mem: u8, displace: u8) -> u32 {
-        match mem {
-            0b000 => self.bw() as u32 + self.ix() as u32 + displace as u32,
-            0b001 => self.bw() as u32 + self.iy() as u32 + displace as u32,
-            0b010 => self.bp() as u32 + self.ix() as u32 + displace as u32,
-            0b011 => self.bp() as u32 + self.iy() as u32 + displace as u32,
-            0b100 => self.ix() as u32 + displace as u32,
-            0b101 => self.iy() as u32 + displace as u32,
-            0b110 => self.bp() as u32 + displace as u32,
-            0b111 => self.bw() as u32 + displace as u32,
-            _ => panic!("invalid memory inner mode {:b}", mem)
-        }
-    }
-
-    #[inline]
-    pub fn memory_address_10 (&self, mem: u8, displace: u16) -> u32 {
-        match mem {
-            0b000 => self.bw() as u32 + self.ix() as u32 + displace as u32,
-            0b001 => self.bw() as u32 + self.iy() as u32 + displace as u32,
-            0b010 => self.bp() as u32 + self.ix() as u32 + displace as u32,
-            0b011 => self.bp() as u32 + self.iy() as u32 + displace as u32,
-            0b100 => self.ix() as u32 + displace as u32,
-            0b101 => self.iy() as u32 + displace as u32,
-            0b110 => self.bp() as u32 + displace as u32,
-            0b111 => self.bw() as u32 + displace as u32,
-            _ => panic!("invalid memory inner mode {:b}", mem)
+            _ => unreachable!()
         }
     }
 
-    /// Read-only handle to memory
-    pub fn memory (&self) -> &[u8] {
-        &self.memory
+    /// Read the two words of a memory operand, such as a far pointer
+    /// or a pair of bounds.
+    pub(crate) fn read_operand_pair (&mut self, operand: Operand) -> (u16, u16) {
+        let (segment, addr) = self.operand_address(operand).expect("memory operand");
+        let segment = self.operand_segment(segment);
+        (self.read_segment_u16(segment, addr), self.read_segment_u16(segment, addr.wrapping_add(2)))
     }
 
-    /// Read-only handle to extended memory
-    pub fn extended (&self) -> &[u8] {
-        &self.extended
+    /// Handle to memory and devices
+    pub fn bus (&self) -> &dyn Bus {
+        self.bus.as_ref()
     }
 
-    /// Read-only handle to IO ports memory
-    pub fn ports (&self) -> &[u8] {
-        &self.ports
+    /// Mutable handle to memory and devices
+    pub fn bus_mut (&mut self) -> &mut dyn Bus {
+        self.bus.as_mut()
     }
 
     /// Read-only handle to internal IO memory
@@ -360,200 +496,197 @@ impl CPU {
         &self.internal
     }
 
-    pub fn xa (&self) -> bool {
-        self.ports[0xff80] > 0
+    /// Read byte from memory without side effects
+    pub fn peek_byte (&self, addr: u32) -> u8 {
+        self.bus.peek(self.physical_address(addr))
     }
 
-    pub fn set_xa (&mut self, value: bool) {
-        self.ports[0xff80] = if value { 1 } else { 0 };
+    pub fn get_byte (&mut self, addr: u32) -> u8 {
+        self.bus.read(self.physical_address(addr))
     }
 
-    pub fn get_byte (&self, addr: u32) -> u8 {
-        if addr < 0xA0000 {
-            if self.xa() {
-                self.extended[addr as usize]
-            } else {
-                self.memory[addr as usize]
-            }
-        } else {
-            self.memory[addr as usize]
-        }
+    pub fn set_byte (&mut self, addr: u32, value: u8) {
+        self.write_physical(self.physical_address(addr), value)
     }
 
-    pub fn set_byte (&mut self, addr: u32, value: u8) {
-        if addr < 0xA0000 {
-            if self.xa() {
-                self.extended[addr as usize] = value
-            } else {
-                self.memory[addr as usize] = value
-            }
-        } else {
-            self.memory[addr as usize] = value
+    /// Write byte to a physical address, as logged by [CPU::log_writes].
+    pub(crate) fn write_physical (&mut self, addr: u32, value: u8) {
+        if let Some(log) = self.write_log.as_mut() {
+            log.push((addr, value));
         }
+        self.bus.write(addr, value)
+    }
+
+    /// Start or stop recording memory writes, for [CPU::take_writes].
+    pub fn log_writes (&mut self, enable: bool) {
+        self.write_log = enable.then(Vec::new);
+    }
+
+    /// Physical addresses and values written to memory since the last call,
+    /// in order, while [CPU::log_writes] is enabled.
+    pub fn take_writes (&mut self) -> Vec<(u32, u8)> {
+        self.write_log.as_mut().map(std::mem::take).unwrap_or_default()
     }
 
     /// Program address
     pub fn program_address (&self) -> u32 {
-        ((self.ps as u32) * 0x10) + self.pc as u32
+        linear_address(self.ps, self.pc)
     }
 
     /// Stack address
     pub fn stack_address (&self) -> u32 {
-        ((self.ss as u32) * 0x10) + self.sp as u32
+        linear_address(self.ss, self.sp)
     }
 
-    /// Effective address
-    pub fn effective_address (&self, addr: u32) -> u32 {
-        let segment = match self.segment {
-            None               => self.ds0,
-            Some(Segment::DS0) => self.ds0,
-            Some(Segment::DS1) => self.ds1,
-            Some(Segment::PS)  => self.ps,
-            Some(Segment::SS)  => self.ss
-        } as u32 * 0x10;
-        (segment + addr as u32) % 0xFFFFF
+    /// Segment of memory operands: DS0, unless overridden.
+    fn data_segment (&self) -> u16 {
+        self.operand_segment(Segment::DS0)
     }
 
-    /// Target address (always offset from DS1)
-    pub fn ds1_address (&self, addr: u32) -> u32 {
-        (self.ds1 as u32 * 0x10) + addr as u32
+    /// Segment of a memory operand with this default segment, unless overridden.
+    fn operand_segment (&self, default: Segment) -> u16 {
+        match self.segment.unwrap_or(default) {
+            Segment::DS0 => self.ds0,
+            Segment::DS1 => self.ds1,
+            Segment::PS  => self.ps,
+            Segment::SS  => self.ss
+        }
     }
 
-    pub fn peek_u8 (&mut self) -> u8 {
-        self.get_byte(self.program_address())
+    /// Effective address
+    pub fn effective_address (&self, offset: u16) -> u32 {
+        linear_address(self.data_segment(), offset)
     }
 
-    pub fn peek_i8 (&mut self) -> i8 {
-        self.get_byte(self.program_address()) as i8
+    /// Target address (always offset from DS1)
+    pub fn ds1_address (&self, offset: u16) -> u32 {
+        linear_address(self.ds1, offset)
     }
 
-    pub fn next_u8 (&mut self) -> u8 {
-        let byte = self.peek_u8();
-        self.pc += 1;
-        byte
+    /// Read byte from effective address
+    pub fn read_u8 (&mut self, offset: u16) -> u8 {
+        self.read_segment_u8(self.data_segment(), offset)
     }
 
-    pub fn next_i8 (&mut self) -> i8 {
-        let byte = self.peek_i8();
-        self.pc += 1;
-        byte
+    /// Read word from effective address
+    pub fn read_u16 (&mut self, offset: u16) -> u16 {
+        self.read_segment_u16(self.data_segment(), offset)
     }
 
-    pub fn next_u16 (&mut self) -> u16 {
-        let lo = self.next_u8() as u16;
-        let hi = self.next_u8() as u16;
-        hi << 8 | lo
+    /// Write byte to effective address
+    pub fn write_u8 (&mut self, offset: u16, value: u8) {
+        self.write_segment_u8(self.data_segment(), offset, value)
     }
 
-    pub fn next_i16 (&mut self) -> i16 {
-        let lo = self.next_u8();
-        let hi = self.next_u8();
-        i16::from_le_bytes([lo, hi])
+    /// Write word to effective address
+    pub fn write_u16 (&mut self, offset: u16, value: u16) {
+        self.write_segment_u16(self.data_segment(), offset, value)
     }
 
-    /// Read byte from effective address
-    pub fn read_u8 (&mut self, addr: u32) -> u8 {
-        self.get_byte(self.effective_address(addr))
+    /// Read byte from an offset in a segment
+    pub fn read_segment_u8 (&mut self, segment: u16, offset: u16) -> u8 {
+        let addr = linear_address(segment, offset);
+        self.memory_transfer(addr, false);
+        self.get_byte(addr)
     }
 
-    /// Read word from effective address
-    pub fn read_u16 (&mut self, addr: u32) -> u16 {
-        let lo = self.read_u8(addr);
-        let hi = self.read_u8(addr + 1);
+    /// Read word from an offset in a segment. A word at offset FFFFH
+    /// wraps around to the start of the segment.
+    pub fn read_segment_u16 (&mut self, segment: u16, offset: u16) -> u16 {
+        let addr = linear_address(segment, offset);
+        self.memory_transfer(addr, true);
+        let lo = self.get_byte(addr);
+        let hi = self.get_byte(linear_address(segment, offset.wrapping_add(1)));
         u16::from_le_bytes([lo, hi])
     }
 
-    /// Write byte to effective address
-    pub fn write_u8 (&mut self, addr: u32, value: u8) {
-        let ea = self.effective_address(addr);
-        self.set_byte(ea, value);
+    /// Write byte to an offset in a segment
+    pub fn write_segment_u8 (&mut self, segment: u16, offset: u16, value: u8) {
+        let addr = linear_address(segment, offset);
+        self.memory_transfer(addr, false);
+        self.set_byte(addr, value);
     }
 
-    /// Write word to effective address
-    pub fn write_u16 (&mut self, addr: u32, value: u16) {
-        let ea = self.effective_address(addr);
+    /// Write word to an offset in a segment. A word at offset FFFFH
+    /// wraps around to the start of the segment.
+    pub fn write_segment_u16 (&mut self, segment: u16, offset: u16, value: u16) {
+        let addr = linear_address(segment, offset);
+        self.memory_transfer(addr, true);
         let [lo, hi] = value.to_le_bytes();
-        self.set_byte(ea + 0, lo);
-        self.set_byte(ea + 1, hi);
+        self.set_byte(addr, lo);
+        self.set_byte(linear_address(segment, offset.wrapping_add(1)), hi);
     }
 
-    /// Read byte from input port
-    pub fn input_u8 (&self, addr: u32) -> u8 {
-        self.ports[addr as usize]
+    /// Read byte from input port. The internal I/O block, FF00H-FFFFH
+    /// unless moved by IDB, is internal to the CPU, and so are the
+    /// peripheral units mapped with OPSEL.
+    pub fn input_u8 (&mut self, port: u16) -> u8 {
+        self.io_transfer(port, false);
+        self.read_port(port)
+    }
+
+    fn read_port (&mut self, port: u16) -> u8 {
+        if let Some(offset) = self.internal_offset(port) {
+            self.read_internal(offset)
+        } else if let Some((unit, register)) = self.peripheral(port) {
+            self.read_peripheral(unit, register)
+        } else {
+            self.bus.input(port)
+        }
     }
 
     /// Read word from input port
-    pub fn input_u16 (&self, addr: u32) -> u16 {
-        let lo = self.input_u8(addr) as u16;
-        let hi = self.input_u8(addr + 1) as u16;
-        hi << 8 | lo
+    pub fn input_u16 (&mut self, port: u16) -> u16 {
+        self.io_transfer(port, true);
+        let lo = self.read_port(port);
+        let hi = self.read_port(port.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
     }
 
-    pub fn on_output (&mut self, addr: u16, callback: Box<dyn Fn(&CPU)->()>) {
-        self.outputs.insert(addr, callback);
+    /// Write byte to output port. The internal I/O block, FF00H-FFFFH
+    /// unless moved by IDB, is internal to the CPU, and so are the
+    /// peripheral units mapped with OPSEL.
+    pub fn output_u8 (&mut self, port: u16, data: u8) {
+        self.io_transfer(port, false);
+        self.write_port(port, data)
     }
 
-    /// Write byte to input port
-    pub fn output_u8 (&mut self, addr: u16, data: u8) {
-        self.ports[addr as usize] = data;
-        if let Some(callback) = self.outputs.get(&addr) {
-            callback(&self);
+    fn write_port (&mut self, port: u16, data: u8) {
+        if let Some(offset) = self.internal_offset(port) {
+            self.write_internal(offset, data)
+        } else if let Some((unit, register)) = self.peripheral(port) {
+            self.write_peripheral(unit, register, data)
+        } else {
+            self.bus.output(port, data)
         }
     }
 
-    /// Write byte to output port
-    pub fn output_u16 (&mut self, addr: u16, data: u16) {
+    /// Write word to output port
+    pub fn output_u16 (&mut self, port: u16, data: u16) {
+        self.io_transfer(port, true);
         let [lo, hi] = data.to_le_bytes();
-        self.output_u8(addr + 0, lo);
-        self.output_u8(addr + 1, hi);
+        self.write_port(port, lo);
+        self.write_port(port.wrapping_add(1), hi);
     }
 
     pub fn push_u16 (&mut self, data: u16) {
-        //panic!("push {data}");
-        self.set_sp(self.sp() - 2);
-        let sp = self.stack_address() as usize;
-        let [lo, hi] = data.to_le_bytes();
-        self.memory[sp + 0] = lo;
-        self.memory[sp + 1] = hi;
-        //self.dump_stack(4);
+        self.set_sp(self.sp().wrapping_sub(2));
+        self.write_segment_u16(self.ss, self.sp, data);
     }
 
     pub fn pop_u16 (&mut self) -> u16 {
-        let sp = self.stack_address() as usize;
-        let lo = self.memory[sp + 0];
-        let hi = self.memory[sp + 1];
-        self.set_sp(self.sp() + 2);
-        //self.dump_stack(4);
-        u16::from_le_bytes([lo, hi])
+        let data = self.read_segment_u16(self.ss, self.sp);
+        self.set_sp(self.sp().wrapping_add(2));
+        data
     }
 
 }
 
+/// Linear address of an offset in a segment. Addresses past the end of
+/// the megabyte wrap around to its start.
 #[inline]
-pub fn get_mode_reg_mem (cpu: &mut CPU) -> [u8;4] {
-    let arg  = cpu.next_u8();
-    let mode = (arg & B_MODE) >> 6;
-    let reg  = (arg & B_REG)  >> 3;
-    let mem  = (arg & B_MEM)  >> 0;
-    [arg, mode, reg, mem]
-}
-
-#[inline]
-pub fn get_mode_sreg_mem (cpu: &mut CPU) -> [u8;4] {
-    let arg  = cpu.next_u8();
-    let mode = (arg & B_MODE) >> 6;
-    let sreg = (arg & B_SREG) >> 3;
-    let mem  = (arg & B_MEM)  >> 0;
-    [arg, mode, sreg, mem]
-}
-
-#[inline]
-pub fn get_mode_code_mem (cpu: &mut CPU) -> [u8;4] {
-    let arg  = cpu.next_u8();
-    let mode = (arg & B_MODE) >> 6;
-    let code = (arg & B_REG)  >> 3;
-    let mem  = (arg & B_MEM)  >> 0;
-    [arg, mode, code, mem]
+pub fn linear_address (segment: u16, offset: u16) -> u32 {
+    ((segment as u32) << 4).wrapping_add(offset as u32) & 0xFFFFF
 }
 
 #[inline]
@@ -567,31 +700,3 @@ pub fn sign_extend_32 (data: u32, size: u32) -> i32 {
     assert!(size > 0 && size <= 32);
     ((data << (32 - size)) as i32) >> (32 - size)
 }
-
-#[inline]
-pub fn get_source_word (state: &mut CPU, arg: u8) -> u16 {
-    let mode = (arg & B_MODE) >> 6;
-    let mem  = arg & B_MEM;
-    match mode {
-        0b11 => state.get_register_u16(mem),
-        _ => {
-            let addr = state.memory_address(mode, mem);
-            state.read_u16(addr)
-        }
-    }
-}
-
-#[inline]
-pub fn set_source_word (state: &mut CPU, arg: u8, val: u16) {
-    let mode = (arg & B_MODE) >> 6;
-    let mem  = arg & B_MEM;
-    match mode {
-        0b11 => {
-            state.set_register_u16(mem, val);
-        },
-        _ => {
-            let addr = state.memory_address(mode, mem);
-            state.write_u16(addr, val);
-        }
-    }
-}
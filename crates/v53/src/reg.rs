@@ -58,6 +58,16 @@ impl CPU {
         }
     }
 
+    /// Read a byte or word register.
+    pub fn get_register (&self, word: bool, reg: u8) -> u16 {
+        if word { self.get_register_u16(reg) } else { self.get_register_u8(reg) as u16 }
+    }
+
+    /// Write a byte or word register.
+    pub fn set_register (&mut self, word: bool, reg: u8, value: u16) {
+        if word { self.set_register_u16(reg, value) } else { self.set_register_u8(reg, value as u8) }
+    }
+
     pub fn get_segment_register (&self, sreg: u8) -> u16 {
         match sreg {
             0b00 => self.ds1,
@@ -85,8 +95,6 @@ macro_rules! define_general_purpose_register {
         $w:ident $w_set:ident
         $h:ident $h_set:ident
         $l:ident $l_set:ident
-        $inc:ident
-        $dec:ident
     ) => {
         $(#[$attr])*
         impl CPU {
@@ -109,30 +117,6 @@ macro_rules! define_general_purpose_register {
                 self.$w = u16::from_le_bytes([value, self.$h()])
             }
         }
-
-        #[inline]
-        pub fn $inc (state: &mut CPU) -> u64 {
-            let value = state.$w();
-            let (result, unsigned_overflow) = value.overflowing_add(1);
-            let (_, signed_overflow) = (value as i16).overflowing_add(1);
-            state.$w_set(result);
-            state.set_pzs(result);
-            state.set_cy(unsigned_overflow);
-            state.set_v(signed_overflow);
-            2
-        }
-
-        #[inline]
-        pub fn $dec (state: &mut CPU) -> u64 {
-            let value = state.$w();
-            let (result, unsigned_overflow) = value.overflowing_sub(1);
-            let (_, signed_overflow) = (value as i16).overflowing_sub(1);
-            state.$w_set(result);
-            state.set_pzs(result);
-            state.set_cy(unsigned_overflow);
-            state.set_v(signed_overflow);
-            2
-        }
     }
 }
 
@@ -150,7 +134,7 @@ define_general_purpose_register!(
     ///   - Byte input/output
     ///   - BCD rotate
     ///   - Data exchange
-    aw set_aw ah set_ah al set_al inc_aw dec_aw
+    aw set_aw ah set_ah al set_al
 );
 
 define_general_purpose_register!(
@@ -158,7 +142,7 @@ define_general_purpose_register!(
     ///
     /// - BW is default for:
     ///   - Data exchange (table reference)
-    bw set_bw bh set_bh bl set_bl inc_bw dec_bw
+    bw set_bw bh set_bh bl set_bl
 );
 
 define_general_purpose_register!(
@@ -171,7 +155,7 @@ define_general_purpose_register!(
     ///   - Shift instructions
     ///   - Rotate instructions
     ///   - BCD operation
-    cw set_cw ch set_ch cl set_cl inc_cw dec_cw
+    cw set_cw ch set_ch cl set_cl
 );
 
 define_general_purpose_register!(
@@ -180,15 +164,13 @@ define_general_purpose_register!(
     /// - DW is default for:
     ///   - Word multiplication/division
     ///   - Indirect addressing input/output
-    dw set_dw dh set_dh dl set_dl inc_dw dec_dw
+    dw set_dw dh set_dh dl set_dl
 );
 
 macro_rules! define_special_register {
     (
         $(#[$attr:meta])*
         $w:ident $w_set:ident
-        $inc:ident
-        $dec:ident
     ) => {
         $(#[$attr])*
         impl CPU {
@@ -199,72 +181,48 @@ macro_rules! define_special_register {
                 self.$w = value;
             }
         }
-
-        #[inline]
-        pub fn $inc (state: &mut CPU) -> u64 {
-            let value = state.$w();
-            let (result, unsigned_overflow) = value.overflowing_add(1);
-            let (_, signed_overflow) = (value as i16).overflowing_add(1);
-            state.$w_set(result);
-            state.set_pzs(result);
-            state.set_cy(unsigned_overflow);
-            state.set_v(signed_overflow);
-            2
-        }
-
-        #[inline]
-        pub fn $dec (state: &mut CPU) -> u64 {
-            let value = state.$w();
-            let (result, unsigned_overflow) = value.overflowing_sub(1);
-            let (_, signed_overflow) = (value as i16).overflowing_sub(1);
-            state.$w_set(result);
-            state.set_pzs(result);
-            state.set_cy(unsigned_overflow);
-            state.set_v(signed_overflow);
-            2
-        }
     }
 }
 
 define_special_register!(
     /// The PS register contains the location of the program segment.
-    ps  set_ps   inc_ps  dec_ps
+    ps  set_ps
 );
 define_special_register!(
     /// The SS register contains the location of the stack segment.
-    ss  set_ss   iec_ss  dec_ss
+    ss  set_ss
 );
 define_special_register!(
     /// The DS0 register contains the location of data segment 0.
-    ds0 set_ds0  inc_ds0 dec_ds0
+    ds0 set_ds0
 );
 define_special_register!(
     /// The DS1 register contains the location of data segment 1.
-    ds1 set_ds1  inc_ds1 dec_ds1
+    ds1 set_ds1
 );
 define_special_register!(
     /// The stack pointer register.
-    sp  set_sp   inc_sp  dec_sp
+    sp  set_sp
 );
 define_special_register!(
     /// The block pointer register.
-    bp  set_bp   inc_bp  dec_bp
+    bp  set_bp
 );
 define_special_register!(
     /// The program counter register.
-    pc  set_pc   inc_pc  dec_pc
+    pc  set_pc
 );
 define_special_register!(
     /// The IX register.
-    ix  set_ix   inc_ix  dec_ix
+    ix  set_ix
 );
 define_special_register!(
     /// The IY register.
-    iy  set_iy   inc_iy  dec_iy
+    iy  set_iy
 );
 define_special_register!(
     /// The PSW (program status word) register contains flags.
-    psw set_psw  inc_psw dec_psw
+    psw set_psw
 );
 
 pub fn register_name_u8 (reg: u8) -> &'static str {
@@ -295,6 +253,10 @@ pub fn register_name_u16 (reg: u8) -> &'static str {
     }
 }
 
+pub fn register_name (word: bool, reg: u8) -> &'static str {
+    if word { register_name_u16(reg) } else { register_name_u8(reg) }
+}
+
 pub fn segment_register_name (sreg: u8) -> &'static str {
     match sreg {
         0b00 => "DS1",
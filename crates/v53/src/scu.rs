@@ -0,0 +1,267 @@
+//! Serial control unit: an asynchronous serial interface like the 8251,
+//! with separate mode and interrupt mask registers. Bits are shifted at the
+//! baud rate clock from the output of counter 1 of the TCU. The host side
+//! of the serial line is a pair of byte queues.
+
+use crate::*;
+use std::collections::VecDeque;
+
+/// ICU input requested when the SCU is ready to transmit or has received
+/// a character, unless masked.
+pub const SERIAL_IRQ: u8 = 1;
+
+/// Counter of the TCU whose output is the baud rate clock.
+pub const BAUD_COUNTER: usize = 1;
+
+/// Status bit: the transmit buffer is empty
+pub const TX_READY: u8 = 0x01;
+/// Status bit: a received character waits in the receive buffer
+pub const RX_READY: u8 = 0x02;
+/// Status bit: nothing is being transmitted
+pub const TX_EMPTY: u8 = 0x04;
+/// Status bit: parity error
+pub const PARITY_ERROR: u8 = 0x08;
+/// Status bit: overrun error, a character was received before the
+/// previous one was read
+pub const OVERRUN_ERROR: u8 = 0x10;
+/// Status bit: framing error
+pub const FRAMING_ERROR: u8 = 0x20;
+
+/// Command bit: enable the transmitter
+const COMMAND_TX_ENABLE: u8 = 0x01;
+/// Command bit: enable the receiver
+const COMMAND_RX_ENABLE: u8 = 0x04;
+/// Command bit: clear the error flags
+const COMMAND_ERROR_RESET: u8 = 0x10;
+/// Command bit: reset the unit, except for the mode
+const COMMAND_RESET: u8 = 0x40;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scu {
+    /// Mode register: bits 1-0 baud rate factor (x1, x16, x64),
+    /// bits 3-2 character length (5-8 bits), bit 4 parity enable,
+    /// bit 5 even parity, bits 7-6 stop bits (1, 1.5, 2)
+    mode:     u8,
+    /// Command register
+    command:  u8,
+    /// Interrupt mask: bit 0 masks TxRDY, bit 1 masks RxRDY
+    mask:     u8,
+    /// Error flags of the status register
+    errors:   u8,
+    /// Character written by the CPU, waiting to be transmitted
+    transmit: Option<u8>,
+    /// Character being transmitted, and the baud clocks left
+    shifting: Option<(u8, u32)>,
+    /// Character received, waiting to be read by the CPU
+    received: Option<u8>,
+    /// Baud clocks left to receive the next character from the host
+    receiving: Option<u32>,
+    /// Characters sent by the host, not received yet
+    input:    VecDeque<u8>,
+    /// Characters transmitted, not taken by the host yet
+    output:   VecDeque<u8>,
+}
+
+impl Default for Scu {
+    fn default () -> Self {
+        Self {
+            mode: 0x4E, command: 0x00, mask: 0x03, errors: 0x00,
+            transmit: None, shifting: None, received: None, receiving: None,
+            input: VecDeque::new(), output: VecDeque::new(),
+        }
+    }
+}
+
+impl Scu {
+
+    /// Bits of a character.
+    fn length (&self) -> u32 {
+        5 + ((self.mode >> 2) & 3) as u32
+    }
+
+    /// Baud clocks of a character frame: start bit, data bits, parity
+    /// bit and stop bits, rounding 1.5 stop bits up.
+    fn frame (&self) -> u32 {
+        let parity = (self.mode >> 4) & 1;
+        let stop = match self.mode >> 6 { 0 | 1 => 1, _ => 2 };
+        let factor = match self.mode & 3 { 2 => 16, 3 => 64, _ => 1 };
+        (1 + self.length() + parity as u32 + stop) * factor
+    }
+
+    /// Keep the bits of a character.
+    fn character (&self, data: u8) -> u8 {
+        data & (0xFF >> (8 - self.length()))
+    }
+
+    /// Status register.
+    pub fn status (&self) -> u8 {
+        let mut status = self.errors;
+        if self.transmit.is_none() {
+            status |= TX_READY;
+        }
+        if self.received.is_some() {
+            status |= RX_READY;
+        }
+        if self.transmit.is_none() && self.shifting.is_none() {
+            status |= TX_EMPTY;
+        }
+        status
+    }
+
+    /// Whether an unmasked interrupt is requested.
+    pub fn interrupt (&self) -> bool {
+        let status = self.status();
+        let transmit = self.command & COMMAND_TX_ENABLE != 0 && status & TX_READY != 0;
+        (transmit && self.mask & 1 == 0) || (status & RX_READY != 0 && self.mask & 2 == 0)
+    }
+
+    /// Read the receive buffer (0), status (1), or interrupt mask (3).
+    pub fn read (&mut self, register: u8) -> u8 {
+        match register {
+            0 => self.received.take().unwrap_or(0x00),
+            1 => self.status(),
+            3 => self.mask,
+            _ => 0xFF
+        }
+    }
+
+    /// Write the transmit buffer (0), command (1), mode (2), or interrupt
+    /// mask (3).
+    pub fn write (&mut self, register: u8, data: u8) {
+        match register {
+            0 => {
+                self.transmit = Some(self.character(data));
+                self.start_transmit();
+            },
+            1 => {
+                if data & COMMAND_RESET != 0 {
+                    let (mode, input, output) = (self.mode, self.input.clone(), self.output.clone());
+                    *self = Self { mode, input, output, ..Self::default() };
+                    return
+                }
+                self.command = data;
+                if data & COMMAND_ERROR_RESET != 0 {
+                    self.errors = 0;
+                }
+                if data & COMMAND_RX_ENABLE == 0 {
+                    self.receiving = None;
+                }
+                self.start_transmit();
+            },
+            2 => self.mode = data,
+            3 => self.mask = data & 3,
+            _ => {}
+        }
+    }
+
+    /// Move the transmit buffer to the shift register, if it's free.
+    fn start_transmit (&mut self) {
+        if self.command & COMMAND_TX_ENABLE != 0 && self.shifting.is_none() {
+            if let Some(data) = self.transmit.take() {
+                self.shifting = Some((data, self.frame()));
+            }
+        }
+    }
+
+    /// Count a cycle of the baud rate clock.
+    pub fn clock (&mut self) {
+        if let Some((data, clocks)) = self.shifting {
+            if clocks > 1 {
+                self.shifting = Some((data, clocks - 1));
+            } else {
+                self.output.push_back(data);
+                self.shifting = None;
+                self.start_transmit();
+            }
+        }
+        if self.command & COMMAND_RX_ENABLE == 0 {
+            return
+        }
+        match self.receiving {
+            None if !self.input.is_empty() => self.receiving = Some(self.frame()),
+            Some(clocks) if clocks > 1 => self.receiving = Some(clocks - 1),
+            Some(_) => {
+                self.receiving = None;
+                if let Some(data) = self.input.pop_front() {
+                    if self.received.is_some() {
+                        self.errors |= OVERRUN_ERROR;
+                    }
+                    self.received = Some(self.character(data));
+                }
+            },
+            None => {}
+        }
+    }
+
+    /// Send characters from the host to the receiver.
+    pub fn send (&mut self, data: &[u8]) {
+        self.input.extend(data);
+    }
+
+    /// Take the characters transmitted to the host so far.
+    pub fn take_output (&mut self) -> Vec<u8> {
+        self.output.drain(..).collect()
+    }
+
+    pub(crate) fn save_state (&self, state: &mut StateWriter) {
+        for value in [self.mode, self.command, self.mask, self.errors] {
+            state.u8(value);
+        }
+        for value in [self.transmit, self.received] {
+            state.bool(value.is_some());
+            state.u8(value.unwrap_or(0));
+        }
+        state.bool(self.shifting.is_some());
+        let (data, clocks) = self.shifting.unwrap_or((0, 0));
+        state.u8(data);
+        state.u32(clocks);
+        state.bool(self.receiving.is_some());
+        state.u32(self.receiving.unwrap_or(0));
+        state.bytes(&self.input.iter().copied().collect::<Vec<_>>());
+        state.bytes(&self.output.iter().copied().collect::<Vec<_>>());
+    }
+
+    pub(crate) fn load_state (state: &mut StateReader) -> Result<Self, StateError> {
+        let mut scu = Self {
+            mode:    state.u8()?,
+            command: state.u8()?,
+            mask:    state.u8()?,
+            errors:  state.u8()?,
+            ..Self::default()
+        };
+        for value in [&mut scu.transmit, &mut scu.received] {
+            let some = state.bool()?;
+            let data = state.u8()?;
+            *value = some.then_some(data);
+        }
+        let some = state.bool()?;
+        let (data, clocks) = (state.u8()?, state.u32()?);
+        scu.shifting = some.then_some((data, clocks));
+        let some = state.bool()?;
+        let clocks = state.u32()?;
+        scu.receiving = some.then_some(clocks);
+        scu.input = state.bytes()?.iter().copied().collect();
+        scu.output = state.bytes()?.iter().copied().collect();
+        Ok(scu)
+    }
+
+}
+
+impl CPU {
+
+    /// Serial control unit
+    pub fn scu (&self) -> &Scu {
+        &self.scu
+    }
+
+    /// Send bytes to the serial input, to be received at the baud rate.
+    pub fn serial_send (&mut self, data: &[u8]) {
+        self.scu.send(data);
+    }
+
+    /// Take the bytes transmitted on the serial output so far.
+    pub fn serial_output (&mut self) -> Vec<u8> {
+        self.scu.take_output()
+    }
+
+}
@@ -0,0 +1,129 @@
+//! Save states: the complete state of the CPU and of the memory and devices
+//! on its bus, in a versioned binary format.
+
+use crate::*;
+pub use mpcemu_core::{StateWriter, StateReader, StateError};
+
+/// First bytes of a save state.
+const STATE_MAGIC: &[u8;4] = b"V53S";
+
+/// Version of the save state format. Bump when the saved fields change,
+/// so that older save states are rejected instead of misread.
+pub const STATE_VERSION: u16 = 7;
+
+impl CPU {
+
+    /// Save registers, the clock, internal I/O, interrupt state and the
+    /// peripheral units, followed by the state of the bus. Breakpoints aren't saved.
+    pub fn save_state (&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.raw(STATE_MAGIC);
+        state.u16(STATE_VERSION);
+        for index in 0..VECTOR_REGISTERS.len() {
+            state.u16(self.vector_register(index));
+        }
+        state.u8(match self.segment {
+            None               => 0,
+            Some(Segment::DS0) => 1,
+            Some(Segment::DS1) => 2,
+            Some(Segment::PS)  => 3,
+            Some(Segment::SS)  => 4,
+        });
+        state.bool(self.instruction_start.is_some());
+        state.u16(self.instruction_start.unwrap_or(0));
+        state.u8(self.opcode);
+        state.u64(self.clock);
+        state.u16(self.queue);
+        state.bytes(&self.internal);
+        match self.interrupt_request {
+            Some(vector) => { state.bool(true); state.u8(vector) },
+            None => state.bool(false),
+        }
+        state.bool(self.halted);
+        state.bool(self.trap_undefined);
+        state.u8(self.irq_pins);
+        self.icu.save_state(&mut state);
+        self.tcu.save_state(&mut state);
+        self.dmau.save_state(&mut state);
+        self.scu.save_state(&mut state);
+        self.bus.save_state(&mut state);
+        state.finish()
+    }
+
+    /// Restore a state saved by [CPU::save_state] from a CPU with the same
+    /// memory and devices. Nothing is changed if the state can't be read.
+    pub fn load_state (&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+        if state.raw(STATE_MAGIC.len()).ok() != Some(STATE_MAGIC.as_slice()) {
+            return Err(StateError::Format)
+        }
+        let version = state.u16()?;
+        if version != STATE_VERSION {
+            return Err(StateError::Version { found: version, expected: STATE_VERSION })
+        }
+        let mut registers = [0u16;14];
+        for register in registers.iter_mut() {
+            *register = state.u16()?;
+        }
+        let segment = match state.u8()? {
+            0 => None,
+            1 => Some(Segment::DS0),
+            2 => Some(Segment::DS1),
+            3 => Some(Segment::PS),
+            4 => Some(Segment::SS),
+            _ => return Err(StateError::Format)
+        };
+        let started = state.bool()?;
+        let instruction_start = state.u16()?;
+        let instruction_start = started.then_some(instruction_start);
+        let opcode = state.u8()?;
+        let clock = state.u64()?;
+        let queue = state.u16()?;
+        if queue > QUEUE_SIZE {
+            return Err(StateError::Format)
+        }
+        let mut internal = [0u8;0x100];
+        state.bytes_into(&mut internal)?;
+        let interrupt_request = if state.bool()? { Some(state.u8()?) } else { None };
+        let halted = state.bool()?;
+        let trap_undefined = state.bool()?;
+        let irq_pins = state.u8()?;
+        let icu = Icu::load_state(&mut state)?;
+        let tcu = Tcu::load_state(&mut state)?;
+        let dmau = Dmau::load_state(&mut state)?;
+        let scu = Scu::load_state(&mut state)?;
+
+        // Put the bus back as it was if its state doesn't fit
+        let mut backup = StateWriter::new();
+        self.bus.save_state(&mut backup);
+        let backup = backup.finish();
+        let result = self.bus.load_state(&mut state).and_then(|_| state.finish());
+        if let Err(error) = result {
+            self.bus.load_state(&mut StateReader::new(&backup))?;
+            return Err(error)
+        }
+
+        for (index, value) in registers.into_iter().enumerate() {
+            self.set_vector_register(index, value);
+        }
+        self.segment           = segment;
+        self.instruction_start = instruction_start;
+        self.opcode            = opcode;
+        self.clock             = clock;
+        self.queue             = queue;
+        self.pending           = 0;
+        self.bus_clocks        = 0;
+        self.internal          = internal;
+        self.interrupt_request = interrupt_request;
+        self.halted            = halted;
+        self.trap_undefined    = trap_undefined;
+        self.irq_pins          = irq_pins;
+        self.icu               = icu;
+        self.tcu               = tcu;
+        self.dmau              = dmau;
+        self.scu               = scu;
+        self.breakpoint_hit    = None;
+        Ok(())
+    }
+
+}
@@ -0,0 +1,182 @@
+use crate::*;
+
+/// Name of a block transfer/string instruction, or `None` if the opcode
+/// is not one.
+pub fn string_name (op: u8) -> Option<&'static str> {
+    Some(match op {
+        0x6C => "INM",
+        0x6D => "INMW",
+        0x6E => "OUTM",
+        0x6F => "OUTMW",
+        0xA4 => "MOVBK",
+        0xA5 => "MOVBKW",
+        0xA6 => "CMPBK",
+        0xA7 => "CMPBKW",
+        0xAA => "STM",
+        0xAB => "STMW",
+        0xAC => "LDM",
+        0xAD => "LDMW",
+        0xAE => "CMPM",
+        0xAF => "CMPMW",
+        _ => return None
+    })
+}
+
+/// Whether a string instruction compares, so that repeat prefixes
+/// also terminate on the Z flag.
+pub fn string_compares (op: u8) -> bool {
+    matches!(op, 0xA6 | 0xA7 | 0xAE | 0xAF)
+}
+
+impl CPU {
+
+    /// Execute one iteration of a block transfer/string instruction and
+    /// return its cycle count. The source is DS0:IX (segment can be
+    /// overridden), the destination is DS1:IY. IX and IY advance by the
+    /// operand size, backwards if DIR is set.
+    pub fn string_op (&mut self, op: u8) -> u64 {
+        let word = op & B0 > 0;
+        match op {
+            0x6C | 0x6D => {
+                let data = if word {
+                    self.input_u16(self.dw())
+                } else {
+                    self.input_u8(self.dw()) as u16
+                };
+                self.write_string_destination(word, data);
+                self.advance_iy(word);
+                9
+            },
+            0x6E | 0x6F => {
+                let data = self.read_string_source(word);
+                if word {
+                    self.output_u16(self.dw(), data);
+                } else {
+                    self.output_u8(self.dw(), data as u8);
+                }
+                self.advance_ix(word);
+                8
+            },
+            0xA4 | 0xA5 => {
+                let data = self.read_string_source(word);
+                self.write_string_destination(word, data);
+                self.advance_ix(word);
+                self.advance_iy(word);
+                6
+            },
+            0xA6 | 0xA7 => {
+                let src = self.read_string_source(word);
+                let dst = self.read_string_destination(word);
+                self.alu(ALU_CMP, word, src, dst);
+                self.advance_ix(word);
+                self.advance_iy(word);
+                7
+            },
+            0xAA | 0xAB => {
+                let data = if word { self.aw() } else { self.al() as u16 };
+                self.write_string_destination(word, data);
+                self.advance_iy(word);
+                3
+            },
+            0xAC | 0xAD => {
+                let data = self.read_string_source(word);
+                if word { self.set_aw(data) } else { self.set_al(data as u8) }
+                self.advance_ix(word);
+                5
+            },
+            0xAE | 0xAF => {
+                let dst = self.read_string_destination(word);
+                let acc = if word { self.aw() } else { self.al() as u16 };
+                self.alu(ALU_CMP, word, acc, dst);
+                self.advance_iy(word);
+                5
+            },
+            _ => unreachable!("string op {op:02X}")
+        }
+    }
+
+    fn read_string_source (&mut self, word: bool) -> u16 {
+        let ix = self.ix();
+        if word { self.read_u16(ix) } else { self.read_u8(ix) as u16 }
+    }
+
+    fn read_string_destination (&mut self, word: bool) -> u16 {
+        let (ds1, iy) = (self.ds1(), self.iy());
+        if word { self.read_segment_u16(ds1, iy) } else { self.read_segment_u8(ds1, iy) as u16 }
+    }
+
+    fn write_string_destination (&mut self, word: bool, data: u16) {
+        let (ds1, iy) = (self.ds1(), self.iy());
+        if word { self.write_segment_u16(ds1, iy, data) } else { self.write_segment_u8(ds1, iy, data as u8) }
+    }
+
+    fn string_delta (&self, word: bool) -> u16 {
+        let size: u16 = if word { 2 } else { 1 };
+        if self.dir() { size.wrapping_neg() } else { size }
+    }
+
+    fn advance_ix (&mut self, word: bool) {
+        self.set_ix(self.ix().wrapping_add(self.string_delta(word)));
+    }
+
+    fn advance_iy (&mut self, word: bool) {
+        self.set_iy(self.iy().wrapping_add(self.string_delta(word)));
+    }
+
+}
+
+/// Name of a repeat prefix, or of the bus lock prefix.
+pub fn repeat_name (prefix: u8) -> &'static str {
+    match prefix {
+        0xF0 => "BUSLOCK",
+        0xF2 => "REPNE",
+        0xF3 => "REP",
+        0x64 => "REPNC",
+        0x65 => "REPC",
+        _ => unreachable!()
+    }
+}
+
+impl CPU {
+
+    /// Repeat prefixes: REPNE/REPNZ (F2H), REP/REPE/REPZ (F3H), REPNC (64H)
+    /// and REPC (65H). The prefixed string instruction repeats while CW is
+    /// nonzero, decrementing CW each time. CMPBK and CMPM also stop when Z
+    /// becomes 0 (F3H) or 1 (F2H); REPC and REPNC stop any string instruction
+    /// when CY becomes 0 or 1 respectively. A segment override may follow the
+    /// repeat prefix.
+    ///
+    /// Each step executes a single iteration. Until the repetition ends, PC is
+    /// left pointing at the first prefix of the instruction (a segment
+    /// override or BUSLOCK may precede the repeat prefix), so that interrupts
+    /// can be accepted between iterations and return to the remainder of the
+    /// repetition.
+    pub fn repeat (&mut self, prefix: u8, segment: Option<Segment>, op: u8) -> u64 {
+        let start = self.instruction_start
+            .unwrap_or_else(|| self.pc().wrapping_sub(2 + segment.is_some() as u16));
+        if segment.is_some() {
+            self.segment = segment;
+        }
+        // The prefix overhead is counted once, when the repetition ends
+        if self.cw() == 0 {
+            return 2
+        }
+        let cycles = self.string_op(op);
+        self.set_cw(self.cw() - 1);
+        let compares = string_compares(op);
+        let done = self.cw() == 0 || match prefix {
+            0x64 => self.cy(),
+            0x65 => !self.cy(),
+            0xF2 => compares && self.z(),
+            0xF3 => compares && !self.z(),
+            _ => unreachable!()
+        };
+        if done {
+            cycles + 2
+        } else {
+            self.set_pc(start);
+            cycles
+        }
+    }
+
+}
@@ -0,0 +1,411 @@
+//! Timer/counter unit: three 16-bit down counters, compatible with the
+//! 8254. Counters are clocked from the CPU clock through the prescaler set
+//! by TCKS. The output of counter 0 requests interrupts on IR0 of the ICU,
+//! and the output of counter 1 is the baud rate clock of the SCU.
+
+use crate::*;
+
+/// Internal I/O address of the timer clock selection register (TCKS).
+/// Bits 1-0 divide the CPU clock by 2, 4, 8 or 16 to clock the counters;
+/// bits 2-4 select the external TCLK input instead, for counters 0-2.
+pub const TCKS: u16 = 0xFFF0;
+
+/// ICU input requested by the output of counter 0.
+pub const TIMER_IRQ: u8 = 0;
+
+/// One of the counters of the [Tcu].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Counter {
+    /// Counter mode (0-5)
+    mode:     u8,
+    /// Whether the counter counts in BCD instead of binary
+    bcd:      bool,
+    /// Read/write access: 1 for the LSB only, 2 for the MSB only,
+    /// 3 for the LSB then the MSB
+    access:   u8,
+    /// Count register, written by the CPU
+    reload:   u16,
+    /// Counting element
+    count:    u16,
+    /// Count latched by the latch command, if not read yet
+    latch:    Option<u16>,
+    /// Status latched by the read-back command, if not read yet
+    status:   Option<u8>,
+    /// Whether the next write is the MSB of a two-byte count
+    write_msb: bool,
+    /// Whether the next read is the MSB of a two-byte count
+    read_msb: bool,
+    /// Whether a count was written but isn't loaded yet
+    null:     bool,
+    /// Whether the count register is loaded on the next clock
+    load:     bool,
+    /// Whether a count has been loaded and the counter counts
+    counting: bool,
+    /// Level of the gate input
+    gate:     bool,
+    /// Level of the output
+    out:      bool,
+}
+
+impl Default for Counter {
+    fn default () -> Self {
+        Self {
+            mode: 0, bcd: false, access: 3, reload: 0, count: 0, latch: None, status: None,
+            write_msb: false, read_msb: false, null: true, load: false, counting: false,
+            gate: true, out: false,
+        }
+    }
+}
+
+impl Counter {
+
+    /// Level of the output.
+    pub fn out (&self) -> bool {
+        self.out
+    }
+
+    /// Value of the counting element.
+    pub fn count (&self) -> u16 {
+        self.count
+    }
+
+    /// Program the mode with a control word, which stops the counter
+    /// until a count is written.
+    fn control (&mut self, data: u8) {
+        *self = Self {
+            mode:   match (data >> 1) & 7 { mode @ 0..=5 => mode, mode => mode - 4 },
+            bcd:    data & 1 != 0,
+            access: (data >> 4) & 3,
+            gate:   self.gate,
+            out:    (data >> 1) & 7 != 0,
+            ..Self::default()
+        }
+    }
+
+    fn status_byte (&self) -> u8 {
+        (self.out as u8) << 7 | (self.null as u8) << 6 | self.access << 4 | self.mode << 1 | self.bcd as u8
+    }
+
+    fn latch_count (&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.count);
+        }
+    }
+
+    fn latch_status (&mut self) {
+        if self.status.is_none() {
+            self.status = Some(self.status_byte());
+        }
+    }
+
+    fn write (&mut self, data: u8) {
+        match self.access {
+            1 => self.reload = data as u16,
+            2 => self.reload = (data as u16) << 8,
+            _ => {
+                if !self.write_msb {
+                    self.reload = (self.reload & 0xFF00) | data as u16;
+                    self.write_msb = true;
+                    // Mode 0 stops counting until the count is complete
+                    if self.mode == 0 {
+                        self.out = false;
+                        self.counting = false;
+                    }
+                    return
+                }
+                self.reload = (self.reload & 0x00FF) | (data as u16) << 8;
+                self.write_msb = false;
+            }
+        }
+        self.null = true;
+        match self.mode {
+            0 => {
+                self.out = false;
+                self.load = true;
+            },
+            // Once counting, the new count is used from the next reload
+            2 | 3 if !self.counting => self.load = true,
+            4 => self.load = true,
+            // Waits for a trigger, or the next reload
+            _ => {}
+        }
+    }
+
+    fn read (&mut self) -> u8 {
+        if let Some(status) = self.status.take() {
+            return status
+        }
+        let value = self.latch.unwrap_or(self.count);
+        let (byte, done) = match self.access {
+            1 => (value as u8, true),
+            2 => ((value >> 8) as u8, true),
+            _ if !self.read_msb => {
+                self.read_msb = true;
+                (value as u8, false)
+            },
+            _ => {
+                self.read_msb = false;
+                ((value >> 8) as u8, true)
+            }
+        };
+        if done {
+            self.latch = None;
+        }
+        byte
+    }
+
+    /// Drive the gate input. A rising edge triggers modes 1 and 5 and
+    /// restarts modes 2 and 3; a low gate stops modes 0, 2, 3 and 4.
+    fn set_gate (&mut self, high: bool) {
+        if high && !self.gate {
+            match self.mode {
+                1 | 5 => self.load = true,
+                2 | 3 if self.counting => self.load = true,
+                _ => {}
+            }
+        }
+        if !high && matches!(self.mode, 2 | 3) {
+            self.out = true;
+        }
+        self.gate = high;
+    }
+
+    /// Decrement the counting element, wrapping to the maximum count.
+    fn decrement (&mut self, by: u16) {
+        self.count = if self.bcd {
+            let value = bcd_value(self.count);
+            let value = if value < by as u32 { value + 10000 - by as u32 } else { value - by as u32 };
+            bcd_count(value)
+        } else {
+            self.count.wrapping_sub(by)
+        };
+    }
+
+    /// Count of the count register, where 0 is the maximum.
+    fn initial (&self) -> u32 {
+        match (self.reload, self.bcd) {
+            (0, false) => 0x10000,
+            (0, true) => 10000,
+            (count, false) => count as u32,
+            (count, true) => bcd_value(count),
+        }
+    }
+
+    /// Load the counting element from the count register.
+    fn load_count (&mut self) {
+        self.count = self.reload;
+        self.null = false;
+        self.load = false;
+        self.counting = true;
+        let initial = self.initial();
+        if self.mode == 3 && initial % 2 == 1 {
+            // Odd counts spend one more clock with the output high
+            let half = if self.out { initial + 1 } else { initial - 1 };
+            self.count = if self.bcd { bcd_count(half % 10000) } else { half as u16 };
+        }
+    }
+
+    /// Count a clock pulse.
+    fn clock (&mut self) {
+        if self.load {
+            match self.mode {
+                1 => self.out = false,
+                3 => self.out = true,
+                _ => {}
+            }
+            self.load_count();
+            return
+        }
+        if !self.counting {
+            return
+        }
+        let gated = matches!(self.mode, 0 | 2 | 3 | 4);
+        if gated && !self.gate {
+            return
+        }
+        match self.mode {
+            0 | 1 => {
+                self.decrement(1);
+                if self.count == 0 {
+                    self.out = true;
+                }
+            },
+            2 => {
+                if !self.out {
+                    self.out = true;
+                    self.load_count();
+                    return
+                }
+                self.decrement(1);
+                if self.count == 1 {
+                    self.out = false;
+                }
+            },
+            3 => {
+                self.decrement(2);
+                if self.count == 0 {
+                    self.out = !self.out;
+                    self.load_count();
+                }
+            },
+            _ => {
+                if !self.out {
+                    // End of the strobe, which isn't repeated
+                    self.out = true;
+                    self.counting = false;
+                    return
+                }
+                self.decrement(1);
+                if self.count == 0 {
+                    self.out = false;
+                }
+            }
+        }
+    }
+
+}
+
+fn bcd_value (count: u16) -> u32 {
+    (0..4).rev().fold(0, |value, digit| value * 10 + ((count >> (digit * 4)) & 0xF).min(9) as u32)
+}
+
+fn bcd_count (value: u32) -> u16 {
+    (0..4).fold(0, |count, digit| count | (((value / 10u32.pow(digit)) % 10) as u16) << (digit * 4))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tcu {
+    counters: [Counter;3],
+    /// CPU clock up to which the counters have counted
+    synced:   u64,
+}
+
+impl Tcu {
+
+    pub fn counter (&self, index: usize) -> &Counter {
+        &self.counters[index]
+    }
+
+    /// Read the count or status of a counter (registers 0-2).
+    /// The control register (3) reads as FFH.
+    pub fn read (&mut self, register: u8) -> u8 {
+        match self.counters.get_mut(register as usize) {
+            Some(counter) => counter.read(),
+            None => 0xFF
+        }
+    }
+
+    /// Write the count of a counter (registers 0-2), or a control word,
+    /// latch command or read-back command (register 3).
+    pub fn write (&mut self, register: u8, data: u8) {
+        if let Some(counter) = self.counters.get_mut(register as usize) {
+            return counter.write(data)
+        }
+        match (data >> 6, (data >> 4) & 3) {
+            (3, _) => for (index, counter) in self.counters.iter_mut().enumerate() {
+                if data & (2 << index) != 0 {
+                    // Latch the status first, so that it's read first
+                    if data & 0x10 == 0 { counter.latch_status() }
+                    if data & 0x20 == 0 { counter.latch_count() }
+                }
+            },
+            (index, 0) => self.counters[index as usize].latch_count(),
+            (index, _) => self.counters[index as usize].control(data),
+        }
+    }
+
+    /// Drive the gate input of a counter.
+    pub fn set_gate (&mut self, index: usize, high: bool) {
+        self.counters[index].set_gate(high)
+    }
+
+    /// Count a clock pulse on the counters that `counters` selects (bits 0-2).
+    pub fn clock (&mut self, counters: u8) {
+        for (index, counter) in self.counters.iter_mut().enumerate() {
+            if counters & (1 << index) != 0 {
+                counter.clock();
+            }
+        }
+    }
+
+    pub(crate) fn save_state (&self, state: &mut StateWriter) {
+        state.u64(self.synced);
+        for counter in self.counters.iter() {
+            for value in [counter.mode, counter.access] {
+                state.u8(value);
+            }
+            for value in [counter.reload, counter.count, counter.latch.unwrap_or(0)] {
+                state.u16(value);
+            }
+            state.bool(counter.latch.is_some());
+            state.u8(counter.status.unwrap_or(0));
+            for value in [
+                counter.status.is_some(), counter.bcd, counter.write_msb, counter.read_msb,
+                counter.null, counter.load, counter.counting, counter.gate, counter.out
+            ] {
+                state.bool(value);
+            }
+        }
+    }
+
+    pub(crate) fn load_state (state: &mut StateReader) -> Result<Self, StateError> {
+        let mut tcu = Self { synced: state.u64()?, ..Self::default() };
+        for counter in tcu.counters.iter_mut() {
+            counter.mode   = state.u8()?;
+            counter.access = state.u8()?;
+            counter.reload = state.u16()?;
+            counter.count  = state.u16()?;
+            let latch      = state.u16()?;
+            counter.latch  = state.bool()?.then_some(latch);
+            let status     = state.u8()?;
+            counter.status = state.bool()?.then_some(status);
+            for flag in [
+                &mut counter.bcd, &mut counter.write_msb, &mut counter.read_msb, &mut counter.null,
+                &mut counter.load, &mut counter.counting, &mut counter.gate, &mut counter.out
+            ] {
+                *flag = state.bool()?;
+            }
+            if counter.mode > 5 || !(1..=3).contains(&counter.access) {
+                return Err(StateError::Format)
+            }
+        }
+        Ok(tcu)
+    }
+
+}
+
+impl CPU {
+
+    /// Timer/counter unit
+    pub fn tcu (&self) -> &Tcu {
+        &self.tcu
+    }
+
+    /// Drive the gate input of a counter of the TCU.
+    pub fn set_timer_gate (&mut self, index: usize, high: bool) {
+        self.update_tcu();
+        self.tcu.set_gate(index, high);
+        self.update_irq(TIMER_IRQ);
+    }
+
+    /// Count the clock pulses of the TCU since it was last updated, pass
+    /// the output of counter 0 on to the ICU, and clock the SCU on each
+    /// rising edge of the output of counter 1.
+    pub(crate) fn update_tcu (&mut self) {
+        let select = self.internal_register(TCKS);
+        let divider = 2 << (select & 3);
+        let pulses = self.clock.saturating_sub(self.tcu.synced) / divider;
+        self.tcu.synced += pulses * divider;
+        let internal = !(select >> 2) & 0b111;
+        for _ in 0..pulses {
+            let baud = self.tcu.counter(BAUD_COUNTER).out();
+            self.tcu.clock(internal);
+            self.update_irq(TIMER_IRQ);
+            if !baud && self.tcu.counter(BAUD_COUNTER).out() {
+                self.scu.clock();
+                self.update_irq(SERIAL_IRQ);
+            }
+        }
+    }
+
+}
@@ -1,49 +1,1677 @@
 use crate::*;
 
+/// Write bytes to memory through the bus.
+fn load (state: &mut CPU, addr: u32, data: &[u8]) {
+    for (i, byte) in data.iter().enumerate() {
+        state.bus_mut().write(addr + i as u32, *byte);
+    }
+}
+
+/// Read bytes from memory through the bus.
+fn peek (state: &CPU, range: std::ops::Range<u32>) -> Vec<u8> {
+    range.map(|addr| state.bus().peek(addr)).collect()
+}
+
 #[test]
 /// Add the contents of memory 0:50H (word data)
 /// to contents of DW register, and store the result to 0:50H:
 fn test_add () {
-    let mut state = CPU::new(vec![]);
+    let mut state = CPU::new(vec![]).unwrap();
     state.aw  = 0x1111;
     state.ds1 = 0x1112;
+    state.ps  = 0x0000;
 
-    state.memory = vec![
+    let program = [
         0xBA, 0x88, 0x88,  // MOV DW, 0x8888
         0xB8, 0x00, 0x00,  // MOV AW, 0x0000
-        0xC4,              // MOV DS1, AW
+        0x8E, 0b11_000_000, // MOV DS1, AW
         0xBF, 0x50, 0x00,  // MOV IY, 0x0050
         0x01, 0b00_010_101 // ADD DS1: WORD PTR [IY], DW
     ];
+    load(&mut state, 0, &program);
+    state.output_u8(WCY1, 0x00);
+    state.output_u8(WCY2, 0x00);
 
-    state.step();
+    // The prefetch queue starts out empty
+    state.step(false).unwrap();
 
-    assert_eq!(state.clock, 2);
+    assert_eq!(state.clock, 6);
     assert_eq!(state.pc, 3);
     assert_eq!(state.dw, 0x8888);
 
-    state.step();
+    state.step(false).unwrap();
 
-    assert_eq!(state.clock, 4);
+    assert_eq!(state.clock, 8);
     assert_eq!(state.pc, 6);
     assert_eq!(state.aw, 0x0000);
 
-    state.step();
+    state.step(false).unwrap();
 
-    assert_eq!(state.clock, 14);
-    assert_eq!(state.pc, 7);
+    assert_eq!(state.clock, 10);
+    assert_eq!(state.pc, 8);
     assert_eq!(state.ds1, 0x0000);
 
-    state.step();
+    state.step(false).unwrap();
 
-    assert_eq!(state.clock, 16);
-    assert_eq!(state.pc, 10);
+    assert_eq!(state.clock, 14);
+    assert_eq!(state.pc, 11);
     assert_eq!(state.iy, 0x0050);
 
-    state.step();
+    state.step(false).unwrap();
+
+    assert_eq!(state.clock, 21);
+    assert_eq!(state.pc, 13);
+    assert_eq!(state.peek_byte(0x0050), 0x88);
+    assert_eq!(state.peek_byte(0x0051), 0x88);
+}
+
+#[test]
+/// Byte and word ALU operations set flags according to operand width,
+/// and group 83H sign-extends its immediate.
+fn test_alu_flags () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+
+    let program = [
+        0xB0, 0x7F,             // MOV AL, 7FH
+        0x04, 0x01,             // ADD AL, 01H
+        0x1C, 0x01,             // SUBC AL, 01H
+        0xB8, 0x05, 0x00,       // MOV AW, 0005H
+        0x83, 0b11_101_000, 0xFF, // SUB AW, FFFFH (sign-extended)
+        0x83, 0b11_111_000, 0x06, // CMP AW, 0006H
+    ];
+    load(&mut state, 0, &program);
+
+    state.step(false).unwrap();
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0x80);
+    assert!(state.s() && state.v() && state.ac() && !state.cy() && !state.z());
+
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0x7F);
+    assert!(!state.s() && state.v() && state.ac() && !state.cy());
+
+    state.step(false).unwrap();
+    state.step(false).unwrap();
+    assert_eq!(state.aw(), 0x0006);
+    assert!(state.cy() && !state.v());
+
+    state.step(false).unwrap();
+    assert_eq!(state.aw(), 0x0006);
+    assert!(state.z() && !state.cy() && state.p());
+}
+
+#[test]
+/// Rotates through carry and arithmetic shifts by CL and by immediate count.
+fn test_shift () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+
+    let program = [
+        0xB0, 0x81,               // MOV AL, 81H
+        0xF9,                     // SET1 CY
+        0xD0, 0b11_011_000,       // RORC AL, 1
+        0xB1, 0x03,               // MOV CL, 03H
+        0xD2, 0b11_111_000,       // SHRA AL, CL
+        0xBB, 0x01, 0x80,         // MOV BW, 8001H
+        0xC1, 0b11_000_011, 0x11, // ROL BW, 11H
+    ];
+    load(&mut state, 0, &program);
+
+    state.step(false).unwrap();
+    state.step(false).unwrap();
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0xC0);
+    assert!(state.cy() && !state.v());
+
+    state.step(false).unwrap();
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0xF8);
+    assert!(!state.cy() && state.s());
+
+    state.step(false).unwrap();
+    state.step(false).unwrap();
+    assert_eq!(state.bw(), 0x0003);
+    assert!(state.cy());
+}
+
+#[test]
+/// Signed and unsigned multiply/divide, and the divide error interrupt.
+fn test_mul_div () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.ss = 0x0000;
+    state.sp = 0x1000;
+    // Divide error handler at 0000:0400
+    load(&mut state, 0, &[0x00, 0x04, 0x00, 0x00]);
+
+    let program = [
+        0xB8, 0xFE, 0xFF,         // MOV AW, -2
+        0xB1, 0x03,               // MOV CL, 3
+        0xF6, 0b11_101_001,       // MUL CL
+        0xB8, 0xF9, 0xFF,         // MOV AW, -7
+        0xF6, 0b11_111_001,       // DIV CL
+        0x6B, 0b11_011_001, 0xFE, // MUL BW, CW, -2
+        0xB1, 0x00,               // MOV CL, 0
+        0xF6, 0b11_110_001,       // DIVU CL
+    ];
+    load(&mut state, 0x100, &program);
+    state.pc = 0x100;
+
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.aw(), 0xFFFA);
+    assert!(!state.cy() && !state.v());
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.al() as i8, -2);
+    assert_eq!(state.ah() as i8, -1);
+
+    state.step(false).unwrap();
+    assert_eq!(state.bw(), 0xFFFA);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.pc(), 0x0400);
+    assert_eq!(state.sp(), 0x1000 - 6);
+    assert_eq!(state.peek_byte(0x1000 - 6), 0x13);
+}
+
+#[test]
+/// Packed and unpacked BCD adjustment after addition and subtraction.
+fn test_bcd_adjust () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+
+    let program = [
+        0xB0, 0x38,       // MOV AL, 38H
+        0x04, 0x45,       // ADD AL, 45H
+        0x27,             // ADJ4A
+        0x2C, 0x84,       // SUB AL, 84H
+        0x2F,             // ADJ4S
+        0xB8, 0x08, 0x00, // MOV AW, 0008H
+        0x04, 0x05,       // ADD AL, 05H
+        0x37,             // ADJBA
+        0xD5, 0x0A,       // CVTDB
+        0xD4, 0x0A,       // CVTBD
+        0x2C, 0x07,       // SUB AL, 07H
+        0x3F,             // ADJBS
+    ];
+    load(&mut state, 0, &program);
+
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.al(), 0x83);
+    assert!(!state.cy() && state.ac());
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.al(), 0x99);
+    assert!(state.cy());
+
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.aw(), 0x0103);
+    assert!(state.cy() && state.ac());
+
+    state.step(false).unwrap();
+    assert_eq!(state.aw(), 0x000D);
+
+    state.step(false).unwrap();
+    assert_eq!(state.aw(), 0x0103);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.aw(), 0x0006);
+    assert!(state.cy());
+}
+
+#[test]
+/// Packed BCD string arithmetic and nibble rotation through AL.
+fn test_bcd_string () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps  = 0x0000;
+    state.ds0 = 0x0100;
+    state.ds1 = 0x0200;
+
+    // 4-digit strings, least significant byte first: 1999 and 0001
+    load(&mut state, 0x1000, &[0x01, 0x00]);
+    load(&mut state, 0x2000, &[0x99, 0x19]);
+    let program = [
+        0xB1, 0x04,                   // MOV CL, 4
+        0x0F, 0x20,                   // ADD4S
+        0x0F, 0x26,                   // CMP4S
+        0x0F, 0x22,                   // SUB4S
+        0xB0, 0x1A,                   // MOV AL, 1AH
+        0xB7, 0x34,                   // MOV BH, 34H
+        0x0F, 0x28, 0b11_000_111,     // ROL4 BH
+        0x0F, 0x2A, 0b11_000_111,     // ROR4 BH
+    ];
+    load(&mut state, 0, &program);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(peek(&state, 0x2000..0x2002), &[0x00, 0x20]);
+    assert!(!state.cy() && !state.z());
+    assert_eq!(state.ix(), 0);
+
+    state.step(false).unwrap();
+    assert_eq!(peek(&state, 0x2000..0x2002), &[0x00, 0x20]);
+    assert!(!state.cy());
+
+    state.step(false).unwrap();
+    assert_eq!(peek(&state, 0x2000..0x2002), &[0x99, 0x19]);
+
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.bh(), 0x4A);
+    assert_eq!(state.al(), 0x13);
+
+    state.step(false).unwrap();
+    assert_eq!(state.bh(), 0x34);
+    assert_eq!(state.al(), 0x1A);
+}
+
+#[test]
+/// Single-bit operations and bit field insertion/extraction.
+fn test_bit_ops () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps  = 0x0000;
+    state.ds1 = 0x0100;
+
+    let program = [
+        0xB1, 0x09,                         // MOV CL, 9
+        0x0F, 0x15, 0b11_000_011,           // SET1 BW, CL
+        0x0F, 0x1E, 0b00_000_110, 0x00, 0x02, 0x07, // NOT1 [0200H], 7
+        0x0F, 0x18, 0b00_000_110, 0x00, 0x02, 0x07, // TEST1 [0200H], 7
+        0x0F, 0x10, 0b11_000_011,           // TEST1 BL, CL
+        0xB8, 0x2D, 0x00,                   // MOV AW, 002DH
+        0xB2, 0x0E,                         // MOV DL, 14
+        0x0F, 0x39, 0b11_000_010, 0x05,     // INS DL, 5
+        0xB2, 0x0E,                         // MOV DL, 14
+        0x0F, 0x3B, 0b11_000_010, 0x05,     // EXT DL, 5
+    ];
+    load(&mut state, 0, &program);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.bw(), 0x0200);
+
+    state.step(false).unwrap();
+    assert_eq!(state.peek_byte(0x0200), 0x80);
+
+    state.step(false).unwrap();
+    assert!(!state.z());
+
+    state.step(false).unwrap();
+    assert!(state.z());
+
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.dl(), 4);
+    assert_eq!(state.iy(), 2);
+    assert_eq!(peek(&state, 0x1000..0x1004), &[0x00, 0x40, 0x0B, 0x00]);
+
+    // Extract the same field back from DS0:IX
+    state.ds0 = 0x0100;
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.aw(), 0x002D);
+    assert_eq!(state.dl(), 4);
+    assert_eq!(state.ix(), 2);
+
+    // The next word is only accessed when the field reaches into it
+    let cycle = BUS_CYCLE + state.memory_wait_states(0x1000);
+    state.cancel_instruction();
+    assert_eq!(state.extract_bit_field(0, 3), 4);
+    assert_eq!(state.bus_clocks, cycle);
+    state.cancel_instruction();
+    assert_eq!(state.insert_bit_field(0, 3), 4);
+    assert_eq!(state.bus_clocks, 2 * cycle);
+    state.cancel_instruction();
+    assert_eq!(state.extract_bit_field(14, 3), 2);
+    assert_eq!(state.bus_clocks, 2 * cycle);
+    state.cancel_instruction();
+    assert_eq!(state.insert_bit_field(14, 3), 2);
+    assert_eq!(state.bus_clocks, 4 * cycle);
+}
+
+#[test]
+/// Repeat prefixes with their Z/CY termination conditions,
+/// and segment overrides on either side of the prefix.
+fn test_repeat () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps  = 0x0000;
+    state.ds0 = 0x0100;
+    state.ds1 = 0x0200;
+    state.ss  = 0x0300;
+
+    load(&mut state, 0x1000, b"abcd");
+    load(&mut state, 0x2000, b"abXd");
+    load(&mut state, 0x3000, b"wxyz");
+    let program = [
+        0xB9, 0x04, 0x00, // MOV CW, 4
+        0xF3, 0xA6,       // REPE CMPBK
+        0xB9, 0x04, 0x00, // MOV CW, 4
+        0xB0, b'X',       // MOV AL, 'X'
+        0x33, 0xFF,       // XOR IY, IY
+        0xF2, 0xAE,       // REPNE CMPM
+        0x33, 0xF6,       // XOR IX, IX
+        0x33, 0xFF,       // XOR IY, IY
+        0xB9, 0x04, 0x00, // MOV CW, 4
+        0xF3, 0x36, 0xA4, // REP SS: MOVBK
+        0x33, 0xF6,       // XOR IX, IX
+        0x33, 0xFF,       // XOR IY, IY
+        0xB9, 0x04, 0x00, // MOV CW, 4
+        0x2E, 0x65, 0xA7, // PS: REPC CMPBKW
+    ];
+    load(&mut state, 0, &program);
+
+    for _ in 0..4 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 1);
+    assert_eq!(state.ix(), 3);
+    assert!(!state.z());
+
+    for _ in 0..6 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 1);
+    assert_eq!(state.iy(), 3);
+    assert!(state.z());
+
+    for _ in 0..7 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 0);
+    assert_eq!(peek(&state, 0x2000..0x2004), b"wxyz");
+
+    // Compares PS:0000 (04B9H, F300H) with DS1:0000 ("wx", "yz"):
+    // the first compare borrows and continues, the second one stops.
+    // Each iteration re-executes the segment override preceding the prefix.
+    for _ in 0..7 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 2);
+    assert_eq!(state.ix(), 4);
+    assert!(!state.cy());
+}
+
+#[test]
+/// Repeated instructions execute one iteration per step and can be
+/// interrupted between iterations, resuming afterwards.
+fn test_repeat_interrupt () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps  = 0x0000;
+    state.ss  = 0x0000;
+    state.sp  = 0x0800;
+    state.ds1 = 0x0200;
+    state.set_ie(true);
+    // Interrupt handler at 0000:0400 just returns
+    load(&mut state, 0x20 * 4, &[0x00, 0x04, 0x00, 0x00]);
+    load(&mut state, 0x0400, &[0xCF]); // RETI
+
+    let program = [
+        0xB9, 0x03, 0x00, // MOV CW, 3
+        0xB0, 0x55,       // MOV AL, 55H
+        0x2E, 0xF3, 0xAA, // PS: REP STM
+    ];
+    load(&mut state, 0x100, &program);
+    state.pc = 0x100;
+
+    for _ in 0..4 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 2);
+    assert_eq!(state.pc(), 0x105);
+
+    state.request_interrupt(0x20);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x400);
+    assert_eq!(state.peek_byte(0x07FA), 0x05);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x105);
+
+    // Each remaining iteration re-executes the segment override, then STM
+    for _ in 0..4 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 0);
+    assert_eq!(state.pc(), 0x108);
+    assert_eq!(peek(&state, 0x2000..0x2004), &[0x55, 0x55, 0x55, 0x00]);
+
+    // An interrupt returns to the bus lock prefix, not past it, and isn't
+    // accepted between the prefix and the repetition
+    let program = [
+        0xB9, 0x02, 0x00, // MOV CW, 2
+        0xF0, 0xF3, 0xAA, // BUSLOCK REP STM
+    ];
+    load(&mut state, 0x108, &program);
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.pc(), 0x10C);
+    state.request_interrupt(0x20);
+    state.step(false).unwrap();
+    assert_eq!(state.cw(), 1);
+    assert_eq!(state.pc(), 0x10B);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x400);
+    assert_eq!(state.peek_byte(0x07FA), 0x0B);
+    state.step(false).unwrap();
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 0);
+    assert_eq!(state.pc(), 0x10E);
+}
+
+#[test]
+/// Nested stack frames with PREPARE/DISPOSE, and CHKIND bounds checking.
+fn test_prepare_chkind () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.ss = 0x0000;
+    state.sp = 0x0800;
+    state.bp = 0x0900;
+    load(&mut state, 0x08FE, &[0x34, 0x12]);
+    // Bounds handler at 0000:0400
+    load(&mut state, 0x14, &[0x00, 0x04, 0x00, 0x00]);
+    // Bounds 1..=10 at 0300H
+    load(&mut state, 0x0300, &[0x01, 0x00, 0x0A, 0x00]);
+
+    let program = [
+        0xC8, 0x10, 0x00, 0x02,                 // PREPARE 10H, 2
+        0xC9,                                   // DISPOSE
+        0xBB, 0x0A, 0x00,                       // MOV BW, 10
+        0x62, 0b00_011_110, 0x00, 0x03,         // CHKIND BW, [0300H]
+        0x43,                                   // INC BW
+        0x62, 0b00_011_110, 0x00, 0x03,         // CHKIND BW, [0300H]
+    ];
+    load(&mut state, 0x100, &program);
+    state.pc = 0x100;
+
+    state.step(false).unwrap();
+    assert_eq!(state.bp(), 0x07FE);
+    assert_eq!(state.sp(), 0x07FA - 0x10);
+    assert_eq!(peek(&state, 0x07FA..0x0800), &[0xFE, 0x07, 0x34, 0x12, 0x00, 0x09]);
+
+    state.step(false).unwrap();
+    assert_eq!(state.bp(), 0x0900);
+    assert_eq!(state.sp(), 0x0800);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.pc(), 0x10C);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.pc(), 0x0400);
+}
+
+#[test]
+/// HALT suspends execution until an interrupt is accepted.
+fn test_halt () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.ss = 0x0000;
+    state.sp = 0x0800;
+    load(&mut state, 0x20 * 4, &[0x00, 0x04, 0x00, 0x00]);
+    load(&mut state, 0x100, &[0xF4]); // HALT
+    state.pc = 0x100;
+
+    assert_eq!(state.step(false), Err(Stop::Halted));
+    assert!(state.halted());
+    for _ in 0..10 { assert_eq!(state.step(false), Err(Stop::Halted)) }
+    assert_eq!(state.pc(), 0x101);
+
+    // Masked interrupts don't resume execution
+    state.request_interrupt(0x20);
+    assert_eq!(state.step(false), Err(Stop::Halted));
+    assert!(state.halted());
+
+    state.set_ie(true);
+    state.step(false).unwrap();
+    assert!(!state.halted());
+    assert_eq!(state.pc(), 0x0400);
+    assert_eq!(state.peek_byte(0x07FA), 0x01);
+}
+
+#[test]
+/// Direct and indirect near/far calls, returns and branches,
+/// PUSH rm and DEC rm.
+fn test_call_ret () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.ss = 0x0000;
+    state.sp = 0x0800;
+    // Far pointer 0020:0210, near pointers 0120H and 0130H, a byte counter
+    load(&mut state, 0x0300, &[0x10, 0x02, 0x20, 0x00, 0x20, 0x01, 0x00, 0x30, 0x01]);
+    load(&mut state, 0x0400, &[0xCA, 0x02, 0x00]);  // RETF 2
+    load(&mut state, 0x0410, &[0xCB]);                                        // RETF
+    load(&mut state, 0x0120, &[0xC2, 0x04, 0x00]);  // RET 4
+    load(&mut state, 0x0130, &[0xFF, 0b00_101_110, 0x00, 0x03]); // BR FAR [0300H]
+
+    let program = [
+        0x9A, 0x00, 0x02, 0x20, 0x00,           // CALL 0020:0200
+        0xFF, 0b00_011_110, 0x00, 0x03,         // CALL FAR [0300H]
+        0xFF, 0b00_010_110, 0x04, 0x03,         // CALL [0304H]
+        0xF9,                                   // SET1 CY
+        0xFE, 0b00_001_110, 0x06, 0x03,         // DEC [0306H]
+        0xFF, 0b00_110_110, 0x04, 0x03,         // PUSH [0304H]
+        0xFF, 0b00_100_110, 0x07, 0x03,         // BR [0307H]
+    ];
+    load(&mut state, 0x100, &program);
+    state.pc = 0x100;
+
+    state.step(false).unwrap();
+    assert_eq!((state.ps(), state.pc(), state.sp()), (0x0020, 0x0200, 0x07FC));
+    assert_eq!(peek(&state, 0x07FC..0x0800), &[0x05, 0x01, 0x00, 0x00]);
+    state.step(false).unwrap();
+    assert_eq!((state.ps(), state.pc(), state.sp()), (0x0000, 0x0105, 0x0802));
+
+    state.step(false).unwrap();
+    assert_eq!((state.ps(), state.pc(), state.sp()), (0x0020, 0x0210, 0x07FE));
+    state.step(false).unwrap();
+    assert_eq!((state.ps(), state.pc(), state.sp()), (0x0000, 0x0109, 0x0802));
+
+    state.step(false).unwrap();
+    assert_eq!((state.pc(), state.sp()), (0x0120, 0x0800));
+    state.step(false).unwrap();
+    assert_eq!((state.pc(), state.sp()), (0x010D, 0x0806));
+
+    // DEC leaves CY untouched
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.peek_byte(0x0306), 0xFF);
+    assert!(state.cy());
+    assert!(state.s());
+
+    state.step(false).unwrap();
+    assert_eq!(state.sp(), 0x0804);
+    assert_eq!(peek(&state, 0x0804..0x0806), &[0x20, 0x01]);
+
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x0130);
+    state.step(false).unwrap();
+    assert_eq!((state.ps(), state.pc()), (0x0020, 0x0210));
+
+    // POP PSW and RETI keep the fixed bits of the PSW
+    load(&mut state, 0x0140, &[0x9D, 0xCF]);   // POP PSW, RETI
+    load(&mut state, 0x07F8, &[0x00, 0x00, 0x50, 0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    state.ps = 0x0000;
+    state.pc = 0x0140;
+    state.sp = 0x07F8;
+    state.step(false).unwrap();
+    assert_eq!(state.psw(), 0xF002);
+    state.step(false).unwrap();
+    assert_eq!((state.ps(), state.pc(), state.sp()), (0x0000, 0x0150, 0x0800));
+    assert_eq!(state.psw(), 0xFFD7);
+}
+
+#[test]
+/// PUSH R/POP R, PUSH imm, MOV rm imm, XCH rm, TEST, TRANS and INM.
+fn test_transfer () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps  = 0x0000;
+    state.ss  = 0x0000;
+    state.ds0 = 0x0000;
+    state.ds1 = 0x0000;
+    state.sp  = 0x0800;
+    state.aw  = 0x1234;
+    state.bw  = 0x0300;
+    state.cw  = 0x0002;
+    state.dw  = 0x0040;
+    state.iy  = 0x0500;
+    state.bus_mut().output(0x40, 0x99);
+    load(&mut state, 0x03CD, &[0x42]);
+
+    let program = [
+        0x60,                                   // PUSH R
+        0x61,                                   // POP R
+        0x6A, 0xFE,                             // PUSH -2
+        0x68, 0x78, 0x56,                       // PUSH 5678H
+        0xC7, 0b01_000_111, 0x02, 0xCD, 0xAB,   // MOV [BW +2], ABCDH
+        0x87, 0b01_000_111, 0x02,               // XCH AW, [BW +2]
+        0x84, 0b11_100_000,                     // TEST AL, AH
+        0xA8, 0x00,                             // TEST AL, 0
+        0xD7,                                   // TRANS
+        0xF3, 0x6C,                             // REP INM
+    ];
+    load(&mut state, 0x100, &program);
+    state.pc = 0x100;
+
+    state.step(false).unwrap();
+    assert_eq!(state.sp(), 0x07F0);
+    assert_eq!(peek(&state, 0x07F0..0x0800), &[
+        0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08,
+        0x00, 0x03, 0x40, 0x00, 0x02, 0x00, 0x34, 0x12,
+    ]);
+    state.aw = 0;
+    state.bp = 0xFFFF;
+    state.step(false).unwrap();
+    assert_eq!((state.aw(), state.bp(), state.iy(), state.sp()), (0x1234, 0x0000, 0x0500, 0x0800));
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.sp(), 0x07FC);
+    assert_eq!(peek(&state, 0x07FC..0x0800), &[0x78, 0x56, 0xFE, 0xFF]);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.aw(), 0xABCD);
+    assert_eq!(peek(&state, 0x0302..0x0304), &[0x34, 0x12]);
+
+    state.step(false).unwrap();
+    assert!(state.s());
+    assert!(!state.z());
+    state.step(false).unwrap();
+    assert!(state.z());
+    assert_eq!(state.aw(), 0xABCD);
+
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0x42);
+
+    for _ in 0..2 { state.step(false).unwrap() }
+    assert_eq!(state.cw(), 0);
+    assert_eq!(state.iy(), 0x0502);
+    assert_eq!(peek(&state, 0x0500..0x0502), &[0x99, 0x99]);
+}
+
+#[test]
+/// Decoding yields the instruction's length, including displacements and
+/// immediates, and the same instruction disassembles to its mnemonic.
+fn test_decode () {
+    let decode = |bytes: &[u8]| {
+        let (instruction, length) = Instruction::decode(bytes).unwrap();
+        (instruction.to_string(), length)
+    };
+    assert_eq!(decode(&[0x01, 0b00_010_101]), ("ADDW [IY], DW".into(), 2));
+    assert_eq!(decode(&[0x8B, 0b01_000_110, 0xFE]), ("MOV AW, [BP -2]".into(), 3));
+    assert_eq!(decode(&[0xC7, 0b10_000_111, 0x34, 0x12, 0xCD, 0xAB]), ("MOVW [BW + 1234], ABCD".into(), 6));
+    assert_eq!(decode(&[0x83, 0b00_111_110, 0x50, 0x00, 0xFF]), ("CMPW [0050], FFFF".into(), 5));
+    assert_eq!(decode(&[0xC1, 0b11_100_011, 0x04]), ("SHLW BW, 4".into(), 3));
+    assert_eq!(decode(&[0x7C, 0xFE]), ("BLT -2".into(), 2));
+    assert_eq!(decode(&[0xE5, 0x10]), ("IN AW, 10".into(), 2));
+    assert_eq!(decode(&[0xC4, 0b00_110_100]), ("MOV DS1, IX, [IX]".into(), 2));
+    assert_eq!(decode(&[0x26, 0xF3, 0xA4]), ("DS1:".into(), 1));
+    assert_eq!(decode(&[0xF3, 0x2E, 0xA5]), ("REP PS: MOVBKW".into(), 3));
+    assert_eq!(decode(&[0xF3, 0x90]), ("REP".into(), 1));
+    assert_eq!(decode(&[0x0F, 0x1D, 0b11_000_001, 0x07]), ("SET1W CW, 7".into(), 4));
+    assert_eq!(decode(&[0x0F, 0x31, 0b11_001_000]), ("INS AL, CL".into(), 3));
+    assert_eq!(decode(&[0xFF, 0b00_011_111]), ("CALL FAR [BW]".into(), 2));
+    assert_eq!(decode(&[0x62, 0b11_000_000]), ("(undefined 62)".into(), 2));
+    // A width pads the whole instruction
+    let (instruction, _) = Instruction::decode(&[0xC1, 0b11_100_011, 0x04]).unwrap();
+    assert_eq!(format!("{instruction:12}|"), "SHLW BW, 4  |");
+    // Incomplete instructions don't decode
+    assert_eq!(Instruction::decode(&[0xC7, 0b10_000_111, 0x34]), None);
+}
+
+#[test]
+/// Undefined and unimplemented instructions, breakpoints and oversized
+/// images are reported instead of aborting.
+fn test_stop () {
+    assert_eq!(
+        CPU::new(vec![0; 0x100001]).err(),
+        Some(Error::ImageTooBig { size: 0x100001, capacity: 0x100000 })
+    );
+
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.ss = 0x0000;
+    state.sp = 0x0800;
+    load(&mut state, 6 * 4, &[0x00, 0x04, 0x00, 0x00]);
+    let program = [
+        0x90,                   // NOP
+        0xF1,                   // (undefined)
+        0xD8, 0b11_000_000,     // FPO1
+        0x0F, 0xFF,             // (undefined)
+    ];
+    load(&mut state, 0x100, &program);
+    state.pc = 0x100;
+
+    state.set_breakpoint(0x100);
+    assert_eq!(state.step(false), Err(Stop::Breakpoint(0x100)));
+    assert_eq!(state.pc(), 0x100);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x101);
+
+    assert_eq!(state.step(false), Err(Stop::Undefined(0xF1)));
+    assert_eq!(state.pc(), 0x101);
+
+    // With the trap enabled, the handler returns to the undefined opcode
+    state.set_trap_undefined(true);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x400);
+    assert_eq!(peek(&state, 0x07FA..0x07FC), &[0x01, 0x01]);
+
+    state.pc = 0x102;
+    let clock = state.clock;
+    assert!(matches!(state.step(false), Err(Stop::Unimplemented(Instruction::Coprocessor { .. }))));
+    assert_eq!(state.pc(), 0x102);
+    assert_eq!(state.clock, clock);
+
+    // Undefined opcodes of the 0FH map keep their second byte
+    state.set_trap_undefined(false);
+    state.pc = 0x104;
+    assert_eq!(state.step(false), Err(Stop::UndefinedExtended(0xFF)));
+    assert_eq!(Stop::UndefinedExtended(0xFF).to_string(), "undefined opcode 0F FF");
+    assert_eq!(state.pc(), 0x104);
+    state.set_trap_undefined(true);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x400);
+}
+
+#[test]
+/// Memory and I/O go through the bus, so devices can have read side effects.
+/// The internal I/O area stays inside the CPU.
+fn test_bus () {
+    /// Memory with a counter that increments on every read,
+    /// at port 10H and at memory address 800H.
+    struct Counter(Memory, u8);
+    impl Bus for Counter {
+        fn peek (&self, addr: u32) -> u8 {
+            if addr == 0x800 { self.1 } else { self.0.peek(addr) }
+        }
+        fn read (&mut self, addr: u32) -> u8 {
+            let value = self.peek(addr);
+            if addr == 0x800 { self.1 += 1 }
+            value
+        }
+        fn write (&mut self, addr: u32, value: u8) {
+            self.0.write(addr, value)
+        }
+        fn input (&mut self, port: u16) -> u8 {
+            self.1 += 1;
+            self.1 - 1 + port as u8
+        }
+        fn output (&mut self, port: u16, value: u8) {
+            self.0.output(port, value)
+        }
+    }
+
+    let program = [
+        0xE4, 0x10,             // IN AL, 10H
+        0xE4, 0x10,             // IN AL, 10H
+        0xA0, 0x00, 0x08,       // MOV AL, [0800H]
+        0xE6, 0x20,             // OUT 20H, AL
+        0xBA, 0x40, 0xFF,       // MOV DW, FF40H
+        0xEE,                   // OUT DW, AL
+        0xEC,                   // IN AL, DW
+    ];
+    let mut state = CPU::with_bus(Box::new(Counter(Memory::new(0x10000, &program), 0)));
+    state.ps = 0x0000;
+
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0x10);
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0x11);
+    assert_eq!(state.bus().peek(0x800), 2);
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 2);
+    assert_eq!(state.bus().peek(0x800), 3);
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.internal()[0x40], 2);
+    state.set_al(0);
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 2);
+}
+
+#[test]
+/// ROM mapped at the top of the address space runs from the reset vector;
+/// RAM, mirrors and unmapped memory behave as described by the map.
+fn test_memory_map () {
+    let mut rom = vec![0x00; 0x10000];
+    rom[0xFFF0..0xFFF5].copy_from_slice(&[0xEA, 0x00, 0x00, 0x00, 0xF0]); // BR F000:0000
+    rom[0x0000..0x0009].copy_from_slice(&[
+        0xA2, 0x00, 0x00,       // MOV [0000], AL
+        0xA2, 0x00, 0xF0,       // MOV [F000], AL
+        0xA0, 0x00, 0x90,       // MOV AL, [9000]
+    ]);
+    let memory = MemoryMap::new(Unmapped::Fault)
+        .ram(0x00000, 0x8000)
+        .mirror(0x08000, 0x8000, 0x00000)
+        .rom(0xF0000, rom);
+    let mut state = CPU::with_bus(Box::new(memory));
+    state.set_al(0x42);
+
+    state.step(false).unwrap();
+    assert_eq!(state.program_address(), 0xF0000);
+    state.step(false).unwrap();
+    assert_eq!(state.bus().peek(0x0000), 0x42);
+    assert_eq!(state.bus().peek(0x8000), 0x42);
+
+    // ROM ignores writes
+    state.ds0 = 0xF000;
+    state.step(false).unwrap();
+    assert_eq!(state.bus().peek(0xFF000), 0x00);
+
+    // Unmapped memory floats, and is reported
+    state.ds0 = 0x1000;
+    assert_eq!(state.step(false), Err(Stop::Fault(0x19000)));
+    assert_eq!(state.al(), 0xFF);
+    assert_eq!(state.program_address(), 0xF0009);
+
+    let memory = MemoryMap::new(Unmapped::Float(0x00));
+    assert_eq!(memory.peek(0x12345), 0x00);
+
+    // A mirror reaching past the end of the address space is unmapped there
+    let mut memory = MemoryMap::new(Unmapped::Fault)
+        .mirror(0x10000, 0x100, 0xFFFFFFF0);
+    assert_eq!(memory.read(0x10020), 0xFF);
+    assert_eq!(memory.take_fault(), Some(0x10020));
+}
+
+#[test]
+/// In XA mode, page registers map 16 KB logical pages into 16 MB;
+/// BRKXA and RETXA switch modes through the vector table.
+fn test_xa () {
+    let mut state = CPU::with_bus(Box::new(Memory::new(0x404000, &[])));
+    state.ps = 0x0000;
+    state.ss = 0x0000;
+    state.sp = 0x0800;
+    load(&mut state, 0x20 * 4, &[0x00, 0x02, 0x00, 0x00]); // BRKXA vector: 0000:0200
+    load(&mut state, 0x100, &[
+        0xB8, 0x00, 0x01,       // MOV AW, 0100H
+        0xBA, 0x00, 0xFF,       // MOV DW, FF00H
+        0xEF,                   // OUT DW, AW
+        0x0F, 0xE0, 0x20,       // BRKXA 20H
+    ]);
+    // Page 0 is now at 400000H, where RETXA reads its vector too
+    load(&mut state, 0x400000 + 0x21 * 4, &[0x00, 0x03, 0x00, 0x00]); // 0000:0300
+    load(&mut state, 0x400200, &[
+        0xA3, 0x10, 0x00,       // MOV [0010], AW
+        0x0F, 0xF0, 0x21,       // RETXA 21H
+    ]);
+    load(&mut state, 0x300, &[
+        0xBA, 0x80, 0xFF,       // MOV DW, FF80H
+        0xEE,                   // OUT DW, AL
+        0xEC,                   // IN AL, DW
+    ]);
+    state.pc = 0x100;
+
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.page_register(0), 0x100);
+    assert_eq!(state.page_register(1), 0x001);
+    assert_eq!(state.physical_address(0x04321), 0x04321);
+
+    state.step(false).unwrap();
+    assert!(state.xa());
+    assert_eq!((state.ps(), state.pc()), (0x0000, 0x0200));
+    assert_eq!(state.sp(), 0x0800);
+    assert_eq!(state.physical_address(0x04321), 0x004321);
+    assert_eq!(state.physical_address(0x00321), 0x400321);
+    assert_eq!(state.physical_address(0xFC321), 0x0FC321);
+
+    state.step(false).unwrap();
+    assert_eq!(state.bus().peek(0x400011), 0x01);
+    assert_eq!(state.bus().peek(0x000011), 0x00);
+    state.step(false).unwrap();
+    assert!(!state.xa());
+    assert_eq!((state.ps(), state.pc()), (0x0000, 0x0300));
+
+    // XAM is read-only
+    state.set_al(0x01);
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(state.al(), 0x00);
+}
+
+#[test]
+/// Instructions take longer for wait states in the memory block or I/O
+/// space they access, for words at odd addresses, and for instruction bytes
+/// that the prefetch queue doesn't hold yet.
+fn test_timing () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps  = 0x0000;
+    state.ds0 = 0x0000;
+    state.output_u8(WMB0, 0x00);  // Lower block 0-3FFFH, upper block FC000H-FFFFFH
+    state.output_u8(WCY1, 0x10);  // Lower 0 waits, middle 1
+    state.output_u8(WCY2, 0x32);  // Upper 2 waits, I/O 3
+    state.sp  = 0x1000;
+
+    // Clocks of a single instruction, starting with a full queue
+    let cycles = |state: &mut CPU, program: &[u8]| {
+        load(state, 0x100, program);
+        state.pc = 0x100;
+        state.queue = QUEUE_SIZE;
+        let clock = state.clock;
+        state.step(false).unwrap();
+        state.clock - clock
+    };
+
+    assert_eq!(cycles(&mut state, &[0xA1, 0x00, 0x10]), 5);     // MOV AW, [1000]
+    assert_eq!(cycles(&mut state, &[0xA1, 0x01, 0x10]), 7);     // MOV AW, [1001]
+    assert_eq!(cycles(&mut state, &[0xA0, 0x01, 0x10]), 5);     // MOV AL, [1001]
+    assert_eq!(cycles(&mut state, &[0xA1, 0x00, 0x80]), 6);     // MOV AW, [8000]
+    assert_eq!(cycles(&mut state, &[0xA1, 0x01, 0x80]), 9);     // MOV AW, [8001]
+    assert_eq!(cycles(&mut state, &[0x50]), 3);                 // PUSH AW
+    state.sp = 0x8001;
+    assert_eq!(cycles(&mut state, &[0x50]), 7);                 // PUSH AW
+    state.ds0 = 0xF000;
+    assert_eq!(cycles(&mut state, &[0xA1, 0x00, 0xC0]), 7);     // MOV AW, [C000]
+    assert_eq!(cycles(&mut state, &[0xE4, 0x10]), 8);           // IN AL, 10H
+    assert_eq!(cycles(&mut state, &[0xE5, 0x11]), 15);          // IN AW, 11H
+    state.dw = 0xFF80;
+    assert_eq!(cycles(&mut state, &[0xED]), 7);                 // IN AW, DW
+
+    // The peripheral units are on the chip, without wait states
+    state.output_u8(IULA, 0x20);
+    state.output_u8(TULA, 0x40);
+    state.output_u8(OPSEL, 0x06);
+    assert_eq!(cycles(&mut state, &[0xE4, 0x22]), 5);           // IN AL, 22H
+    assert_eq!(cycles(&mut state, &[0xE4, 0x40]), 5);           // IN AL, 40H
+    assert_eq!(cycles(&mut state, &[0xE4, 0x10]), 8);           // IN AL, 10H
+
+    // A branch empties the queue, which refills while it executes
+    // (by 2 words in 7 clocks, with 1 wait state in the middle block)
+    load(&mut state, 0x8000, &[
+        0xEB, 0x00,             // BR $+2
+        0x90,                   // NOP
+    ]);
+    state.pc = 0x8000;
+    state.queue = QUEUE_SIZE;
+    state.step(false).unwrap();
+    assert_eq!(state.queue_length(), 4);
+    let clock = state.clock;
+    state.step(false).unwrap();
+    assert_eq!(state.clock - clock, 1);
+
+    // Instruction bytes missing from the queue stall execution
+    state.pc = 0x8000;
+    state.queue = 0;
+    let clock = state.clock;
+    state.step(false).unwrap();
+    assert_eq!(state.clock - clock, 3 + 7);
+}
+
+#[test]
+/// Offsets wrap around within their 64 KB segment, and linear addresses
+/// wrap around at 1 MB, on every path to memory.
+fn test_address_wrap () {
+    let mut state = CPU::new(vec![]).unwrap();
+
+    assert_eq!(linear_address(0xFFFF, 0x0010), 0x00000);
+    assert_eq!(linear_address(0xFFFF, 0x000F), 0xFFFFF);
+    assert_eq!(linear_address(0xF000, 0xFFFF), 0xFFFFF);
+
+    // Base, index and displacement add up to a 16-bit offset
+    state.bw = 0xFFFF;
+    state.ix = 0x0002;
+    let operand = Operand::Memory { mode: 0b01, mem: 0b000, disp: 0x0001 };
+    assert_eq!(state.operand_address(operand), Some((Segment::DS0, 0x0002)));
+
+    // Data: DS0 near the top of memory, and a word at offset FFFFH
+    state.ds0 = 0xFFFF;
+    assert_eq!(state.effective_address(0x0010), 0x00000);
+    state.write_u16(0x0010, 0x1234);
+    assert_eq!(peek(&state, 0x00000..0x00002), &[0x34, 0x12]);
+    state.ds0 = 0x1000;
+    state.write_u16(0xFFFF, 0xABCD);
+    assert_eq!(peek(&state, 0x1FFFF..0x20000), &[0xCD]);
+    assert_eq!(peek(&state, 0x10000..0x10001), &[0xAB]);
+    assert_eq!(state.read_u16(0xFFFF), 0xABCD);
+
+    // Stack: SP wraps around in both directions, as do words at SS:FFFFH
+    state.ss = 0x2000;
+    state.sp = 0x0000;
+    state.push_u16(0x5678);
+    assert_eq!(state.sp(), 0xFFFE);
+    assert_eq!(peek(&state, 0x2FFFE..0x30000), &[0x78, 0x56]);
+    assert_eq!(state.pop_u16(), 0x5678);
+    assert_eq!(state.sp(), 0x0000);
+    state.sp = 0x0001;
+    state.push_u16(0x9ABC);
+    assert_eq!(peek(&state, 0x2FFFF..0x30000), &[0xBC]);
+    assert_eq!(peek(&state, 0x20000..0x20001), &[0x9A]);
+
+    // The stack goes through the page registers in XA mode
+    state.set_page_register(0x20000 >> 14, 0x020);
+    state.set_xa(true);
+    state.sp = 0x0010;
+    state.push_u16(0xDEF0);
+    assert_eq!(peek(&state, 0x8000E..0x80010), &[0xF0, 0xDE]);
+    assert_eq!(state.pop_u16(), 0xDEF0);
+    state.set_xa(false);
+
+    // Program: PC wraps around within PS, and PS:PC at the top of memory
+    load(&mut state, 0xFFFFF, &[0xB0]);         // MOV AL, 42H
+    load(&mut state, 0xF0000, &[0x42]);
+    state.ps = 0xF000;
+    state.pc = 0xFFFF;
+    state.step(false).unwrap();
+    assert_eq!(state.al(), 0x42);
+    assert_eq!(state.pc(), 0x0001);
+    assert_eq!(state.program_address(), 0xF0001);
+    state.ps = 0xFFFF;
+    state.pc = 0x0010;
+    assert_eq!(state.program_address(), 0x00000);
+
+    // Far pointers: the segment word of a pointer at offset FFFEH is at 0000H
+    load(&mut state, 0x1FFFE, &[0x34, 0x12]);
+    load(&mut state, 0x10000, &[0x00, 0xE0]);
+    load(&mut state, 0x00000, &[
+        0xC5, 0b00_110_110, 0xFE, 0xFF,         // MOV DS0, IX, [FFFE]
+    ]);
+    state.ps = 0x0000;
+    state.pc = 0x0000;
+    state.step(false).unwrap();
+    assert_eq!((state.ds0(), state.ix()), (0xE000, 0x1234));
+
+    // Addresses based on BP are in SS, unless overridden; others in DS0
+    state.ds0 = 0x1000;
+    state.ss  = 0x3000;
+    state.bp  = 0x0100;
+    state.ix  = 0x0000;
+    load(&mut state, 0x300FE, &[0x11, 0x22]);
+    load(&mut state, 0x100FE, &[0x33, 0x44]);
+    load(&mut state, 0x00000, &[
+        0x8B, 0b01_000_110, 0xFE,               // MOV AW, [BP-2]
+        0x8B, 0b01_001_010, 0xFE,               // MOV CW, [BP+IX-2]
+        0x3E, 0x8B, 0b01_010_110, 0xFE,         // MOV DW, DS0:[BP-2]
+        0x8B, 0b10_011_100, 0xFE, 0x00,         // MOV BW, [IX+00FEH]
+        0x89, 0b01_000_110, 0x00,               // MOV [BP], AW
+    ]);
+    state.pc = 0x0000;
+    for _ in 0..6 { state.step(false).unwrap() }
+    assert_eq!((state.aw(), state.cw(), state.dw(), state.bw()), (0x2211, 0x2211, 0x4433, 0x4433));
+    assert_eq!(peek(&state, 0x30100..0x30102), &[0x11, 0x22]);
+}
+
+#[test]
+/// Test vectors in the single-step JSON format run one instruction each,
+/// and are tallied by opcode.
+fn test_vectors () {
+    let vectors = Vector::parse_all(r#"[
+        {
+            "name": "add word [bx+si], dx",
+            "bytes": [1, 16],
+            "initial": {
+                "regs": {"ax": 0, "bx": 256, "cx": 0, "dx": 34952, "cs": 0, "ss": 0,
+                         "ds": 16, "es": 0, "sp": 0, "bp": 0, "si": 4, "di": 0,
+                         "ip": 1024, "flags": 61442},
+                "ram": [[1024, 1], [1025, 16], [516, 17], [517, 17]],
+                "queue": [1, 16]
+            },
+            "final": {
+                "regs": {"ip": 1026, "flags": 61574},
+                "ram": [[516, 153], [517, 153]],
+                "queue": []
+            },
+            "cycles": [[], [], [], [], [], [], [], []]
+        },
+        {
+            "name": "mov al, 42h",
+            "bytes": [176, 66],
+            "initial": {"regs": {"ax": 0, "cs": 0, "ip": 0}, "ram": []},
+            "final": {"regs": {"ax": 67, "ip": 2}, "ram": []}
+        },
+        {
+            "name": "segment override and repeat",
+            "bytes": [54, 243, 164],
+            "initial": {
+                "regs": {"cx": 2, "ds": 0, "es": 0, "ss": 4096, "si": 512, "di": 768, "cs": 0, "ip": 0},
+                "ram": [[512, 1], [513, 2], [66048, 3], [66049, 4]]
+            },
+            "final": {
+                "regs": {"cx": 0, "si": 514, "di": 770, "ip": 3},
+                "ram": [[768, 3], [769, 4]]
+            }
+        },
+        {
+            "name": "loop $",
+            "bytes": [226, 254],
+            "initial": {"regs": {"cx": 3, "cs": 0, "ip": 0}, "ram": []},
+            "final": {"regs": {"cx": 2, "ip": 0}, "ram": []}
+        }
+    ]"#).unwrap();
+    assert_eq!(vectors.len(), 4);
+    assert_eq!(vectors[0].initial.registers[0], Some(0));
+    assert_eq!(vectors[0].initial.queue, 2);
+    assert_eq!(vectors[0].cycles, Some(8));
+    assert_eq!(vectors[0].expected.memory, &[(516, 153), (517, 153)]);
+
+    let mut report = Report::new();
+    let results: Vec<_> = vectors.iter().map(|vector| vector.run(0xFFFF)).collect();
+    assert_eq!(results[0], &[Mismatch::Cycles { expected: 8, actual: 7 }]);
+    assert_eq!(results[1], &[Mismatch::Register { name: "AW", expected: 0x43, actual: 0x42 }]);
+    assert_eq!(results[2], &[]);
+    // A branch to itself is a single iteration
+    assert_eq!(results[3], &[]);
+    report.record("01", &results[0]);
+    report.record("B0", &results[1]);
+    report.record("A4", &results[2]);
+    assert_eq!(report.total(), Tally { passed: 1, cycles: 1, failed: 1 });
+    assert_eq!(report.to_string(), "\
+opcode      pass  cycles    fail
+01             0       1       0
+A4             1       0       0
+B0             0       0       1
+total          1       1       1
+");
+
+    assert!(Vector::parse_all(r#"{"bytes": [144]}"#).is_err());
+    assert!(Vector::parse_all(r#"[{"bytes": [144], "initial": {"regs": {"zz": 1}}, "final": {}}]"#).is_err());
+}
+
+#[test]
+/// Execute whole instructions, including branches to themselves,
+/// prefixes and every iteration of a repetition.
+fn test_step_instruction () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.ds1 = 0x0000;
+    state.iy = 0x0300;
+    let program = [
+        0xEB, 0xFE,       // BR $
+        0xE2, 0xFE,       // DBNZ $
+        0xF3, 0x90,       // REP NOP
+        0xF3, 0xAA,       // REP STM
+    ];
+    load(&mut state, 0x100, &program);
+    state.pc = 0x100;
+    state.set_cw(3);
+
+    let clock = state.clock;
+    state.step_instruction().unwrap();
+    assert_eq!(state.pc(), 0x100);
+    assert!(state.clock - clock < 100);
+
+    state.pc = 0x102;
+    let clock = state.clock;
+    state.step_instruction().unwrap();
+    assert_eq!((state.pc(), state.cw()), (0x102, 2));
+    assert!(state.clock - clock < 100);
+
+    state.pc = 0x104;
+    state.step_instruction().unwrap();
+    assert_eq!((state.pc(), state.cw()), (0x106, 2));
+
+    state.step_instruction().unwrap();
+    assert_eq!((state.pc(), state.cw()), (0x108, 0));
+    assert_eq!(state.iy(), 0x0302);
+}
+
+#[test]
+/// Follow a reference trace, and stop at the first instruction whose
+/// outcome differs from it.
+fn test_trace () {
+    let program = [
+        0xB8, 0x34, 0x12, // MOV AW, 1234H
+        0xA3, 0x00, 0x02, // MOV [0200H], AW
+        0x40,             // INC AW
+        0x90,             // NOP
+    ];
+    let trace = "\
+00000: AW=0000 PC=0000  mov aw,1234h
+00003: AW=1234 PC=0003  mov [0200h],aw
+W 00200=1234
+00006: AW=1234 F=F004  inc aw
+00007: AW=1236  nop
+";
+    let run = |trace: &str| {
+        let mut state = CPU::new(program.to_vec()).unwrap();
+        state.ps = 0x0000;
+        let result = state.lockstep(TraceReader::new(trace.as_bytes()), 0xFFFF);
+        // Writes are no longer logged, however it ended
+        assert!(state.write_log.is_none());
+        result
+    };
+
+    let records: Vec<_> = TraceReader::new(trace.as_bytes()).collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[1].addr, 0x00003);
+    assert_eq!(records[1].registers[0], Some(0x1234));
+    assert_eq!(records[1].text, "mov [0200h],aw");
+    assert_eq!(records[1].writes, &[(0x200, 0x34), (0x201, 0x12)]);
+    assert_eq!(records[2].registers[13], Some(0xF004));
+
+    // The increment went wrong, as found before the next instruction
+    let divergence = run(trace).unwrap_err();
+    assert_eq!(divergence.steps, 2);
+    assert_eq!(divergence.line, 5);
+    assert_eq!(divergence.instruction.map(|(addr, _)| addr), Some(0x00006));
+    assert_eq!(divergence.reference.as_ref().map(|record| record.line), Some(4));
+    assert_eq!(divergence.mismatches, &[Mismatch::Register { name: "AW", expected: 0x1236, actual: 0x1235 }]);
+
+    // A memory write went wrong
+    let divergence = run(&trace.replace("=1234\n", "=12\n")).unwrap_err();
+    assert_eq!(divergence.steps, 1);
+    assert_eq!(divergence.line, 2);
+    assert_eq!(divergence.mismatches, &[
+        Mismatch::Write { expected: Some((0x200, 0x12)), actual: Some((0x200, 0x34)) },
+        Mismatch::Write { expected: None, actual: Some((0x201, 0x12)) },
+    ]);
+
+    assert_eq!(run(&trace.replace("AW=1236", "AW=1235")), Ok(4));
+    let mut reader = TraceReader::new("00000: nop\n   (loops for 3 instructions)\n".as_bytes());
+    assert_eq!(reader.next(), None);
+    assert!(reader.error().is_some());
+}
+
+#[test]
+/// Save the state, run on, then load it back and run the same again.
+fn test_save_state () {
+    let map = || MemoryMap::new(Unmapped::Fault)
+        .ram(0x00000, 0x1000)
+        .rom(0xFFFF0, vec![
+            0x40,       // INC AW
+            0x50,       // PUSH AW
+            0xF4,       // HALT
+        ]);
+    let mut state = CPU::with_bus(Box::new(map()));
+    state.sp = 0x0100;
+    state.set_page_register(3, 0x123);
+    state.step(false).unwrap();
+    let saved = state.save_state();
+    assert_eq!(&saved[..4], b"V53S");
+    assert_eq!(u16::from_le_bytes([saved[4], saved[5]]), STATE_VERSION);
+
+    state.step(false).unwrap();
+    assert_eq!(state.step(false), Err(Stop::Halted));
+    let (clock, memory) = (state.clock, peek(&state, 0xFE..0x100));
+    assert_eq!(memory, &[0x01, 0x00]);
+
+    state.load_state(&saved).unwrap();
+    assert_eq!((state.aw, state.sp, state.pc), (0x0001, 0x0100, 0x0001));
+    assert_eq!(state.page_register(3), 0x123);
+    assert!(!state.halted());
+    // RAM is restored too
+    assert_eq!(peek(&state, 0xFE..0x100), &[0x00, 0x00]);
+    state.step(false).unwrap();
+    assert_eq!(state.step(false), Err(Stop::Halted));
+    assert_eq!((state.clock, peek(&state, 0xFE..0x100)), (clock, memory));
+
+    // States that don't fit are rejected, leaving the CPU as it was
+    let mut version = saved.clone();
+    version[4] = 0xFF;
+    assert_eq!(state.load_state(&version), Err(StateError::Version { found: 0x00FF, expected: STATE_VERSION }));
+    assert_eq!(state.load_state(&saved[..saved.len() - 1]), Err(StateError::Truncated));
+    assert_eq!(state.load_state(b"MZ"), Err(StateError::Format));
+    let mut other = CPU::with_bus(Box::new(map().ram(0x10000, 0x1000)));
+    assert_eq!(other.load_state(&saved), Err(StateError::Layout));
+    assert_eq!(state.pc, 0x0003);
+    assert_eq!(peek(&state, 0xFE..0x100), &[0x01, 0x00]);
+}
+
+#[test]
+/// Program the ICU, and accept the interrupts it prioritizes.
+fn test_icu () {
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.pc = 0x0100;
+    state.sp = 0x0800;
+    load(&mut state, 0x0100, &[0x90; 0x10]);
+    load(&mut state, 0x1000, &[0x90; 0x1000]);
+    // Vectors 20H-27H and FFH point to 1000H + vector * 10H
+    for vector in (0x20..0x28).chain([0xFF]) {
+        load(&mut state, vector * 4, &(0x1000 + vector as u16 * 0x10).to_le_bytes());
+    }
+    state.set_ie(true);
+
+    // ICU at ports 20H and 22H
+    state.output_u8(IULA, 0x20);
+    state.output_u8(OPSEL, 0x02);
+    assert_eq!(state.peripheral(0x22), Some((Unit::Icu, 1)));
+    assert_eq!(state.peripheral(0x21), None);
+
+    // Nothing is requested before initialization
+    state.set_irq(1, true);
+    state.set_irq(1, false);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x0101);
+
+    state.output_u8(0x20, 0x13); // ICW1: edge-triggered, single, ICW4
+    state.output_u8(0x22, 0x20); // ICW2: vectors 20H-27H
+    state.output_u8(0x22, 0x01); // ICW4: 8086 mode
+    state.output_u8(0x22, 0xF8); // OCW1: only IR0-IR2
+    assert_eq!(state.input_u8(0x22), 0xF8);
+
+    // Higher priorities first, lower ones after EOI
+    state.set_irq(1, true);
+    state.set_irq(0, true);
+    state.set_irq(3, true);
+    assert!(state.icu().interrupt());
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1200);
+    state.output_u8(0x20, 0x0B); // OCW3: read ISR
+    assert_eq!(state.input_u8(0x20), 0x01);
+    state.set_ie(true);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1201, "IR0 is in service");
+    state.output_u8(0x20, 0x20); // Non-specific EOI
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1210);
+    state.set_ie(true);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1211);
+    state.output_u8(0x20, 0x61); // Specific EOI of IR1
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1212, "IR0 and IR1 were acknowledged, IR3 is masked");
+
+    // Level-triggered inputs request again until they go low
+    state.output_u8(0x20, 0x1B);
+    state.output_u8(0x22, 0x20);
+    state.output_u8(0x22, 0x03); // Automatic EOI
+    state.output_u8(0x22, 0xF8);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1200);
+    state.set_ie(true);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1200);
+    state.set_irq(0, false);
+    state.set_ie(true);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1210);
+    state.output_u8(0x20, 0x0C); // Poll
+    assert_eq!(state.input_u8(0x20), 0x81);
+
+    // A slave on IR2 supplies the vector (here nothing answers)
+    state.output_u8(0x20, 0x11);
+    state.output_u8(0x22, 0x20);
+    state.output_u8(0x22, 0x04); // ICW3: slave on IR2
+    state.output_u8(0x22, 0x01);
+    state.output_u8(0x22, 0xFB);
+    state.set_irq(2, true);
+    state.set_ie(true);
+    state.step(false).unwrap();
+    assert_eq!(state.pc(), 0x1FF0);
+}
+
+#[test]
+/// Count in each mode of the TCU, and interrupt from counter 0.
+fn test_tcu () {
+    let mut tcu = Tcu::default();
+    let outputs = |tcu: &mut Tcu, index: usize, pulses: usize| -> String {
+        (0..pulses).map(|_| {
+            tcu.clock(1 << index);
+            if tcu.counter(index).out() { '1' } else { '0' }
+        }).collect()
+    };
+
+    // Mode 0: high once the count has run out
+    tcu.write(3, 0x30);
+    tcu.write(0, 0x04);
+    tcu.write(0, 0x00);
+    assert_eq!(outputs(&mut tcu, 0, 7), "0000111");
+    assert_eq!(tcu.counter(0).count(), 0xFFFE);
+
+    // Mode 2: low for a clock in every period
+    tcu.write(3, 0x74); // Counter 1, LSB then MSB, mode 2
+    tcu.write(1, 0x03);
+    tcu.write(1, 0x00);
+    assert_eq!(outputs(&mut tcu, 1, 10), "1101101101");
+
+    // Mode 3: odd counts are high one clock longer than low
+    tcu.write(3, 0x96); // Counter 2, LSB only, mode 3
+    tcu.write(2, 0x05);
+    assert_eq!(outputs(&mut tcu, 2, 11), "11100111001");
+
+    // Mode 4: strobe once
+    tcu.write(3, 0x18); // Counter 0, LSB only, mode 4
+    tcu.write(0, 0x02);
+    assert_eq!(outputs(&mut tcu, 0, 6), "110111");
+
+    // Modes 1 and 5 wait for the gate
+    tcu.write(3, 0x12); // Counter 0, LSB only, mode 1
+    tcu.write(0, 0x02);
+    tcu.set_gate(0, false);
+    assert_eq!(outputs(&mut tcu, 0, 2), "11");
+    tcu.set_gate(0, true);
+    assert_eq!(outputs(&mut tcu, 0, 4), "0011");
+    tcu.write(3, 0x1A); // Mode 5
+    tcu.write(0, 0x02);
+    tcu.set_gate(0, false);
+    tcu.set_gate(0, true);
+    assert_eq!(outputs(&mut tcu, 0, 5), "11011");
+
+    // BCD counting
+    tcu.write(3, 0x31); // Counter 0, LSB then MSB, mode 0, BCD
+    tcu.write(0, 0x10);
+    tcu.write(0, 0x00);
+    outputs(&mut tcu, 0, 2);
+    assert_eq!(tcu.counter(0).count(), 0x0009);
+
+    // The latch command holds the count until it has been read
+    tcu.write(3, 0x00);
+    outputs(&mut tcu, 0, 3);
+    assert_eq!((tcu.read(0), tcu.read(0)), (0x09, 0x00));
+    assert_eq!((tcu.read(0), tcu.read(0)), (0x06, 0x00));
+
+    // The read-back command latches the status, then the count
+    tcu.write(3, 0xC2);
+    assert_eq!(tcu.read(0), 0b0011_0001);
+    assert_eq!((tcu.read(0), tcu.read(0)), (0x06, 0x00));
+
+    // Counter 0 of the TCU at ports 40H-46H requests IR0 every 100 clocks
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.pc = 0x0100;
+    state.sp = 0x0800;
+    load(&mut state, 0x0100, &[0xEB, 0xFE]); // BR $
+    load(&mut state, 0x20 * 4, &[0x00, 0x10, 0x00, 0x00]);
+    state.output_u8(WCY1, 0x00);
+    state.output_u8(WCY2, 0x00);
+    state.output_u8(IULA, 0x20);
+    state.output_u8(TULA, 0x40);
+    state.output_u8(OPSEL, 0x06);
+    // The output goes high when the counter is programmed, before the
+    // ICU is initialized
+    state.output_u8(0x46, 0x34); // Counter 0, mode 2
+    state.output_u8(0x40, 50);
+    state.output_u8(0x40, 0);
+    state.output_u8(0x20, 0x13);
+    state.output_u8(0x22, 0x20);
+    state.output_u8(0x22, 0x01);
+    state.set_ie(true);
+    while state.pc() != 0x1000 {
+        state.step(false).unwrap();
+        assert!(state.clock < 200);
+    }
+    assert!(state.clock >= 100);
+    state.output_u8(0x46, 0x00);
+    assert!(state.input_u8(0x40) < 50);
+}
+
+#[test]
+/// Transfer between memory and devices with the DMA unit, programmed as
+/// a uPD71071 and as an 8237.
+fn test_dma () {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    /// Memory with a device on channel 1 that sends its bytes while it has
+    /// any, and a device on channel 2 that always takes bytes.
+    struct Devices {
+        memory:   Memory,
+        input:    VecDeque<u8>,
+        output:   Rc<RefCell<Vec<u8>>>,
+        terminal: Rc<RefCell<Vec<u8>>>,
+    }
+    impl Bus for Devices {
+        fn peek (&self, addr: u32) -> u8 {
+            self.memory.peek(addr)
+        }
+        fn write (&mut self, addr: u32, value: u8) {
+            self.memory.write(addr, value)
+        }
+        fn input (&mut self, port: u16) -> u8 {
+            self.memory.input(port)
+        }
+        fn output (&mut self, port: u16, value: u8) {
+            self.memory.output(port, value)
+        }
+        fn dma_requests (&mut self) -> u8 {
+            0b0100 | if self.input.is_empty() { 0 } else { 0b0010 }
+        }
+        fn dma_read (&mut self, channel: u8) -> u8 {
+            assert_eq!(channel, 1);
+            self.input.pop_front().unwrap()
+        }
+        fn dma_write (&mut self, channel: u8, value: u8) {
+            assert_eq!(channel, 2);
+            self.output.borrow_mut().push(value)
+        }
+        fn dma_terminal_count (&mut self, channel: u8) {
+            self.terminal.borrow_mut().push(channel)
+        }
+    }
+
+    let output = Rc::new(RefCell::new(vec![]));
+    let terminal = Rc::new(RefCell::new(vec![]));
+    let mut state = CPU::with_bus(Box::new(Devices {
+        memory:   Memory::new(0x10000, &[0x90; 0x100]),
+        input:    VecDeque::from([1, 2, 3, 4, 5, 6]),
+        output:   output.clone(),
+        terminal: terminal.clone(),
+    }));
+    state.ps = 0x0000;
+    state.output_u8(WCY1, 0x00);
+    state.output_u8(WCY2, 0x00);
+    load(&mut state, 0x3000, &[0xAA, 0xBB]);
+
+    // DMA unit at ports 80H-8FH, with uPD71071 registers
+    state.output_u8(DULA, 0x80);
+    state.output_u8(SCTL, 0x01);
+    state.output_u8(OPSEL, 0x01);
+    assert_eq!(state.input_u8(0x8F), 0x0F, "all channels masked");
+
+    // Channel 1: 4 bytes from the device to 2000H, on demand
+    state.output_u8(0x81, 0x01);
+    state.output_u16(0x82, 0x0003);
+    state.output_u16(0x84, 0x2000);
+    state.output_u8(0x86, 0x00);
+    state.output_u8(0x8A, 0x04);
+    assert_eq!(state.input_u16(0x84), 0x2000);
+    // Channel 2: bytes from 3000H to the device, one at a time, repeatedly
+    state.output_u8(0x81, 0x02);
+    state.output_u16(0x82, 0x0001);
+    state.output_u16(0x84, 0x3000);
+    state.output_u8(0x86, 0x00);
+    state.output_u8(0x8A, 0x58);
+    state.output_u8(0x8F, 0x09);
+
+    // Channel 1 has priority, and transfers while the device requests
+    let clock = state.clock;
+    state.log_writes(true);
+    state.step(false).unwrap();
+    assert_eq!(peek(&state, 0x2000..0x2005), &[1, 2, 3, 4, 0]);
+    assert_eq!(state.take_writes(), &[(0x2000, 1), (0x2001, 2), (0x2002, 3), (0x2003, 4)]);
+    state.log_writes(false);
+    assert_eq!(state.clock - clock, 4 * BUS_CYCLE + 3, "4 transfers, then NOP");
+    assert_eq!(*output.borrow(), &[]);
+    assert_eq!(*terminal.borrow(), &[1]);
+    assert_eq!(state.input_u8(0x8F), 0x0B, "channel 1 is masked at terminal count");
+    assert_eq!(state.input_u8(0x8B), 0x02);
+    assert_eq!(state.input_u8(0x8B), 0x00);
+    for _ in 0..3 { state.step(false).unwrap() }
+    assert_eq!(*output.borrow(), &[0xAA, 0xBB, 0xAA], "channel 2 autoinitializes");
+    assert_eq!(*terminal.borrow(), &[1, 2]);
+    state.output_u8(0x81, 0x02);
+    assert_eq!(state.input_u16(0x84), 0x3001);
+    assert_eq!(state.input_u16(0x82), 0x0000);
+    state.output_u8(0x8F, 0x0F);
+
+    // With 8237 registers: copy 3 bytes from 3000H to 4000H
+    state.output_u8(SCTL, 0x03);
+    state.output_u8(0x8D, 0x00); // Master clear
+    for (register, value) in [(0x80, 0x3000), (0x81, 0x0002), (0x82, 0x4000), (0x83, 0x0002)] {
+        state.output_u8(register, value as u8);
+        state.output_u8(register, (value >> 8) as u8);
+    }
+    state.output_u8(0x88, 0x01); // Memory to memory
+    state.output_u8(0x8B, 0x88); // Channel 0: block, read
+    state.output_u8(0x8B, 0x85); // Channel 1: block, write
+    state.output_u8(0x8E, 0x00); // Unmask all
+    state.output_u8(0x89, 0x04); // Request channel 0
+    state.output_u8(WCY1, 0x11); // 1 wait state
+    let clock = state.clock;
+    state.step(false).unwrap();
+    assert_eq!(state.clock - clock, 3 * 2 * (BUS_CYCLE + 1) + 3 + 1, "3 reads and writes, then NOP");
+    assert_eq!(peek(&state, 0x4000..0x4004), &[0xAA, 0xBB, 0x00, 0x00]);
+    assert_eq!(state.input_u8(0x88), 0x02);
+    assert_eq!(state.input_u8(0x80), 0x03);
+    assert_eq!(state.input_u8(0x80), 0x30);
+}
+
+#[test]
+/// Transmit and receive characters with the SCU, at the baud rate of
+/// counter 1 of the TCU.
+fn test_scu () {
+    let mut scu = Scu::default();
+    scu.write(2, 0x4D); // x1, 8 bits, no parity, 1 stop bit
+    scu.write(1, 0x05); // Transmitter and receiver enabled
+    scu.write(3, 0x00);
+    assert_eq!(scu.status() & (TX_READY | TX_EMPTY), TX_READY | TX_EMPTY);
+    assert!(scu.interrupt());
+
+    // A character takes 10 baud clocks, while the next one waits
+    scu.write(0, b'O');
+    scu.write(0, b'K');
+    assert_eq!(scu.status() & (TX_READY | TX_EMPTY), 0);
+    for _ in 0..9 { scu.clock() }
+    assert_eq!(scu.take_output(), b"");
+    scu.clock();
+    assert_eq!(scu.take_output(), b"O");
+    assert_eq!(scu.status() & (TX_READY | TX_EMPTY), TX_READY);
+    for _ in 0..10 { scu.clock() }
+    assert_eq!(scu.take_output(), b"K");
+    assert_eq!(scu.status() & TX_EMPTY, TX_EMPTY);
+
+    // Characters from the host, with an overrun if one isn't read in time
+    scu.send(b"ab");
+    for _ in 0..11 { scu.clock() }
+    assert_eq!(scu.status() & RX_READY, RX_READY);
+    for _ in 0..11 { scu.clock() }
+    assert_eq!(scu.status() & (RX_READY | OVERRUN_ERROR), RX_READY | OVERRUN_ERROR);
+    assert_eq!(scu.read(0), b'b');
+    scu.write(1, 0x15); // Error reset
+    assert_eq!(scu.status() & (RX_READY | OVERRUN_ERROR), 0);
+
+    // 7-bit characters lose the top bit
+    scu.write(2, 0x49);
+    scu.write(0, 0xC1);
+    for _ in 0..9 { scu.clock() }
+    assert_eq!(scu.take_output(), &[0x41]);
+
+    // The SCU at ports 50H-56H receives on IR1, with TxRDY masked
+    let mut state = CPU::new(vec![]).unwrap();
+    state.ps = 0x0000;
+    state.pc = 0x0100;
+    state.sp = 0x0800;
+    load(&mut state, 0x0100, &[0xEB, 0xFE]); // BR $
+    load(&mut state, 0x21 * 4, &[0x00, 0x10, 0x00, 0x00]);
+    state.output_u8(WCY1, 0x00);
+    state.output_u8(WCY2, 0x00);
+    state.output_u8(IULA, 0x20);
+    state.output_u8(TULA, 0x40);
+    state.output_u8(SULA, 0x50);
+    state.output_u8(OPSEL, 0x0E);
+    state.output_u8(0x46, 0x56); // Counter 1, LSB only, mode 3
+    state.output_u8(0x42, 0x02);
+    state.output_u8(0x54, 0x4D);
+    state.output_u8(0x56, 0x01); // Mask TxRDY
+    state.output_u8(0x52, 0x05);
+    state.output_u8(0x20, 0x13);
+    state.output_u8(0x22, 0x20);
+    state.output_u8(0x22, 0x01);
+    state.set_ie(true);
+    state.output_u8(0x50, b'!');
+    state.serial_send(b"?");
+    while state.pc() != 0x1000 {
+        state.step(false).unwrap();
+        assert!(state.clock < 500);
+    }
+    assert_eq!(state.serial_output(), b"!");
+    assert_eq!(state.input_u8(0x52) & RX_READY, RX_READY);
+    assert_eq!(state.input_u8(0x50), b'?');
+    assert_eq!(state.input_u8(0x52) & RX_READY, 0);
+}
+
+#[test]
+/// Move the internal I/O block with IDB, and log writes to internal
+/// addresses without a register.
+fn test_internal_io () {
+    let mut state = CPU::new(vec![]).unwrap();
+    assert_eq!(state.internal_offset(0xFFEB), Some(0xEB));
+    assert_eq!(state.internal_offset(0xFEEB), None);
+
+    // Writes where there is no register are logged and ignored
+    state.output_u8(0xFFC0, 0x12);
+    state.output_u8(WCY1, 0x00);
+    assert_eq!(state.take_unused_writes(), &[(0xC0, 0x12)]);
+    assert_eq!(state.take_unused_writes(), &[]);
+    assert_eq!(state.input_u8(0xFFC0), 0x00);
+    assert_eq!(state.input_u8(WCY1), 0x00);
+
+    // Moved to 1200H-12FFH, with IDB still at FFFFH
+    state.output_u8(IDB, 0x12);
+    assert_eq!(state.input_u8(0x12EB), 0x00);
+    assert_eq!(state.input_u8(IDB), 0x12);
+    assert_eq!(state.input_u8(0x12FF), 0x12);
+    state.output_u8(0xFFEB, 0x55);
+    assert_eq!(state.internal()[0xEB], 0x00);
+    assert_eq!(state.bus_mut().input(0xFFEB), 0x55);
+
+    // The internal block takes no bus cycles, wherever it is
+    let clocks = state.bus_clocks;
+    state.output_u8(0x12EB, 0x77);
+    assert_eq!(state.bus_clocks, clocks);
+    state.output_u8(0xFFEA, 0x00);
+    assert!(state.bus_clocks > clocks);
 
-    assert_eq!(state.clock, 23);
-    assert_eq!(state.pc, 12);
-    assert_eq!(state.memory()[0x0050], 0x88);
-    assert_eq!(state.memory()[0x0051], 0x88);
+    // The units are mapped by their registers in the moved block
+    state.output_u8(0x12FA, 0x20);
+    state.output_u8(0x12FD, 0x02);
+    assert_eq!(state.peripheral(0x22), Some((Unit::Icu, 1)));
+    state.output_u8(0x22, 0x5A);
+    assert_eq!(state.input_u8(0x22), 0x5A);
 }
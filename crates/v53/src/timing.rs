@@ -0,0 +1,167 @@
+//! Bus timing: the clocks of memory and I/O bus cycles with their wait
+//! states, and the prefetch queue, which fills during the clocks left idle
+//! by an instruction. Only the lower, middle and upper memory blocks set by
+//! WMB0 and their wait states in WCY1 and WCY2 are applied; the other wait
+//! control registers are held, but have no effect.
+
+use crate::*;
+
+/// Clocks of a bus cycle without wait states.
+pub const BUS_CYCLE: u64 = 2;
+
+/// Size of the instruction prefetch queue, in bytes.
+pub const QUEUE_SIZE: u16 = 6;
+
+/// Internal I/O address of the memory block boundary register. Bits 2-0 and
+/// 6-4 set the size of the lower and upper memory block to 16 KB << n; the
+/// middle block is what remains of the megabyte.
+pub const WMB0: u16 = 0xFFEA;
+
+/// Internal I/O address of the wait cycle register for the lower (bits 2-0)
+/// and middle (bits 6-4) memory blocks.
+pub const WCY1: u16 = 0xFFEB;
+
+/// Internal I/O address of the second memory block boundary register (WMB1).
+pub const WMB1: u16 = 0xFFF3;
+
+/// Internal I/O address of the wait cycle register for DMA and refresh
+/// cycles (WCY0).
+pub const WCY0: u16 = 0xFFEC;
+
+/// Internal I/O address of the wait cycle address control register (WAC).
+pub const WAC: u16 = 0xFFED;
+
+/// Internal I/O address of the first programmable wait cycle register (WCY3).
+pub const WCY3: u16 = 0xFFF5;
+
+/// Internal I/O address of the second programmable wait cycle register (WCY4).
+pub const WCY4: u16 = 0xFFF6;
+
+/// Internal I/O address of the wait cycle register for the upper memory
+/// block (bits 2-0) and external I/O (bits 6-4).
+pub const WCY2: u16 = 0xFFF4;
+
+impl CPU {
+
+    pub(crate) fn internal_register (&self, port: u16) -> u8 {
+        self.internal[(port - 0xFF00) as usize]
+    }
+
+    /// Wait states inserted in memory bus cycles at a physical address.
+    /// Blocks are selected by A19-A0, so they repeat every megabyte.
+    pub fn memory_wait_states (&self, addr: u32) -> u64 {
+        let boundaries = self.internal_register(WMB0);
+        let lower = 0x4000u32 << (boundaries & 0b111);
+        let upper = 0x4000u32 << ((boundaries >> 4) & 0b111);
+        let addr = addr & 0xFFFFF;
+        let waits = if addr < lower {
+            self.internal_register(WCY1)
+        } else if addr >= 0x100000u32.saturating_sub(upper) {
+            self.internal_register(WCY2)
+        } else {
+            self.internal_register(WCY1) >> 4
+        };
+        (waits & 0b111) as u64
+    }
+
+    /// Wait states inserted in external I/O bus cycles.
+    pub fn io_wait_states (&self) -> u64 {
+        ((self.internal_register(WCY2) >> 4) & 0b111) as u64
+    }
+
+    /// Account for a data transfer at a logical memory address. Cycle counts
+    /// of instructions assume one bus cycle per operand without wait states;
+    /// this adds the wait states, and the second bus cycle needed by a word
+    /// at an odd address.
+    pub fn memory_transfer (&mut self, addr: u32, word: bool) {
+        let waits = self.memory_wait_states(self.physical_address(addr));
+        self.bus_cycles(word && addr % 2 == 1, waits);
+    }
+
+    /// Account for a data transfer at an I/O port, like
+    /// [CPU::memory_transfer]. The internal I/O block and the peripheral
+    /// units, which are on the chip, take no bus cycles.
+    pub fn io_transfer (&mut self, port: u16, word: bool) {
+        if self.internal_offset(port).is_none() && self.peripheral(port).is_none() {
+            let waits = self.io_wait_states();
+            self.bus_cycles(word && port % 2 == 1, waits);
+        }
+    }
+
+    /// Account for a DMA bus cycle at a physical address, between
+    /// instructions, like [CPU::memory_transfer].
+    pub(crate) fn dma_cycle (&mut self, addr: u32, word: bool) {
+        let waits = self.memory_wait_states(addr);
+        self.bus_cycles(word && addr % 2 == 1, waits);
+        self.advance_clock(BUS_CYCLE);
+    }
+
+    fn bus_cycles (&mut self, split: bool, waits: u64) {
+        let cycles = 1 + split as u64;
+        self.pending    += (cycles - 1) * BUS_CYCLE + cycles * waits;
+        self.bus_clocks += cycles * (BUS_CYCLE + waits);
+    }
+
+    /// Address of the next instruction byte to prefetch.
+    fn prefetch_address (&self) -> u32 {
+        linear_address(self.ps, self.pc.wrapping_add(self.queue))
+    }
+
+    /// Clocks of the next prefetch bus cycle.
+    fn prefetch_clocks (&self) -> u64 {
+        BUS_CYCLE + self.memory_wait_states(self.physical_address(self.prefetch_address()))
+    }
+
+    /// Fetch a word (or a byte, at an odd address) into the prefetch queue.
+    /// Returns the clocks taken by the bus cycle.
+    fn prefetch_cycle (&mut self) -> u64 {
+        let clocks = self.prefetch_clocks();
+        self.queue += 2 - (self.prefetch_address() % 2) as u16;
+        clocks
+    }
+
+    /// Take the bytes of an instruction from the prefetch queue, stalling
+    /// until enough of them have been fetched.
+    pub(crate) fn dequeue (&mut self, length: u16) {
+        while self.queue < length {
+            let clocks = self.prefetch_cycle();
+            self.pending    += clocks;
+            self.bus_clocks += clocks;
+        }
+        self.queue -= length;
+    }
+
+    /// Discard the prefetch queue, as a branch does.
+    pub fn flush_queue (&mut self) {
+        self.queue = 0;
+    }
+
+    /// Discard the prefetch queue and the clocks accounted so far to an
+    /// instruction that couldn't be executed.
+    pub(crate) fn cancel_instruction (&mut self) {
+        self.queue = 0;
+        self.pending = 0;
+        self.bus_clocks = 0;
+    }
+
+    /// Number of instruction bytes waiting in the prefetch queue.
+    pub fn queue_length (&self) -> u16 {
+        self.queue
+    }
+
+    /// Add the cycles of an instruction or interrupt to the clock, along
+    /// with its stalls and wait states. The prefetch queue fills during the
+    /// clocks left over by data transfers.
+    pub(crate) fn advance_clock (&mut self, cycles: u64) {
+        let total = cycles + self.pending;
+        let mut idle = total.saturating_sub(self.bus_clocks);
+        while self.queue < QUEUE_SIZE && idle >= self.prefetch_clocks() {
+            idle -= self.prefetch_cycle();
+        }
+        self.queue = self.queue.min(QUEUE_SIZE);
+        self.clock += total;
+        self.pending = 0;
+        self.bus_clocks = 0;
+    }
+
+}
@@ -0,0 +1,254 @@
+//! Lockstep comparison with a reference trace, such as the log written by
+//! MAME's `trace` debugger command for the same ROM. Each instruction is a
+//! line with its address, the registers before executing it, and its
+//! disassembly:
+//!
+//! ```text
+//! FFFF0: AW=0000 BW=0000 CW=0000 DW=0000 PS=FFFF SS=0000 ... PSW=F002  jmp F000:0100
+//! ```
+//!
+//! The address is the first field ending in a colon. Registers are
+//! `NAME=HEX` fields, using V53 or 8086 names, and may appear before or
+//! after the address; `PC`/`IP` is the offset in PS, and `F` is accepted
+//! for PSW. Memory written by an instruction may follow it on lines of
+//! `W ADDR=VALUE` fields, with a byte (2 digits) or a little-endian word
+//! (4 digits) at a physical address. Other lines are ignored.
+
+use crate::*;
+use std::fmt::{Display, Formatter};
+use std::io::BufRead;
+
+/// An instruction of a reference trace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Line number in the trace, starting at 1
+    pub line:      usize,
+    /// Address of the instruction
+    pub addr:      u32,
+    /// Registers before executing the instruction, in the order of
+    /// [VECTOR_REGISTERS]. Registers that aren't given aren't compared.
+    pub registers: [Option<u16>;14],
+    /// Disassembly of the instruction by the reference
+    pub text:      String,
+    /// Bytes written to memory by the instruction, at physical addresses
+    pub writes:    Vec<(u32, u8)>,
+}
+
+impl TraceRecord {
+
+    /// Parse the line of an instruction, or return `None` if it isn't one.
+    pub fn parse (line: usize, text: &str) -> Option<Self> {
+        let mut record = Self { line, ..Self::default() };
+        let mut addr = None;
+        let mut rest = text.trim_start();
+        while let Some(field) = rest.split_whitespace().next() {
+            if let Some((name, value)) = field.split_once('=') {
+                let index = match name.to_ascii_lowercase().as_str() {
+                    "f" => Some(PSW),
+                    name => register_index(name)
+                };
+                if let (Some(index), Ok(value)) = (index, u16::from_str_radix(value, 16)) {
+                    record.registers[index] = Some(value);
+                }
+            } else if let Some(value) = field.strip_suffix(':').filter(|_| addr.is_none()) {
+                addr = Some(u32::from_str_radix(value, 16).ok()?);
+            } else {
+                break
+            }
+            rest = rest[field.len()..].trim_start();
+        }
+        record.addr = addr?;
+        record.text = rest.trim_end().into();
+        Some(record)
+    }
+
+    /// Parse a line of memory writes into the record, or return `false`
+    /// if it isn't one.
+    fn parse_writes (&mut self, text: &str) -> bool {
+        let mut fields = text.split_whitespace();
+        if fields.next() != Some("W") {
+            return false
+        }
+        let mut writes = vec![];
+        for field in fields {
+            let Some((addr, value)) = field.split_once('=') else {
+                return false
+            };
+            let (Ok(addr), Ok(data)) = (u32::from_str_radix(addr, 16), u16::from_str_radix(value, 16)) else {
+                return false
+            };
+            match value.len() {
+                2 => writes.push((addr, data as u8)),
+                4 => writes.extend([(addr, data as u8), (addr.wrapping_add(1), (data >> 8) as u8)]),
+                _ => return false
+            }
+        }
+        self.writes.extend(writes);
+        true
+    }
+
+}
+
+/// Reads the instructions of a trace, one at a time.
+pub struct TraceReader<R> {
+    lines: std::io::Lines<R>,
+    line:  usize,
+    /// Instruction read, waiting for its memory writes
+    next:  Option<TraceRecord>,
+    error: Option<String>,
+}
+
+impl<R: BufRead> TraceReader<R> {
+
+    pub fn new (reader: R) -> Self {
+        Self { lines: reader.lines(), line: 0, next: None, error: None }
+    }
+
+    /// Why the trace ended early, if it did.
+    pub fn error (&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = TraceRecord;
+    fn next (&mut self) -> Option<TraceRecord> {
+        loop {
+            let text = match self.lines.next() {
+                Some(Ok(text)) => text,
+                Some(Err(error)) => {
+                    self.error = Some(format!("line {}: {error}", self.line + 1));
+                    return None
+                },
+                None => return self.next.take()
+            };
+            self.line += 1;
+            if text.contains("(loops for") {
+                // Collapsed loops can't be followed
+                self.error = Some(format!("line {}: loops are collapsed, trace with noloop", self.line));
+                return None
+            }
+            if let Some(record) = TraceRecord::parse(self.line, &text) {
+                if let Some(previous) = self.next.replace(record) {
+                    return Some(previous)
+                }
+            } else if let Some(record) = self.next.as_mut() {
+                record.parse_writes(&text);
+            }
+        }
+    }
+}
+
+/// First difference between the CPU and a reference trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Number of instructions that matched
+    pub steps:       usize,
+    /// Address and decoding of the instruction that diverged,
+    /// if it isn't the first one
+    pub instruction: Option<(u32, Instruction)>,
+    /// Line of the instruction that diverged in the trace
+    pub reference:   Option<TraceRecord>,
+    /// Trace line where the difference was found
+    pub line:        usize,
+    pub mismatches:  Vec<Mismatch>,
+}
+
+impl Display for Divergence {
+    fn fmt (&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "diverged after {} instructions, at line {} of the trace", self.steps, self.line)?;
+        if let Some((addr, instruction)) = self.instruction {
+            writeln!(f, "  {addr:05X}  {instruction}")?;
+        }
+        if let Some(reference) = self.reference.as_ref() {
+            writeln!(f, "  {:05X}  {}  (line {})", reference.addr, reference.text, reference.line)?;
+        }
+        for mismatch in self.mismatches.iter() {
+            writeln!(f, "  {mismatch}")?;
+        }
+        Ok(())
+    }
+}
+
+impl CPU {
+
+    /// Differences between the state of the CPU and the state before
+    /// an instruction of a trace.
+    fn compare_trace (&self, record: &TraceRecord, flags_mask: u16) -> Vec<Mismatch> {
+        let mut mismatches = vec![];
+        let actual = self.program_address();
+        if actual != record.addr {
+            mismatches.push(Mismatch::Address { expected: record.addr, actual });
+        }
+        for (index, name) in VECTOR_REGISTERS.iter().enumerate() {
+            let Some(expected) = record.registers[index] else {
+                continue
+            };
+            let actual = self.vector_register(index);
+            let mask = if index == PSW { flags_mask } else { 0xFFFF };
+            if (actual ^ expected) & mask != 0 {
+                mismatches.push(Mismatch::Register { name, expected, actual });
+            }
+        }
+        mismatches
+    }
+
+    /// Execute instructions in lockstep with a reference trace, until its
+    /// end or the first difference in program address, registers or memory
+    /// writes. Flags outside of `flags_mask` are not compared. Returns the
+    /// number of instructions executed.
+    pub fn lockstep (
+        &mut self, trace: impl IntoIterator<Item = TraceRecord>, flags_mask: u16
+    ) -> Result<usize, Box<Divergence>> {
+        self.log_writes(true);
+        let result = self.follow_trace(trace, flags_mask);
+        self.log_writes(false);
+        result
+    }
+
+    fn follow_trace (
+        &mut self, trace: impl IntoIterator<Item = TraceRecord>, flags_mask: u16
+    ) -> Result<usize, Box<Divergence>> {
+        let mut previous: Option<(u32, Instruction, TraceRecord)> = None;
+        let mut steps: usize = 0;
+        for record in trace {
+            // Registers show the outcome of the previous instruction
+            let mismatches = self.compare_trace(&record, flags_mask);
+            if !mismatches.is_empty() {
+                let (instruction, reference) = match previous {
+                    Some((addr, instruction, reference)) => (Some((addr, instruction)), Some(reference)),
+                    None => (None, None)
+                };
+                return Err(Box::new(Divergence {
+                    steps: steps.saturating_sub(1), instruction, reference, line: record.line, mismatches
+                }))
+            }
+            let addr = self.program_address();
+            let (instruction, _) = self.peek_instruction();
+            self.take_writes();
+            let result = self.step_instruction();
+            let writes = self.take_writes();
+            let mut mismatches = vec![];
+            if let Err(stop) = result {
+                mismatches.push(Mismatch::Stop(stop));
+            }
+            for i in 0..writes.len().max(record.writes.len()) {
+                let (expected, actual) = (record.writes.get(i).copied(), writes.get(i).copied());
+                if expected != actual {
+                    mismatches.push(Mismatch::Write { expected, actual });
+                }
+            }
+            if !mismatches.is_empty() {
+                return Err(Box::new(Divergence {
+                    steps, instruction: Some((addr, instruction)), line: record.line,
+                    reference: Some(record), mismatches
+                }))
+            }
+            steps += 1;
+            previous = Some((addr, instruction, record));
+        }
+        Ok(steps)
+    }
+
+}
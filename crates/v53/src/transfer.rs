@@ -0,0 +1,40 @@
+use crate::*;
+
+impl CPU {
+
+    /// PUSH R: push AW, CW, DW, BW, the original SP, BP, IX and IY.
+    pub fn push_registers (&mut self) {
+        let sp = self.sp();
+        for value in [self.aw(), self.cw(), self.dw(), self.bw(), sp, self.bp(), self.ix(), self.iy()] {
+            self.push_u16(value);
+        }
+    }
+
+    /// POP R: pop IY, IX, BP, (discarded) SP, BW, DW, CW and AW.
+    pub fn pop_registers (&mut self) {
+        let iy = self.pop_u16();
+        self.set_iy(iy);
+        let ix = self.pop_u16();
+        self.set_ix(ix);
+        let bp = self.pop_u16();
+        self.set_bp(bp);
+        self.pop_u16();
+        let bw = self.pop_u16();
+        self.set_bw(bw);
+        let dw = self.pop_u16();
+        self.set_dw(dw);
+        let cw = self.pop_u16();
+        self.set_cw(cw);
+        let aw = self.pop_u16();
+        self.set_aw(aw);
+    }
+
+    /// TRANS: load AL from the table at DS0:BW (segment can be overridden),
+    /// indexed by AL.
+    pub fn translate (&mut self) {
+        let addr = self.bw().wrapping_add(self.al() as u16);
+        let data = self.read_u8(addr);
+        self.set_al(data);
+    }
+
+}
@@ -0,0 +1,288 @@
+//! Single-instruction test vectors, in the JSON format of the public
+//! V20/8088 single-step test suites: each test gives the initial registers
+//! and memory, the instruction bytes, the registers and memory that changed,
+//! and the bus cycles taken.
+
+use crate::*;
+use crate::json::Json;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result};
+
+/// Registers of a test vector state. 8086 names (`ax`, `cs`, `si`, `ip`,
+/// `flags`...) are accepted in place of these.
+pub const VECTOR_REGISTERS: [&str;14] = [
+    "AW", "BW", "CW", "DW", "PS", "SS", "DS0", "DS1", "SP", "BP", "IX", "IY", "PC", "PSW"
+];
+
+pub(crate) const PSW: usize = 13;
+
+pub(crate) fn register_index (name: &str) -> Option<usize> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "aw"  | "ax"    => 0,
+        "bw"  | "bx"    => 1,
+        "cw"  | "cx"    => 2,
+        "dw"  | "dx"    => 3,
+        "ps"  | "cs"    => 4,
+        "ss"            => 5,
+        "ds0" | "ds"    => 6,
+        "ds1" | "es"    => 7,
+        "sp"            => 8,
+        "bp"            => 9,
+        "ix"  | "si"    => 10,
+        "iy"  | "di"    => 11,
+        "pc"  | "ip"    => 12,
+        "psw" | "flags" => 13,
+        _ => return None
+    })
+}
+
+impl CPU {
+
+    pub(crate) fn vector_register (&self, index: usize) -> u16 {
+        [
+            self.aw, self.bw, self.cw, self.dw, self.ps, self.ss, self.ds0,
+            self.ds1, self.sp, self.bp, self.ix, self.iy, self.pc, self.psw
+        ][index]
+    }
+
+    pub(crate) fn set_vector_register (&mut self, index: usize, value: u16) {
+        *[
+            &mut self.aw, &mut self.bw, &mut self.cw, &mut self.dw, &mut self.ps,
+            &mut self.ss, &mut self.ds0, &mut self.ds1, &mut self.sp, &mut self.bp,
+            &mut self.ix, &mut self.iy, &mut self.pc, &mut self.psw
+        ][index] = value;
+    }
+
+}
+
+/// Registers and memory before or after a test.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VectorState {
+    /// Values of the registers, in the order of [VECTOR_REGISTERS].
+    /// Registers that aren't given are unchanged (after) or don't matter (before).
+    pub registers: [Option<u16>;14],
+    /// Bytes of memory at physical addresses
+    pub memory: Vec<(u32, u8)>,
+    /// Number of instruction bytes already in the prefetch queue
+    pub queue: u16,
+}
+
+impl VectorState {
+    fn from_json (json: &Json) -> std::result::Result<Self, String> {
+        let mut state = Self::default();
+        let registers = json.get("regs").and_then(Json::as_object).unwrap_or(&[]);
+        for (name, value) in registers {
+            let index = register_index(name).ok_or_else(|| format!("unknown register {name}"))?;
+            let value = value.as_u64().ok_or_else(|| format!("invalid value of {name}"))?;
+            state.registers[index] = Some(value as u16);
+        }
+        for entry in json.get("ram").and_then(Json::as_array).unwrap_or(&[]) {
+            match entry.as_array().unwrap_or(&[]) {
+                [addr, value] => match (addr.as_u64(), value.as_u64()) {
+                    (Some(addr), Some(value)) => state.memory.push((addr as u32, value as u8)),
+                    _ => return Err("invalid ram entry".into())
+                },
+                _ => return Err("invalid ram entry".into())
+            }
+        }
+        state.queue = json.get("queue").and_then(Json::as_array).map_or(0, |queue| queue.len() as u16);
+        Ok(state)
+    }
+}
+
+/// A single-instruction test.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Vector {
+    pub name:     String,
+    /// Bytes of the instruction, including prefixes
+    pub bytes:    Vec<u8>,
+    pub initial:  VectorState,
+    /// Registers and memory that the instruction changes
+    pub expected: VectorState,
+    /// Clock cycles the instruction takes, if known
+    pub cycles:   Option<u64>,
+}
+
+impl Vector {
+
+    /// Parse a file of test vectors: an array of tests, or a single test.
+    pub fn parse_all (text: &str) -> std::result::Result<Vec<Self>, String> {
+        match Json::parse(text)? {
+            Json::Array(tests) => tests.iter().enumerate()
+                .map(|(i, test)| Self::from_json(test).map_err(|e| format!("test {i}: {e}")))
+                .collect(),
+            test => Ok(vec![Self::from_json(&test)?])
+        }
+    }
+
+    fn from_json (json: &Json) -> std::result::Result<Self, String> {
+        let bytes = json.get("bytes").and_then(Json::as_array).ok_or("missing bytes")?
+            .iter().map(|byte| byte.as_u64().map(|byte| byte as u8)).collect::<Option<_>>()
+            .ok_or("invalid bytes")?;
+        // Either a count, or a list of the states of each cycle
+        let cycles = json.get("cycles").and_then(|cycles| cycles.as_u64()
+            .or_else(|| cycles.as_array().map(|cycles| cycles.len() as u64)));
+        Ok(Self {
+            name:     json.get("name").and_then(Json::as_str).unwrap_or_default().into(),
+            bytes,
+            initial:  VectorState::from_json(json.get("initial").ok_or("missing initial state")?)?,
+            expected: VectorState::from_json(json.get("final").ok_or("missing final state")?)?,
+            cycles,
+        })
+    }
+
+    /// Run the test on a CPU with 1 MB of memory and no wait states, and
+    /// list the differences from the expected state. Flags outside of
+    /// `flags_mask` (undefined after the instruction) are not compared.
+    pub fn run (&self, flags_mask: u16) -> Vec<Mismatch> {
+        let mut cpu = CPU::new(vec![]).unwrap();
+        cpu.output_u8(WCY1, 0x00);
+        cpu.output_u8(WCY2, 0x00);
+        for (index, value) in self.initial.registers.iter().enumerate() {
+            if let Some(value) = value {
+                cpu.set_vector_register(index, *value);
+            }
+        }
+        let (ps, pc) = (cpu.ps(), cpu.pc());
+        for (i, byte) in self.bytes.iter().enumerate() {
+            cpu.bus_mut().write(linear_address(ps, pc.wrapping_add(i as u16)), *byte);
+        }
+        for (addr, value) in self.initial.memory.iter() {
+            cpu.bus_mut().write(*addr, *value);
+        }
+        cpu.queue = self.initial.queue.min(QUEUE_SIZE);
+
+        if let Err(stop) = cpu.step_instruction() {
+            return vec![Mismatch::Stop(stop)]
+        }
+
+        let mut mismatches = vec![];
+        for (index, name) in VECTOR_REGISTERS.iter().enumerate() {
+            let Some(expected) = self.expected.registers[index].or(self.initial.registers[index]) else {
+                continue
+            };
+            let actual = cpu.vector_register(index);
+            let mask = if index == PSW { flags_mask } else { 0xFFFF };
+            if (actual ^ expected) & mask != 0 {
+                mismatches.push(Mismatch::Register { name, expected, actual });
+            }
+        }
+        for (addr, expected) in self.expected.memory.iter() {
+            let actual = cpu.bus().peek(*addr);
+            if actual != *expected {
+                mismatches.push(Mismatch::Memory { addr: *addr, expected: *expected, actual });
+            }
+        }
+        if let Some(expected) = self.cycles {
+            if cpu.clock != expected {
+                mismatches.push(Mismatch::Cycles { expected, actual: cpu.clock });
+            }
+        }
+        mismatches
+    }
+
+}
+
+/// Difference between the expected and actual outcome of a test.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Register { name: &'static str, expected: u16, actual: u16 },
+    Memory { addr: u32, expected: u8, actual: u8 },
+    /// The program address differs
+    Address { expected: u32, actual: u32 },
+    /// A memory write differs from the expected one, or only one was made
+    Write { expected: Option<(u32, u8)>, actual: Option<(u32, u8)> },
+    /// Only the cycle count differs from the expected one
+    Cycles { expected: u64, actual: u64 },
+    /// The instruction couldn't be executed
+    Stop(Stop),
+}
+
+impl Display for Mismatch {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        match self {
+            Self::Register { name, expected, actual } =>
+                write!(f, "{name} is {actual:04X}, expected {expected:04X}"),
+            Self::Memory { addr, expected, actual } =>
+                write!(f, "[{addr:05X}] is {actual:02X}, expected {expected:02X}"),
+            Self::Address { expected, actual } =>
+                write!(f, "address is {actual:05X}, expected {expected:05X}"),
+            Self::Write { expected, actual } => {
+                let write = |w: &Option<(u32, u8)>| w.map_or("none".into(), |(addr, value)|
+                    format!("[{addr:05X}]={value:02X}"));
+                write!(f, "wrote {}, expected {}", write(actual), write(expected))
+            },
+            Self::Cycles { expected, actual } =>
+                write!(f, "took {actual} cycles, expected {expected}"),
+            Self::Stop(stop) =>
+                write!(f, "stopped: {stop}"),
+        }
+    }
+}
+
+/// Results of a group of tests.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Tally {
+    /// Tests with the expected state and cycle count
+    pub passed: usize,
+    /// Tests with the expected state, but a different cycle count
+    pub cycles: usize,
+    /// Tests with unexpected registers or memory, or that stopped
+    pub failed: usize,
+}
+
+impl Tally {
+    fn add (&mut self, other: &Tally) {
+        self.passed += other.passed;
+        self.cycles += other.cycles;
+        self.failed += other.failed;
+    }
+}
+
+/// Pass/fail table of test results, by opcode.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub groups: BTreeMap<String, Tally>,
+}
+
+impl Report {
+
+    pub fn new () -> Self {
+        Self::default()
+    }
+
+    /// Count the result of a test of an opcode, as returned by [Vector::run].
+    pub fn record (&mut self, opcode: &str, mismatches: &[Mismatch]) {
+        let tally = self.groups.entry(opcode.into()).or_default();
+        if mismatches.is_empty() {
+            tally.passed += 1
+        } else if mismatches.iter().all(|m| matches!(m, Mismatch::Cycles { .. })) {
+            tally.cycles += 1
+        } else {
+            tally.failed += 1
+        }
+    }
+
+    /// Results of all tests.
+    pub fn total (&self) -> Tally {
+        let mut total = Tally::default();
+        for tally in self.groups.values() {
+            total.add(tally);
+        }
+        total
+    }
+
+}
+
+impl Display for Report {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        writeln!(f, "{:8} {:>7} {:>7} {:>7}", "opcode", "pass", "cycles", "fail")?;
+        let row = |f: &mut Formatter, opcode: &str, tally: &Tally| writeln!(
+            f, "{opcode:8} {:>7} {:>7} {:>7}", tally.passed, tally.cycles, tally.failed
+        );
+        for (opcode, tally) in self.groups.iter() {
+            row(f, opcode, tally)?;
+        }
+        row(f, "total", &self.total())
+    }
+}
@@ -0,0 +1,62 @@
+use crate::*;
+
+/// Internal I/O offset of the first of the 64 page registers (FF00H-FF7FH).
+pub const PAGE_REGISTERS: u16 = 0xFF00;
+
+/// Internal I/O offset of the XA mode register (FF80H), which is read-only.
+pub const XAM: u16 = 0xFF80;
+
+/// Number of page registers, each mapping a 16 KB page of the 1 MB logical
+/// address space.
+pub const PAGES: usize = 64;
+
+impl CPU {
+
+    /// Whether address extension (XA) mode is enabled,
+    /// i.e. bit 0 of XAM is set.
+    pub fn xa (&self) -> bool {
+        self.internal[(XAM - 0xFF00) as usize] & 1 > 0
+    }
+
+    /// Enter or leave XA mode, as BRKXA and RETXA do.
+    pub fn set_xa (&mut self, value: bool) {
+        self.internal[(XAM - 0xFF00) as usize] = value as u8;
+    }
+
+    /// Upper 10 bits of the physical address of a logical page.
+    pub fn page_register (&self, page: usize) -> u16 {
+        let offset = (PAGE_REGISTERS - 0xFF00) as usize + page * 2;
+        u16::from_le_bytes([self.internal[offset], self.internal[offset + 1]]) & 0x3FF
+    }
+
+    pub fn set_page_register (&mut self, page: usize, value: u16) {
+        let offset = (PAGE_REGISTERS - 0xFF00) as usize + page * 2;
+        let [lo, hi] = (value & 0x3FF).to_le_bytes();
+        self.internal[offset] = lo;
+        self.internal[offset + 1] = hi;
+    }
+
+    /// BRKXA (`xa`) or RETXA (`!xa`): load PS:PC from the interrupt vector
+    /// table entry, then enter or leave XA mode. Unlike an interrupt, nothing
+    /// is saved on the stack, and IE and BRK are unaffected. The vector table
+    /// is read in the mode in effect before the instruction.
+    pub fn branch_xa (&mut self, vector: u8, xa: bool) {
+        let (ps, pc) = self.read_vector(vector);
+        self.set_pc(pc);
+        self.set_ps(ps);
+        self.set_xa(xa);
+    }
+
+    /// Address on the bus of a 20-bit logical address. In XA mode, the page
+    /// register selected by bits 19..14 replaces them with the upper 10 bits
+    /// of a 24-bit physical address.
+    pub fn physical_address (&self, addr: u32) -> u32 {
+        if self.xa() {
+            let page = (addr >> 14) as usize & (PAGES - 1);
+            (self.page_register(page) as u32) << 14 | (addr & 0x3FFF)
+        } else {
+            addr
+        }
+    }
+
+}
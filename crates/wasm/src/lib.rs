@@ -19,12 +19,17 @@ impl V53 {
         console_error_panic_hook::set_once();
         let mut mem: Vec<u8> = vec![0u8; rom.length() as usize];
         rom.copy_to(&mut mem);
-        Ok(Self(mpcemu_v53::CPU::new(mem)))
+        mpcemu_v53::CPU::new(mem)
+            .map(Self)
+            .map_err(|e| Error::new(&e.to_string()))
     }
 
     #[wasm_bindgen]
-    pub fn step () {
-        unimplemented!()
+    pub fn step (&mut self) -> Result<(), Error> {
+        match self.0.step(false) {
+            Ok(()) | Err(mpcemu_v53::Stop::Halted) => Ok(()),
+            Err(stop) => Err(Error::new(&stop.to_string()))
+        }
     }
 
     #[wasm_bindgen]